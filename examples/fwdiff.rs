@@ -0,0 +1,34 @@
+// compare two raw program memory images function by function:
+//
+//   fwdiff old.bin new.bin
+//
+// functions are matched by structure and by where they are called from, so code that only
+// moved shows up as unchanged.
+
+use std::env;
+use std::fs;
+use std::process;
+
+use yaxpeax_pic18::analysis::cfg::Cfg;
+use yaxpeax_pic18::analysis::diff::Diff;
+use yaxpeax_pic18::analysis::disasm::Disassembler;
+use yaxpeax_pic18::analysis::explore;
+
+fn load(path: &str) -> Cfg {
+    let image = fs::read(path).unwrap_or_else(|e| {
+        eprintln!("can't read {}: {}", path, e);
+        process::exit(1);
+    });
+    Cfg::build(&explore(&mut Disassembler::new(&image)))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        eprintln!("usage: {} old.bin new.bin", args[0]);
+        process::exit(1);
+    }
+    let old = load(&args[1]);
+    let new = load(&args[2]);
+    print!("{}", Diff::compute(&old, &new));
+}
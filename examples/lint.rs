@@ -0,0 +1,41 @@
+// lint a raw program memory image loaded at address zero:
+//
+//   lint image.bin [device]
+//
+// the device defaults to a PIC18F4550.
+
+use std::env;
+use std::fs;
+use std::process;
+
+use yaxpeax_pic18::analysis::cfg::Cfg;
+use yaxpeax_pic18::analysis::disasm::Disassembler;
+use yaxpeax_pic18::analysis::explore;
+use yaxpeax_pic18::device::{Device, DEVICES, PIC18F4550};
+use yaxpeax_pic18::lint::Linter;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("usage: {} image.bin [device]", args[0]);
+        process::exit(1);
+    }
+    let image = fs::read(&args[1]).unwrap_or_else(|e| {
+        eprintln!("can't read {}: {}", args[1], e);
+        process::exit(1);
+    });
+    let device = match args.get(2) {
+        Some(name) => Device::named(name).unwrap_or_else(|| {
+            let known: Vec<&str> = DEVICES.iter().map(|device| device.name).collect();
+            eprintln!("unknown device {}, try one of {}", name, known.join(", "));
+            process::exit(1);
+        }),
+        None => &PIC18F4550
+    };
+
+    let disassembly = explore(&mut Disassembler::new(&image));
+    let cfg = Cfg::build(&disassembly);
+    for diagnostic in Linter::with_default_rules().run(&disassembly, &cfg, device) {
+        println!("{}", diagnostic);
+    }
+}
@@ -0,0 +1,38 @@
+// print signatures for the routines of a raw program memory image, given its symbols:
+//
+//   sigmake image.bin image.sym [base]
+//
+// symbols are `name address` lines, as `analysis::signature::parse_symbols` reads them.
+
+use std::env;
+use std::fs;
+use std::process;
+
+use yaxpeax_pic18::analysis::disasm::Disassembler;
+use yaxpeax_pic18::analysis::signature::{generate, parse_symbols};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        eprintln!("usage: {} image.bin symbols [base]", args[0]);
+        process::exit(1);
+    }
+    let image = fs::read(&args[1]).unwrap_or_else(|e| {
+        eprintln!("can't read {}: {}", args[1], e);
+        process::exit(1);
+    });
+    let symbols = fs::read_to_string(&args[2]).unwrap_or_else(|e| {
+        eprintln!("can't read {}: {}", args[2], e);
+        process::exit(1);
+    });
+    let base = match args.get(3) {
+        Some(base) => u32::from_str_radix(base.trim_start_matches("0x"), 16).unwrap_or_else(|_| {
+            eprintln!("bad base address {}", base);
+            process::exit(1);
+        }),
+        None => 0
+    };
+
+    let disassembly = Disassembler::at(base, &image).disassemble();
+    print!("{}", generate(&disassembly, &parse_symbols(&symbols)));
+}
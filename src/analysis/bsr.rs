@@ -0,0 +1,220 @@
+use std::collections::BTreeSet;
+
+use crate::{consts, Instruction, Opcode, Operand};
+use crate::analysis::{file_written, writes_w, FileRef};
+use crate::analysis::cfg::{BasicBlock, Cfg, Edge, EdgeKind};
+use crate::analysis::dataflow::{self, Analysis, Solution};
+use crate::analysis::disasm::RESET_VECTOR;
+
+// past this many candidate banks, a BSR value is as good as unknown.
+const MAX_BANKS: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Bank {
+    Known(BTreeSet<u8>),
+    Unknown,
+}
+
+impl Bank {
+    pub fn single(bank: u8) -> Bank {
+        let mut banks = BTreeSet::new();
+        banks.insert(bank & 0x0f);
+        Bank::Known(banks)
+    }
+
+    pub fn banks(&self) -> Option<&BTreeSet<u8>> {
+        match self {
+            Bank::Known(banks) => Some(banks),
+            Bank::Unknown => None
+        }
+    }
+
+    // the bank selected, if there is exactly one candidate.
+    pub fn exact(&self) -> Option<u8> {
+        match self {
+            Bank::Known(banks) if banks.len() == 1 => banks.iter().next().cloned(),
+            _ => None
+        }
+    }
+
+    pub fn join(&mut self, other: &Bank) {
+        let merged = match (&*self, other) {
+            (Bank::Known(ours), Bank::Known(theirs)) => {
+                let banks: BTreeSet<u8> = ours.union(theirs).cloned().collect();
+                if banks.len() > MAX_BANKS {
+                    Bank::Unknown
+                } else {
+                    Bank::Known(banks)
+                }
+            },
+            _ => Bank::Unknown
+        };
+        *self = merged;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BsrState {
+    pub bsr: Bank,
+    // just enough of W to follow `movlw k; movwf BSR`
+    w: Option<u8>,
+}
+
+const BSR: u16 = consts::SFR_BASE + consts::SFRS::BSR;
+
+fn writes_bsr(inst: &Instruction) -> bool {
+    if let Opcode::MOVLB = inst.opcode {
+        return true;
+    }
+    match file_written(inst) {
+        Some(FileRef::Absolute(BSR)) => true,
+        // conservatively: only a write through bank 15 can reach BSR
+        Some(FileRef::Banked(file)) => file as u16 == BSR & 0xff,
+        _ => false
+    }
+}
+
+struct BsrAnalysis {
+    // functions that may leave BSR different from how they found it
+    clobbers: BTreeSet<u32>,
+}
+
+impl BsrAnalysis {
+    fn new(cfg: &Cfg) -> BsrAnalysis {
+        BsrAnalysis {
+            clobbers: dataflow::functions_executing(cfg, writes_bsr),
+        }
+    }
+}
+
+impl Analysis for BsrAnalysis {
+    type State = BsrState;
+
+    fn entry_state(&self, entry: u32) -> BsrState {
+        BsrState {
+            // BSR is cleared on power-on reset. nothing is known about what an interrupt lands on.
+            bsr: if entry == RESET_VECTOR { Bank::single(0) } else { Bank::Unknown },
+            w: None,
+        }
+    }
+
+    fn transfer(&self, _addr: u32, inst: &Instruction, state: &mut BsrState) {
+        let definite = match file_written(inst) {
+            Some(FileRef::Banked(_)) if writes_bsr(inst) => {
+                // a banked write only reaches BSR if BSR selects bank 15 already
+                match state.bsr.banks() {
+                    Some(banks) if !banks.contains(&0x0f) => None,
+                    Some(banks) if banks.len() == 1 => Some(true),
+                    _ => Some(false)
+                }
+            },
+            _ => if writes_bsr(inst) { Some(true) } else { None }
+        };
+        match definite {
+            Some(true) => {
+                state.bsr = match (inst.opcode, inst.operands[0]) {
+                    (Opcode::MOVLB, Operand::ImmediateU8(bank)) => Bank::single(bank),
+                    (Opcode::CLRF, _) => Bank::single(0),
+                    (Opcode::SETF, _) => Bank::single(0x0f),
+                    (Opcode::MOVWF, _) => {
+                        match state.w {
+                            Some(w) => Bank::single(w),
+                            None => Bank::Unknown
+                        }
+                    },
+                    _ => Bank::Unknown
+                };
+            },
+            Some(false) => {
+                state.bsr = Bank::Unknown;
+            },
+            None => {}
+        }
+        if writes_w(inst) {
+            state.w = match (inst.opcode, inst.operands[0]) {
+                (Opcode::MOVLW, Operand::ImmediateU8(k)) => Some(k),
+                _ => None
+            };
+        }
+    }
+
+    fn join(&self, into: &mut BsrState, other: &BsrState) {
+        into.bsr.join(&other.bsr);
+        if into.w != other.w {
+            into.w = None;
+        }
+    }
+
+    fn edge(&self, block: &BasicBlock, edge: &Edge, state: &BsrState) -> BsrState {
+        if edge.kind != EdgeKind::CallReturn {
+            return state.clone();
+        }
+        let preserved = match block.call {
+            Some(target) => !self.clobbers.contains(&target),
+            None => false
+        };
+        BsrState {
+            bsr: if preserved { state.bsr.clone() } else { Bank::Unknown },
+            w: None,
+        }
+    }
+
+    fn call(&self, _target: u32, state: &BsrState) -> Option<BsrState> {
+        Some(BsrState {
+            bsr: state.bsr.clone(),
+            w: None,
+        })
+    }
+}
+
+pub struct BsrTracking {
+    solution: Solution<BsrState>,
+}
+
+impl BsrTracking {
+    pub fn analyze(cfg: &Cfg) -> BsrTracking {
+        BsrTracking {
+            solution: dataflow::solve(cfg, &BsrAnalysis::new(cfg)),
+        }
+    }
+
+    // the BSR value reaching the instruction at `addr`.
+    pub fn bsr_before(&self, addr: u32) -> Option<&Bank> {
+        self.solution.before(addr).map(|state| &state.bsr)
+    }
+
+    // every data address `file` may refer to at `addr`. empty if a banked file can't be
+    // resolved.
+    pub fn resolve(&self, addr: u32, file: FileRef) -> Vec<u16> {
+        match file {
+            FileRef::Absolute(address) => vec![address],
+            FileRef::Banked(_) => {
+                match self.bsr_before(addr).and_then(|bank| bank.banks()) {
+                    Some(banks) => banks.iter().map(|bank| file.resolve(*bank)).collect(),
+                    None => Vec::new()
+                }
+            }
+        }
+    }
+
+    // the instruction at `addr` as it would display, but with banked operands named by the
+    // data address(es) they resolve to.
+    pub fn display(&self, addr: u32, inst: &Instruction) -> String {
+        let text = inst.to_string();
+        let file = match inst.operands[0] {
+            Operand::File(file, true) |
+            Operand::RedirectableFile(file, true, _) => file,
+            _ => { return text; }
+        };
+        let resolved = self.resolve(addr, FileRef::Banked(file));
+        let name = match resolved.len() {
+            0 => { return text; },
+            1 => consts::named_file(resolved[0]).to_string(),
+            _ => {
+                let names: Vec<&str> = resolved.iter().map(|addr| consts::named_file(*addr)).collect();
+                format!("{{{}}}", names.join(" | "))
+            }
+        };
+        text.replace(&format!("[banked 0x{:x}]", file), &format!("[{}]", name))
+    }
+}
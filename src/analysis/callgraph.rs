@@ -0,0 +1,217 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::Opcode;
+use crate::analysis::cfg::{Cfg, EdgeKind};
+use crate::analysis::disasm::{HIGH_PRIORITY_VECTOR, LOW_PRIORITY_VECTOR};
+
+// the PIC18 hardware return stack holds 31 return addresses.
+pub const RETURN_STACK_DEPTH: usize = 31;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CallSite {
+    pub at: u32,
+    // `None` for CALLW, whose target isn't known statically
+    pub target: Option<u32>,
+    // a jump or fallthrough into another function's entry, which pushes nothing: the callee
+    // returns straight to our caller
+    pub tail: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Depth {
+    // stack entries used, and the deepest chain of calls reaching that: function entries
+    // starting with the caller
+    Bounded(usize, Vec<u32>),
+    // the function can recurse, or calls something that can't be followed
+    Unbounded(Vec<u32>),
+}
+
+impl Depth {
+    pub fn entries(&self) -> Option<usize> {
+        match self {
+            Depth::Bounded(entries, _) => Some(*entries),
+            Depth::Unbounded(_) => None
+        }
+    }
+
+    pub fn path(&self) -> &[u32] {
+        match self {
+            Depth::Bounded(_, path) |
+            Depth::Unbounded(path) => path,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackReport {
+    pub entry: u32,
+    // stack used by `entry` and everything it calls, not counting whatever interrupted it
+    pub depth: Depth,
+    // stack used in the worst case, counting interrupts that may preempt `entry`
+    pub worst_case: Option<usize>,
+}
+
+impl StackReport {
+    pub fn exceeds(&self, limit: usize) -> bool {
+        self.worst_case.map(|used| used > limit).unwrap_or(true)
+    }
+}
+
+pub struct CallGraph {
+    entries: BTreeSet<u32>,
+    calls: BTreeMap<u32, Vec<CallSite>>,
+}
+
+impl CallGraph {
+    pub fn build(cfg: &Cfg) -> CallGraph {
+        let mut calls = BTreeMap::new();
+        for function in cfg.functions() {
+            let mut sites = Vec::new();
+            for start in function.blocks.iter() {
+                let block = cfg.block(*start).unwrap();
+                let (at, inst) = block.terminator();
+                if let Some(target) = block.call {
+                    sites.push(CallSite { at, target: Some(target), tail: false });
+                } else if inst.opcode == Opcode::CALLW {
+                    sites.push(CallSite { at, target: None, tail: false });
+                }
+                // `Cfg` stops a function at other functions' entries, so what they call is only
+                // reached through here
+                for edge in block.successors.iter() {
+                    let other = edge.target != function.entry && cfg.function(edge.target).is_some();
+                    if other && edge.kind != EdgeKind::CallReturn {
+                        sites.push(CallSite { at, target: Some(edge.target), tail: true });
+                    }
+                }
+            }
+            calls.insert(function.entry, sites);
+        }
+        CallGraph {
+            entries: cfg.entries().clone(),
+            calls,
+        }
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item=u32> + '_ {
+        self.calls.keys().cloned()
+    }
+
+    pub fn calls(&self, function: u32) -> &[CallSite] {
+        self.calls.get(&function).map(|sites| sites.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn callees(&self, function: u32) -> BTreeSet<u32> {
+        self.calls(function).iter().filter_map(|site| site.target).collect()
+    }
+
+    pub fn callers(&self, function: u32) -> Vec<(u32, CallSite)> {
+        let mut callers = Vec::new();
+        for (caller, sites) in self.calls.iter() {
+            for site in sites.iter() {
+                if site.target == Some(function) {
+                    callers.push((*caller, *site));
+                }
+            }
+        }
+        callers
+    }
+
+    // return stack entries used by calls made from `function`, transitively. the return address
+    // pushed to get into `function` is the caller's to count, and tail calls push nothing.
+    pub fn depth(&self, function: u32) -> Depth {
+        let mut memo = BTreeMap::new();
+        self.depth_from(function, 0, &mut Vec::new(), &mut memo).0
+    }
+
+    // `depth`, `pushed` entries down a chain of calls whose functions are in `active` with the
+    // entries pushed when each was reached. also whether the result went back to one of them,
+    // in which case it only holds for this chain.
+    fn depth_from(&self, function: u32, pushed: usize, active: &mut Vec<(u32, usize)>, memo: &mut BTreeMap<u32, Depth>) -> (Depth, bool) {
+        if let Some(depth) = memo.get(&function) {
+            return (depth.clone(), false);
+        }
+        if let Some((_, then)) = active.iter().find(|(active, _)| *active == function) {
+            // going round a loop of tail calls uses no more stack; any other cycle is recursion
+            if *then == pushed {
+                return (Depth::Bounded(0, vec![function]), true);
+            }
+            return (Depth::Unbounded(vec![function]), true);
+        }
+
+        active.push((function, pushed));
+        let mut deepest = Depth::Bounded(0, vec![function]);
+        let mut cyclic = false;
+        for site in self.calls(function).iter() {
+            let entries = if site.tail { 0 } else { 1 };
+            let callee = match site.target {
+                Some(target) => {
+                    let (depth, back) = self.depth_from(target, pushed + entries, active, memo);
+                    cyclic |= back;
+                    depth
+                },
+                None => Depth::Unbounded(Vec::new()),
+            };
+            let mut path = vec![function];
+            path.extend_from_slice(callee.path());
+            let candidate = match callee {
+                Depth::Bounded(deeper, _) => Depth::Bounded(deeper + entries, path),
+                Depth::Unbounded(_) => Depth::Unbounded(path),
+            };
+            deepest = match (&deepest, &candidate) {
+                (Depth::Unbounded(_), _) => deepest,
+                (_, Depth::Unbounded(_)) => candidate,
+                (Depth::Bounded(ours, _), Depth::Bounded(theirs, _)) => {
+                    if theirs > ours { candidate } else { deepest }
+                }
+            };
+        }
+        active.pop();
+
+        // recursion is unbounded whichever way it's reached, but a depth that went back up the
+        // chain is only known in full once the chain is done
+        if active.is_empty() || !cyclic || matches!(deepest, Depth::Unbounded(_)) {
+            memo.insert(function, deepest.clone());
+        }
+        (deepest, cyclic)
+    }
+
+    // worst-case stack use from each entry point. main code, and any other entry that isn't an
+    // interrupt vector, can be interrupted by the low-priority vector, and either can be
+    // interrupted by the high-priority vector; each interrupt pushes a return address of its own.
+    pub fn stack_reports(&self) -> Vec<StackReport> {
+        let mut depths = BTreeMap::new();
+        for entry in self.entries.iter() {
+            if self.calls.contains_key(entry) {
+                depths.insert(*entry, self.depth(*entry));
+            }
+        }
+
+        let preemption = |vector: u32| -> Option<usize> {
+            match depths.get(&vector) {
+                Some(depth) => depth.entries().map(|entries| entries + 1),
+                None => Some(0)
+            }
+        };
+        let high = preemption(HIGH_PRIORITY_VECTOR);
+        let low = preemption(LOW_PRIORITY_VECTOR);
+
+        let mut reports = Vec::new();
+        for (entry, depth) in depths.iter() {
+            let preempted_by = match *entry {
+                HIGH_PRIORITY_VECTOR => Some(0),
+                LOW_PRIORITY_VECTOR => high,
+                _ => low.and_then(|low| high.map(|high| low + high)),
+            };
+            reports.push(StackReport {
+                entry: *entry,
+                depth: depth.clone(),
+                worst_case: depth.entries().and_then(|entries| preempted_by.map(|extra| entries + extra)),
+            });
+        }
+        reports
+    }
+
+    pub fn overflows(&self) -> Vec<StackReport> {
+        self.stack_reports().into_iter().filter(|report| report.exceeds(RETURN_STACK_DEPTH)).collect()
+    }
+}
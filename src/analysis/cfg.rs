@@ -0,0 +1,306 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::Instruction;
+use crate::analysis::disasm::{flow, instruction_len, Disassembly, Flow};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EdgeKind {
+    Fallthrough,
+    Taken,
+    Skip,
+    // from a block ending in a call to the block the call returns to
+    CallReturn,
+    // from a write to PCL to one of the places it is known to go
+    Computed,
+}
+
+impl EdgeKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EdgeKind::Fallthrough => "fallthrough",
+            EdgeKind::Taken => "taken",
+            EdgeKind::Skip => "skip",
+            EdgeKind::CallReturn => "call-return",
+            EdgeKind::Computed => "computed",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Edge {
+    pub target: u32,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    pub start: u32,
+    // exclusive
+    pub end: u32,
+    pub instructions: Vec<(u32, Instruction)>,
+    pub successors: Vec<Edge>,
+    // the target of a call ending this block, when it is known
+    pub call: Option<u32>,
+}
+
+impl BasicBlock {
+    pub fn terminator(&self) -> (u32, Instruction) {
+        // blocks are never empty
+        self.instructions[self.instructions.len() - 1]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub entry: u32,
+    pub blocks: BTreeSet<u32>,
+}
+
+pub struct Cfg {
+    // where execution starts: the vectors and any user-supplied entry points
+    entries: BTreeSet<u32>,
+    blocks: BTreeMap<u32, BasicBlock>,
+    predecessors: BTreeMap<u32, Vec<(u32, EdgeKind)>>,
+    functions: BTreeMap<u32, Function>,
+}
+
+impl Cfg {
+    pub fn build(disassembly: &Disassembly) -> Cfg {
+        let mut leaders: BTreeSet<u32> = disassembly.entries().clone();
+        let mut function_entries: BTreeSet<u32> = disassembly.entries().clone();
+
+        for (addr, inst) in disassembly.instructions() {
+            let next = addr + instruction_len(inst);
+            match flow(addr, inst) {
+                Flow::Next => {},
+                Flow::Skip => {
+                    leaders.insert(next);
+                    leaders.insert(disassembly.skip_target(next));
+                },
+                Flow::Branch(target) => {
+                    leaders.insert(target);
+                    leaders.insert(next);
+                },
+                Flow::Call(target) => {
+                    leaders.insert(target);
+                    leaders.insert(next);
+                    function_entries.insert(target);
+                },
+                Flow::Jump(target) => {
+                    leaders.insert(target);
+                    leaders.insert(next);
+                },
+                Flow::IndirectJump => {
+                    leaders.extend(disassembly.indirect_targets(addr));
+                    leaders.insert(next);
+                },
+                Flow::IndirectCall |
+                Flow::Return |
+                Flow::Halt => {
+                    leaders.insert(next);
+                }
+            }
+        }
+
+        let mut blocks = BTreeMap::new();
+        for leader in leaders.iter() {
+            let mut addr = *leader;
+            let mut instructions = Vec::new();
+            let mut successors = Vec::new();
+            let mut call = None;
+            while let Some(inst) = disassembly.instruction(addr).cloned() {
+                instructions.push((addr, inst));
+                let next = addr + instruction_len(&inst);
+                let edge = |target, kind| Edge { target, kind };
+                match flow(addr, &inst) {
+                    Flow::Next => {
+                        if leaders.contains(&next) {
+                            successors.push(edge(next, EdgeKind::Fallthrough));
+                            break;
+                        }
+                        addr = next;
+                        continue;
+                    },
+                    Flow::Skip => {
+                        successors.push(edge(next, EdgeKind::Fallthrough));
+                        successors.push(edge(disassembly.skip_target(next), EdgeKind::Skip));
+                    },
+                    Flow::Branch(target) => {
+                        successors.push(edge(next, EdgeKind::Fallthrough));
+                        successors.push(edge(target, EdgeKind::Taken));
+                    },
+                    Flow::Jump(target) => {
+                        successors.push(edge(target, EdgeKind::Taken));
+                    },
+                    Flow::Call(target) => {
+                        call = Some(target);
+                        successors.push(edge(next, EdgeKind::CallReturn));
+                    },
+                    Flow::IndirectCall => {
+                        successors.push(edge(next, EdgeKind::CallReturn));
+                    },
+                    Flow::IndirectJump => {
+                        for target in disassembly.indirect_targets(addr) {
+                            successors.push(edge(target, EdgeKind::Computed));
+                        }
+                    },
+                    Flow::Return |
+                    Flow::Halt => {}
+                }
+                break;
+            }
+            if instructions.is_empty() {
+                continue;
+            }
+            // edges to addresses that never decoded lead nowhere
+            successors.retain(|edge| disassembly.instruction(edge.target).is_some());
+            let (last, last_inst) = instructions[instructions.len() - 1];
+            blocks.insert(*leader, BasicBlock {
+                start: *leader,
+                end: last + instruction_len(&last_inst),
+                instructions,
+                successors,
+                call,
+            });
+        }
+
+        let mut predecessors: BTreeMap<u32, Vec<(u32, EdgeKind)>> = BTreeMap::new();
+        for block in blocks.values() {
+            for edge in block.successors.iter() {
+                predecessors.entry(edge.target).or_default().push((block.start, edge.kind));
+            }
+        }
+
+        let mut cfg = Cfg {
+            entries: disassembly.entries().clone(),
+            blocks,
+            predecessors,
+            functions: BTreeMap::new(),
+        };
+        cfg.group_functions(function_entries);
+        cfg
+    }
+
+    // treat `entry` as the start of a function, even if nothing calls it directly.
+    pub fn add_function(&mut self, entry: u32) {
+        let mut entries: BTreeSet<u32> = self.functions.keys().cloned().collect();
+        entries.insert(entry);
+        self.group_functions(entries);
+    }
+
+    // each function is the set of blocks reachable from its entry without passing through
+    // another function's entry. a jump to another entry is a tail call, not part of the caller.
+    fn group_functions(&mut self, entries: BTreeSet<u32>) {
+        self.functions.clear();
+        for entry in entries.iter() {
+            if !self.blocks.contains_key(entry) {
+                continue;
+            }
+            let mut blocks = BTreeSet::new();
+            let mut worklist = vec![*entry];
+            while let Some(start) = worklist.pop() {
+                if !blocks.insert(start) {
+                    continue;
+                }
+                for edge in self.blocks[&start].successors.iter() {
+                    if edge.target != *entry && entries.contains(&edge.target) {
+                        continue;
+                    }
+                    worklist.push(edge.target);
+                }
+            }
+            self.functions.insert(*entry, Function { entry: *entry, blocks });
+        }
+    }
+
+    pub fn entries(&self) -> &BTreeSet<u32> {
+        &self.entries
+    }
+
+    pub fn block(&self, start: u32) -> Option<&BasicBlock> {
+        self.blocks.get(&start)
+    }
+
+    pub fn block_containing(&self, addr: u32) -> Option<&BasicBlock> {
+        match self.blocks.range(..=addr).next_back() {
+            Some((_, block)) if addr < block.end => Some(block),
+            _ => None
+        }
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item=&BasicBlock> {
+        self.blocks.values()
+    }
+
+    pub fn predecessors(&self, start: u32) -> &[(u32, EdgeKind)] {
+        self.predecessors.get(&start).map(|preds| preds.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn function(&self, entry: u32) -> Option<&Function> {
+        self.functions.get(&entry)
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item=&Function> {
+        self.functions.values()
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        // writing to a String can't fail
+        self.write_dot(&mut out).unwrap();
+        out
+    }
+
+    pub fn write_dot<W: Write>(&self, out: &mut W) -> std::fmt::Result {
+        writeln!(out, "digraph cfg {{")?;
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];")?;
+
+        let mut placed: BTreeSet<u32> = BTreeSet::new();
+        for function in self.functions.values() {
+            writeln!(out, "    subgraph \"cluster_{:04x}\" {{", function.entry)?;
+            writeln!(out, "        label=\"fn_{:04x}\";", function.entry)?;
+            for start in function.blocks.iter() {
+                if placed.insert(*start) {
+                    self.write_dot_block(out, *start)?;
+                }
+            }
+            writeln!(out, "    }}")?;
+        }
+        for start in self.blocks.keys() {
+            if placed.insert(*start) {
+                self.write_dot_block(out, *start)?;
+            }
+        }
+
+        for block in self.blocks.values() {
+            for edge in block.successors.iter() {
+                let style = match edge.kind {
+                    EdgeKind::Fallthrough => "solid",
+                    EdgeKind::Taken => "bold",
+                    EdgeKind::Skip => "dotted",
+                    EdgeKind::CallReturn => "dashed",
+                    EdgeKind::Computed => "dashed, color=red",
+                };
+                writeln!(out, "    \"{:04x}\" -> \"{:04x}\" [label=\"{}\", style={}];",
+                    block.start, edge.target, edge.kind.name(), style)?;
+            }
+            if let Some(target) = block.call {
+                if self.blocks.contains_key(&target) {
+                    writeln!(out, "    \"{:04x}\" -> \"{:04x}\" [label=\"call\", style=dashed, color=blue];",
+                        block.start, target)?;
+                }
+            }
+        }
+        writeln!(out, "}}")
+    }
+
+    fn write_dot_block<W: Write>(&self, out: &mut W, start: u32) -> std::fmt::Result {
+        let block = &self.blocks[&start];
+        let mut label = String::new();
+        for (addr, inst) in block.instructions.iter() {
+            write!(label, "{:04x}: {}\\l", addr, inst)?;
+        }
+        writeln!(out, "        \"{:04x}\" [label=\"{}\"];", start, label.replace('"', "\\\""))
+    }
+}
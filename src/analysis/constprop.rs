@@ -0,0 +1,496 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{consts, Instruction, Opcode};
+use crate::analysis::{file_read, file_written, FileRef};
+use crate::analysis::bsr::BsrTracking;
+use crate::analysis::cfg::{BasicBlock, Cfg, Edge, EdgeKind};
+use crate::analysis::dataflow::{self, Analysis, Solution};
+use crate::analysis::disasm::{Disassembly, RESET_VECTOR};
+use crate::analysis::fsr::{fsr_register, FsrRegister};
+use crate::ir::{self, BinOp, Expr, Flag, Reg, Stmt};
+
+const WREG: u16 = consts::SFR_BASE + consts::SFRS::WREG;
+const BSR: u16 = consts::SFR_BASE + consts::SFRS::BSR;
+const STATUS: u16 = consts::SFR_BASE + consts::SFRS::STATUS;
+const PRODL: u16 = consts::SFR_BASE + consts::SFRS::PRODL;
+const PRODH: u16 = consts::SFR_BASE + consts::SFRS::PRODH;
+const TABLAT: u16 = consts::SFR_BASE + consts::SFRS::TABLAT;
+const TBLPTRL: u16 = consts::SFR_BASE + consts::SFRS::TBLPTRL;
+const TBLPTRH: u16 = consts::SFR_BASE + consts::SFRS::TBLPTRH;
+const TBLPTRU: u16 = consts::SFR_BASE + consts::SFRS::TBLPTRU;
+const PCLATH: u16 = consts::SFR_BASE + consts::SFRS::PCLATH;
+const PCLATU: u16 = consts::SFR_BASE + consts::SFRS::PCLATU;
+
+const FLAGS: [Flag; 5] = [Flag::C, Flag::DC, Flag::Z, Flag::OV, Flag::N];
+
+// cells whose value reads back as it was written. that's general purpose RAM and the core
+// registers; peripheral SFRs read back pins, counters and status, so writes to them are seen but
+// not remembered.
+fn tracked(addr: u16) -> bool {
+    if addr < consts::SFR_BASE {
+        return true;
+    }
+    match addr {
+        WREG | BSR | PRODL | PRODH | TABLAT | TBLPTRL | TBLPTRH | TBLPTRU | PCLATH | PCLATU => true,
+        _ => matches!(fsr_register(addr), Some((_, FsrRegister::Low)) | Some((_, FsrRegister::High))),
+    }
+}
+
+pub(crate) fn reg_cell(reg: Reg) -> u16 {
+    match reg {
+        Reg::W => WREG,
+        Reg::Bsr => BSR,
+        Reg::ProdL => PRODL,
+        Reg::ProdH => PRODH,
+        Reg::Tablat => TABLAT,
+        Reg::Tblptr => TBLPTRL,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConstState {
+    // tracked cells with a known value; anything missing is unknown
+    cells: BTreeMap<u16, u8>,
+    flags: [Option<bool>; 5],
+}
+
+impl ConstState {
+    fn unknown() -> ConstState {
+        ConstState { cells: BTreeMap::new(), flags: [None; 5] }
+    }
+
+    pub fn value(&self, addr: u16) -> Option<u8> {
+        if addr == STATUS {
+            let mut status = 0;
+            for flag in FLAGS.iter() {
+                status |= (self.flag(*flag)? as u8) << flag.bit();
+            }
+            return Some(status);
+        }
+        self.cells.get(&addr).cloned()
+    }
+
+    pub fn w(&self) -> Option<u8> {
+        self.value(WREG)
+    }
+
+    pub fn bsr(&self) -> Option<u8> {
+        self.value(BSR)
+    }
+
+    pub fn flag(&self, flag: Flag) -> Option<bool> {
+        self.flags[flag.bit() as usize]
+    }
+
+    // every tracked cell with a known value.
+    pub fn known(&self) -> impl Iterator<Item=(u16, u8)> + '_ {
+        self.cells.iter().map(|(addr, value)| (*addr, *value))
+    }
+
+    fn set(&mut self, addr: u16, value: Option<u8>) {
+        if addr == STATUS {
+            for flag in FLAGS.iter() {
+                self.flags[flag.bit() as usize] = value.map(|value| value & (1 << flag.bit()) != 0);
+            }
+            return;
+        }
+        if !tracked(addr) {
+            return;
+        }
+        match value {
+            Some(value) => { self.cells.insert(addr, value); },
+            None => { self.cells.remove(&addr); }
+        }
+    }
+
+    fn reg(&self, reg: Reg) -> Option<u32> {
+        match reg {
+            Reg::Tblptr => {
+                let l = self.value(TBLPTRL)? as u32;
+                let h = self.value(TBLPTRH)? as u32;
+                let u = self.value(TBLPTRU)? as u32;
+                Some(((u & 0x3f) << 16) | (h << 8) | l)
+            },
+            _ => self.value(reg_cell(reg)).map(|value| value as u32)
+        }
+    }
+
+    fn set_reg(&mut self, reg: Reg, value: Option<u32>) {
+        match reg {
+            Reg::Tblptr => {
+                self.set(TBLPTRL, value.map(|value| value as u8));
+                self.set(TBLPTRH, value.map(|value| (value >> 8) as u8));
+                self.set(TBLPTRU, value.map(|value| ((value >> 16) & 0x3f) as u8));
+            },
+            Reg::Bsr => self.set(BSR, value.map(|value| (value & 0x0f) as u8)),
+            _ => self.set(reg_cell(reg), value.map(|value| value as u8)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Location {
+    W,
+    File(u16),
+}
+
+// a value an instruction is known to read or write.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KnownValue {
+    pub location: Location,
+    pub value: u8,
+    pub write: bool,
+}
+
+// what running an instruction's IR over known values saw, if anyone's asking.
+#[derive(Default)]
+struct Observed {
+    values: Vec<KnownValue>,
+}
+
+impl Observed {
+    fn push(&mut self, location: Location, value: u8, write: bool) {
+        let known = KnownValue { location, value, write };
+        if !self.values.contains(&known) {
+            self.values.push(known);
+        }
+    }
+}
+
+struct Evaluator<'a> {
+    state: &'a ConstState,
+    disassembly: &'a Disassembly,
+    temps: &'a [Option<u32>],
+}
+
+impl<'a> Evaluator<'a> {
+    fn eval(&self, expr: &Expr, observed: &mut Option<&mut Observed>) -> Option<u32> {
+        match expr {
+            Expr::Const(value) => Some(*value),
+            Expr::Reg(reg) => {
+                let value = self.state.reg(*reg);
+                if let (Reg::W, Some(value), Some(observed)) = (reg, value, observed.as_mut()) {
+                    observed.push(Location::W, value as u8, false);
+                }
+                value
+            },
+            Expr::Flag(flag) => self.state.flag(*flag).map(|flag| flag as u32),
+            Expr::Temp(temp) => self.temps.get(*temp as usize).cloned().flatten(),
+            Expr::Load(addr) => {
+                let addr = (self.eval(addr, observed)? & 0xfff) as u16;
+                let value = self.state.value(addr)?;
+                if let Some(observed) = observed.as_mut() {
+                    observed.push(Location::File(addr), value, false);
+                }
+                Some(value as u32)
+            },
+            Expr::ProgramLoad(addr) => {
+                let addr = self.eval(addr, observed)?;
+                self.disassembly.byte(addr).map(|value| value as u32)
+            },
+            Expr::Not(inner) => self.eval(inner, observed).map(|value| (value == 0) as u32),
+            Expr::Binary(op, left, right) => {
+                let left = self.eval(left, observed);
+                let right = self.eval(right, observed);
+                // anything and zero is zero, whatever the anything was
+                if *op == BinOp::And && (left == Some(0) || right == Some(0)) {
+                    return Some(0);
+                }
+                let (left, right) = (left?, right?);
+                Some(match op {
+                    BinOp::Add => left.wrapping_add(right),
+                    BinOp::Sub => left.wrapping_sub(right),
+                    BinOp::Mul => left.wrapping_mul(right),
+                    BinOp::And => left & right,
+                    BinOp::Or => left | right,
+                    BinOp::Xor => left ^ right,
+                    BinOp::Shl => left.checked_shl(right).unwrap_or(0),
+                    BinOp::Shr => left.checked_shr(right).unwrap_or(0),
+                    BinOp::Eq => (left == right) as u32,
+                    BinOp::Ne => (left != right) as u32,
+                    BinOp::Lt => (left < right) as u32,
+                    BinOp::Gt => (left > right) as u32,
+                })
+            },
+            Expr::Select(cond, then, otherwise) => {
+                match self.eval(cond, observed)? {
+                    0 => self.eval(otherwise, observed),
+                    _ => self.eval(then, observed),
+                }
+            }
+        }
+    }
+}
+
+// run `inst` over `state`, noting known values read and written into `observed`.
+fn step(
+    disassembly: &Disassembly,
+    addr: u32,
+    inst: &Instruction,
+    state: &mut ConstState,
+    mut observed: Option<&mut Observed>
+) {
+    // implicit pointer reads and updates for INDFn and friends are noise unless the FSR was the
+    // operand itself
+    let named: Vec<u16> = file_read(inst).into_iter().chain(file_written(inst))
+        .filter_map(|file| file.absolute())
+        .collect();
+    let implicit = |addr: u16| {
+        !named.contains(&addr) &&
+            matches!(fsr_register(addr), Some((_, FsrRegister::Low)) | Some((_, FsrRegister::High)))
+    };
+
+    let mut temps: Vec<Option<u32>> = Vec::new();
+    for stmt in ir::lift(addr, inst).iter() {
+        let mut seen = Observed::default();
+        let evaluator = Evaluator { state, disassembly, temps: &temps };
+        let mut seen_ref = observed.as_ref().map(|_| &mut seen);
+        match stmt {
+            Stmt::Let(temp, expr) => {
+                let value = evaluator.eval(expr, &mut seen_ref);
+                let temp = *temp as usize;
+                if temps.len() <= temp {
+                    temps.resize(temp + 1, None);
+                }
+                temps[temp] = value;
+            },
+            Stmt::SetReg(reg, expr) => {
+                let value = evaluator.eval(expr, &mut seen_ref);
+                if let (Reg::W, Some(value)) = (reg, value) {
+                    seen.push(Location::W, value as u8, true);
+                }
+                state.set_reg(*reg, value);
+            },
+            Stmt::SetFlag(flag, expr) => {
+                let value = evaluator.eval(expr, &mut seen_ref);
+                state.flags[flag.bit() as usize] = value.map(|value| value != 0);
+            },
+            Stmt::Store(dest, value) => {
+                let dest = evaluator.eval(dest, &mut seen_ref).map(|dest| (dest & 0xfff) as u16);
+                let value = evaluator.eval(value, &mut seen_ref).map(|value| value as u8);
+                match dest {
+                    Some(dest) => {
+                        if let Some(value) = value {
+                            seen.push(Location::File(dest), value, true);
+                        }
+                        state.set(dest, value);
+                    },
+                    None => forget_unknown_store(inst, state),
+                }
+            },
+            // control transfers don't change data, and program memory isn't tracked as
+            // anything but the image
+            _ => {
+                if let Stmt::Skip(cond) | Stmt::Branch(cond, _) | Stmt::Jump(cond) | Stmt::Call { target: cond, .. } |
+                        Stmt::ProgramStore(cond, _) = stmt {
+                    evaluator.eval(cond, &mut seen_ref);
+                }
+            }
+        }
+        if let Some(observed) = observed.as_mut() {
+            for known in seen.values.iter() {
+                match known.location {
+                    Location::File(addr) if implicit(addr) => {},
+                    location => observed.push(location, known.value, known.write),
+                }
+            }
+        }
+    }
+}
+
+// a store whose address isn't known could have gone anywhere a banked operand's offset allows,
+// or anywhere at all through an FSR.
+fn forget_unknown_store(inst: &Instruction, state: &mut ConstState) {
+    match file_written(inst) {
+        Some(FileRef::Banked(file)) => {
+            state.cells.retain(|addr, _| *addr as u8 != file);
+            if file == STATUS as u8 {
+                state.flags = [None; 5];
+            }
+        },
+        _ => {
+            state.cells.clear();
+            state.flags = [None; 5];
+        }
+    }
+}
+
+// data addresses a function may write, itself or through anything it calls. `None` if that
+// can't be pinned down.
+pub(crate) fn function_writes(cfg: &Cfg, bsr: &BsrTracking) -> BTreeMap<u32, Option<BTreeSet<u16>>> {
+    let mut writes: BTreeMap<u32, Option<BTreeSet<u16>>> = BTreeMap::new();
+    let mut callees: BTreeMap<u32, Vec<Option<u32>>> = BTreeMap::new();
+    for function in cfg.functions() {
+        let mut written = Some(BTreeSet::new());
+        let mut calls = Vec::new();
+        for start in function.blocks.iter() {
+            let block = cfg.block(*start).unwrap();
+            for (addr, inst) in block.instructions.iter() {
+                for stmt in ir::lift(*addr, inst).iter() {
+                    let dest = match stmt {
+                        Stmt::Store(Expr::Const(dest), _) => vec![*dest as u16],
+                        Stmt::Store(_, _) => {
+                            match file_written(inst) {
+                                Some(file @ FileRef::Banked(_)) => bsr.resolve(*addr, file),
+                                _ => Vec::new()
+                            }
+                        },
+                        Stmt::SetReg(Reg::Tblptr, _) => vec![TBLPTRL, TBLPTRH, TBLPTRU],
+                        Stmt::SetReg(reg, _) => vec![reg_cell(*reg)],
+                        _ => { continue; }
+                    };
+                    match (dest.is_empty(), written.as_mut()) {
+                        (true, _) => { written = None; },
+                        (false, Some(written)) => written.extend(dest),
+                        (false, None) => {}
+                    }
+                }
+            }
+            if block.terminator().1.opcode == Opcode::CALLW {
+                calls.push(None);
+            } else if block.call.is_some() {
+                calls.push(block.call);
+            }
+        }
+        writes.insert(function.entry, written);
+        callees.insert(function.entry, calls);
+    }
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (entry, calls) in callees.iter() {
+            let mut merged = writes[entry].clone();
+            for call in calls.iter() {
+                let theirs = match call {
+                    Some(target) => writes.get(target).cloned().flatten(),
+                    None => None
+                };
+                merged = match (merged, theirs) {
+                    (Some(ours), Some(theirs)) => Some(ours.union(&theirs).cloned().collect()),
+                    _ => None
+                };
+            }
+            if merged != writes[entry] {
+                writes.insert(*entry, merged);
+                changed = true;
+            }
+        }
+    }
+
+    writes
+}
+
+struct ConstAnalysis<'a> {
+    disassembly: &'a Disassembly,
+    writes: BTreeMap<u32, Option<BTreeSet<u16>>>,
+}
+
+impl<'a> Analysis for ConstAnalysis<'a> {
+    type State = ConstState;
+
+    fn entry_state(&self, entry: u32) -> ConstState {
+        let mut state = ConstState::unknown();
+        // of the tracked registers, only BSR has a defined power-on value
+        if entry == RESET_VECTOR {
+            state.set(BSR, Some(0));
+        }
+        state
+    }
+
+    fn transfer(&self, addr: u32, inst: &Instruction, state: &mut ConstState) {
+        step(self.disassembly, addr, inst, state, None);
+    }
+
+    fn join(&self, into: &mut ConstState, other: &ConstState) {
+        into.cells.retain(|addr, value| other.cells.get(addr) == Some(value));
+        for (ours, theirs) in into.flags.iter_mut().zip(other.flags.iter()) {
+            if ours != theirs {
+                *ours = None;
+            }
+        }
+    }
+
+    fn edge(&self, block: &BasicBlock, edge: &Edge, state: &ConstState) -> ConstState {
+        if edge.kind != EdgeKind::CallReturn {
+            return state.clone();
+        }
+        // W and STATUS are a callee's to return things in, whatever else it leaves alone
+        let mut after = ConstState::unknown();
+        let written = block.call.and_then(|target| self.writes.get(&target).cloned().flatten());
+        if let Some(written) = written {
+            after.cells = state.cells.iter()
+                .filter(|(addr, _)| **addr != WREG && !written.contains(addr))
+                .map(|(addr, value)| (*addr, *value))
+                .collect();
+        }
+        after
+    }
+
+    fn call(&self, _target: u32, state: &ConstState) -> Option<ConstState> {
+        Some(state.clone())
+    }
+}
+
+// values known to be in W, BSR, STATUS and data memory at each instruction. interrupts are not
+// accounted for: RAM an ISR writes may not hold what is reported here.
+pub struct Constants<'a> {
+    disassembly: &'a Disassembly,
+    solution: Solution<ConstState>,
+}
+
+impl<'a> Constants<'a> {
+    pub fn analyze(cfg: &Cfg, disassembly: &'a Disassembly) -> Constants<'a> {
+        let analysis = ConstAnalysis {
+            disassembly,
+            writes: function_writes(cfg, &BsrTracking::analyze(cfg)),
+        };
+        Constants {
+            disassembly,
+            solution: dataflow::solve(cfg, &analysis),
+        }
+    }
+
+    // the state reaching the instruction at `addr`.
+    pub fn before(&self, addr: u32) -> Option<&ConstState> {
+        self.solution.before(addr)
+    }
+
+    pub fn w_before(&self, addr: u32) -> Option<u8> {
+        self.before(addr).and_then(|state| state.w())
+    }
+
+    pub fn value_before(&self, addr: u32, file: u16) -> Option<u8> {
+        self.before(addr).and_then(|state| state.value(file))
+    }
+
+    // known values the instruction at `addr` reads and writes, in the order it touches them.
+    pub fn known_values(&self, addr: u32, inst: &Instruction) -> Vec<KnownValue> {
+        let mut state = match self.before(addr) {
+            Some(state) => state.clone(),
+            None => { return Vec::new(); }
+        };
+        let mut observed = Observed::default();
+        step(self.disassembly, addr, inst, &mut state, Some(&mut observed));
+        observed.values
+    }
+
+    // the instruction at `addr` as it would display, followed by the known values it reads
+    // and writes.
+    pub fn display(&self, addr: u32, inst: &Instruction) -> String {
+        let text = inst.to_string();
+        let known = self.known_values(addr, inst);
+        if known.is_empty() {
+            return text;
+        }
+        let annotations: Vec<String> = known.iter().map(|known| {
+            let name = match known.location {
+                Location::W => "W".to_string(),
+                Location::File(addr) => consts::named_file(addr).to_string(),
+            };
+            let op = if known.write { "<-" } else { "=" };
+            format!("{} {} 0x{:02x}", name, op, known.value)
+        }).collect();
+        format!("{}  ; {}", text, annotations.join(", "))
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{Instruction, Opcode};
+use crate::analysis::cfg::{BasicBlock, Cfg, Edge};
+
+// a forward dataflow problem over a `Cfg`. states only ever move up the lattice through `join`,
+// so a lattice of finite height is all that's needed for `solve` to terminate.
+pub trait Analysis {
+    type State: Clone + PartialEq;
+
+    // the state at a vector or user-supplied entry point.
+    fn entry_state(&self, entry: u32) -> Self::State;
+
+    fn transfer(&self, addr: u32, inst: &Instruction, state: &mut Self::State);
+
+    fn join(&self, into: &mut Self::State, other: &Self::State);
+
+    // the state carried along `edge`, given the state at the end of `block`.
+    fn edge(&self, _block: &BasicBlock, _edge: &Edge, state: &Self::State) -> Self::State {
+        state.clone()
+    }
+
+    // the state a callee starts with, given the state at a call to it. `None` starts the callee
+    // at `entry_state` instead.
+    fn call(&self, _target: u32, _state: &Self::State) -> Option<Self::State> {
+        None
+    }
+}
+
+pub struct Solution<S> {
+    block_in: BTreeMap<u32, S>,
+    before: BTreeMap<u32, S>,
+}
+
+impl<S> Solution<S> {
+    pub fn block_in(&self, start: u32) -> Option<&S> {
+        self.block_in.get(&start)
+    }
+
+    // the state immediately before the instruction at `addr` executes.
+    pub fn before(&self, addr: u32) -> Option<&S> {
+        self.before.get(&addr)
+    }
+}
+
+fn merge<A: Analysis>(
+    analysis: &A,
+    block_in: &mut BTreeMap<u32, A::State>,
+    worklist: &mut BTreeSet<u32>,
+    target: u32,
+    state: A::State
+) {
+    match block_in.get_mut(&target) {
+        Some(existing) => {
+            let prev = existing.clone();
+            analysis.join(existing, &state);
+            if *existing != prev {
+                worklist.insert(target);
+            }
+        },
+        None => {
+            block_in.insert(target, state);
+            worklist.insert(target);
+        }
+    }
+}
+
+pub fn solve<A: Analysis>(cfg: &Cfg, analysis: &A) -> Solution<A::State> {
+    let mut block_in: BTreeMap<u32, A::State> = BTreeMap::new();
+    let mut worklist: BTreeSet<u32> = BTreeSet::new();
+
+    for entry in cfg.entries().iter() {
+        if cfg.block(*entry).is_some() {
+            merge(analysis, &mut block_in, &mut worklist, *entry, analysis.entry_state(*entry));
+        }
+    }
+
+    while let Some(start) = worklist.pop_first() {
+        let block = match cfg.block(start) {
+            Some(block) => block,
+            None => { continue; }
+        };
+        let mut state = block_in[&start].clone();
+        for (addr, inst) in block.instructions.iter() {
+            analysis.transfer(*addr, inst, &mut state);
+        }
+        for edge in block.successors.iter() {
+            let carried = analysis.edge(block, edge, &state);
+            merge(analysis, &mut block_in, &mut worklist, edge.target, carried);
+        }
+        if let Some(target) = block.call {
+            if cfg.block(target).is_some() {
+                let callee_state = analysis.call(target, &state)
+                    .unwrap_or_else(|| analysis.entry_state(target));
+                merge(analysis, &mut block_in, &mut worklist, target, callee_state);
+            }
+        }
+    }
+
+    let mut before = BTreeMap::new();
+    for (start, state) in block_in.iter() {
+        let mut state = state.clone();
+        for (addr, inst) in cfg.block(*start).unwrap().instructions.iter() {
+            before.insert(*addr, state.clone());
+            analysis.transfer(*addr, inst, &mut state);
+        }
+    }
+
+    Solution { block_in, before }
+}
+
+// functions that may execute an instruction matching `pred`, either themselves or through
+// something they call. CALLW, and calls to anything that isn't a known function, are assumed to.
+pub fn functions_executing<F: Fn(&Instruction) -> bool>(cfg: &Cfg, pred: F) -> BTreeSet<u32> {
+    let mut matched = BTreeSet::new();
+    let mut callees: BTreeMap<u32, Vec<Option<u32>>> = BTreeMap::new();
+    for function in cfg.functions() {
+        let mut calls = Vec::new();
+        for start in function.blocks.iter() {
+            let block = cfg.block(*start).unwrap();
+            if block.instructions.iter().any(|(_, inst)| pred(inst)) {
+                matched.insert(function.entry);
+            }
+            if let (_, Instruction { opcode: Opcode::CALLW, .. }) = block.terminator() {
+                calls.push(None);
+            } else if block.call.is_some() {
+                calls.push(block.call);
+            }
+        }
+        callees.insert(function.entry, calls);
+    }
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (entry, calls) in callees.iter() {
+            if matched.contains(entry) {
+                continue;
+            }
+            let reaches = calls.iter().any(|call| {
+                match call {
+                    Some(target) => matched.contains(target) || cfg.function(*target).is_none(),
+                    None => true
+                }
+            });
+            if reaches {
+                matched.insert(*entry);
+                changed = true;
+            }
+        }
+    }
+
+    matched
+}
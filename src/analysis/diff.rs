@@ -0,0 +1,389 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display, Formatter};
+
+use crate::{Instruction, Opcode, Operand};
+use crate::analysis::cfg::Cfg;
+use crate::analysis::disasm::{flow, Flow, HIGH_PRIORITY_VECTOR, LOW_PRIORITY_VECTOR, RESET_VECTOR};
+
+// how alike two functions have to be, 0 to 1, to be taken as versions of each other.
+pub const SIMILARITY_THRESHOLD: f32 = 0.5;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MatchKind {
+    // both start at the same reset or interrupt vector
+    Vector,
+    // the same instructions, once branch and call targets are set aside
+    Identical,
+    // called from the same place in functions already matched
+    CallGraph,
+    // the most alike of what was left
+    Similar,
+}
+
+impl MatchKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MatchKind::Vector => "vector",
+            MatchKind::Identical => "identical",
+            MatchKind::CallGraph => "call graph",
+            MatchKind::Similar => "similar",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Line {
+    Same((u32, Instruction), (u32, Instruction)),
+    // the same opcode, with different operands
+    Changed((u32, Instruction), (u32, Instruction)),
+    Removed((u32, Instruction)),
+    Added((u32, Instruction)),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionMatch {
+    pub old: u32,
+    pub new: u32,
+    pub kind: MatchKind,
+    // 0 to 1, how many instructions the two have in common
+    pub similarity: f32,
+    pub lines: Vec<Line>,
+}
+
+impl FunctionMatch {
+    pub fn is_identical(&self) -> bool {
+        self.lines.iter().all(|line| matches!(line, Line::Same(_, _)))
+    }
+}
+
+// where a control transfer goes, without the address: that moves whenever anything before it
+// grows or shrinks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum Target {
+    None,
+    // the nth instruction of the same function
+    Local(usize),
+    // somewhere else, a call or a tail call
+    Outside,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    opcode: Opcode,
+    operands: [Operand; 2],
+    target: Target,
+}
+
+struct Summary {
+    entry: u32,
+    instructions: Vec<(u32, Instruction)>,
+    keys: Vec<Key>,
+    // for each instruction, where it goes outside the function, if it does
+    outside: Vec<Option<u32>>,
+    // functions called, in the order the calls appear
+    calls: Vec<u32>,
+}
+
+impl Summary {
+    fn new(cfg: &Cfg, entry: u32) -> Summary {
+        let mut by_addr: BTreeMap<u32, Instruction> = BTreeMap::new();
+        if let Some(function) = cfg.function(entry) {
+            for start in function.blocks.iter() {
+                by_addr.extend(cfg.block(*start).iter().flat_map(|block| block.instructions.iter().cloned()));
+            }
+        }
+        let index: BTreeMap<u32, usize> = by_addr.keys().enumerate().map(|(i, addr)| (*addr, i)).collect();
+        let mut keys = Vec::new();
+        let mut outside = Vec::new();
+        let mut calls = Vec::new();
+        for (addr, inst) in by_addr.iter() {
+            let (target, away) = match flow(*addr, inst) {
+                Flow::Call(target) => {
+                    calls.push(target);
+                    (Target::Outside, Some(target))
+                },
+                Flow::Branch(target) |
+                Flow::Jump(target) => match index.get(&target) {
+                    Some(i) => (Target::Local(*i), None),
+                    None => (Target::Outside, Some(target)),
+                },
+                _ => (Target::None, None)
+            };
+            let mut operands = inst.operands;
+            if target != Target::None {
+                operands[0] = Operand::Nothing;
+            }
+            keys.push(Key { opcode: inst.opcode, operands, target });
+            outside.push(away);
+        }
+        Summary {
+            entry,
+            instructions: by_addr.into_iter().collect(),
+            keys,
+            outside,
+            calls,
+        }
+    }
+}
+
+// longest common subsequence table: `table[i][j]` is the length for `a[i..]` and `b[j..]`.
+fn lcs_table<F: Fn(usize, usize) -> bool>(a: usize, b: usize, same: F) -> Vec<Vec<u32>> {
+    let mut table = vec![vec![0u32; b + 1]; a + 1];
+    for i in (0..a).rev() {
+        for j in (0..b).rev() {
+            table[i][j] = if same(i, j) {
+                table[i + 1][j + 1] + 1
+            } else {
+                table[i + 1][j].max(table[i][j + 1])
+            };
+        }
+    }
+    table
+}
+
+fn similarity(old: &Summary, new: &Summary) -> f32 {
+    let total = old.keys.len() + new.keys.len();
+    if total == 0 {
+        return 1.0;
+    }
+    let table = lcs_table(old.keys.len(), new.keys.len(), |i, j| old.keys[i] == new.keys[j]);
+    2.0 * table[0][0] as f32 / total as f32
+}
+
+// set removed and added runs against each other, pairing instructions with the same opcode as
+// changes.
+fn pair_runs(removed: &mut Vec<(u32, Instruction)>, added: &mut Vec<(u32, Instruction)>, lines: &mut Vec<Line>) {
+    for i in 0..removed.len().max(added.len()) {
+        match (removed.get(i), added.get(i)) {
+            (Some(old), Some(new)) if old.1.opcode == new.1.opcode => {
+                lines.push(Line::Changed(*old, *new));
+            },
+            (old, new) => {
+                lines.extend(old.map(|old| Line::Removed(*old)));
+                lines.extend(new.map(|new| Line::Added(*new)));
+            }
+        }
+    }
+    removed.clear();
+    added.clear();
+}
+
+pub struct Diff {
+    pub matches: Vec<FunctionMatch>,
+    // functions of the old image nothing in the new one matched
+    pub removed: Vec<u32>,
+    // functions of the new image nothing in the old one matched
+    pub added: Vec<u32>,
+}
+
+impl Diff {
+    // match up the functions of two images and compare the instructions of each pair.
+    pub fn compute(old: &Cfg, new: &Cfg) -> Diff {
+        let old_functions: BTreeMap<u32, Summary> = old.functions().map(|f| (f.entry, Summary::new(old, f.entry))).collect();
+        let new_functions: BTreeMap<u32, Summary> = new.functions().map(|f| (f.entry, Summary::new(new, f.entry))).collect();
+
+        let mut matcher = Matcher {
+            old: &old_functions,
+            new: &new_functions,
+            forward: BTreeMap::new(),
+            backward: BTreeMap::new(),
+            kinds: BTreeMap::new(),
+        };
+        matcher.match_vectors();
+        matcher.match_identical();
+        matcher.match_calls();
+        matcher.match_similar();
+
+        let matches = matcher.forward.iter().map(|(old_entry, new_entry)| {
+            let lines = matcher.lines(&old_functions[old_entry], &new_functions[new_entry]);
+            let same = lines.iter().filter(|line| matches!(line, Line::Same(_, _))).count();
+            let total = old_functions[old_entry].keys.len() + new_functions[new_entry].keys.len();
+            FunctionMatch {
+                old: *old_entry,
+                new: *new_entry,
+                kind: matcher.kinds[old_entry],
+                similarity: if total == 0 { 1.0 } else { 2.0 * same as f32 / total as f32 },
+                lines,
+            }
+        }).collect();
+        Diff {
+            matches,
+            removed: old_functions.keys().filter(|entry| !matcher.forward.contains_key(entry)).cloned().collect(),
+            added: new_functions.keys().filter(|entry| !matcher.backward.contains_key(entry)).cloned().collect(),
+        }
+    }
+
+    pub fn changed(&self) -> impl Iterator<Item=&FunctionMatch> {
+        self.matches.iter().filter(|m| !m.is_identical())
+    }
+}
+
+struct Matcher<'a> {
+    old: &'a BTreeMap<u32, Summary>,
+    new: &'a BTreeMap<u32, Summary>,
+    forward: BTreeMap<u32, u32>,
+    backward: BTreeMap<u32, u32>,
+    kinds: BTreeMap<u32, MatchKind>,
+}
+
+impl<'a> Matcher<'a> {
+    fn pair(&mut self, old: u32, new: u32, kind: MatchKind) -> bool {
+        if self.forward.contains_key(&old) || self.backward.contains_key(&new) {
+            return false;
+        }
+        self.forward.insert(old, new);
+        self.backward.insert(new, old);
+        self.kinds.insert(old, kind);
+        true
+    }
+
+    fn match_vectors(&mut self) {
+        for vector in [RESET_VECTOR, HIGH_PRIORITY_VECTOR, LOW_PRIORITY_VECTOR].iter() {
+            if self.old.contains_key(vector) && self.new.contains_key(vector) {
+                self.pair(*vector, *vector, MatchKind::Vector);
+            }
+        }
+    }
+
+    // bodies that appear exactly once on each side.
+    fn match_identical(&mut self) {
+        fn unique<'b>(functions: &'b BTreeMap<u32, Summary>, taken: &BTreeMap<u32, u32>) -> HashMap<&'b [Key], Option<u32>> {
+            let mut bodies: HashMap<&[Key], Option<u32>> = HashMap::new();
+            for (entry, summary) in functions.iter().filter(|(entry, _)| !taken.contains_key(entry)) {
+                bodies.entry(&summary.keys).and_modify(|found| *found = None).or_insert(Some(*entry));
+            }
+            bodies
+        }
+        let old_bodies = unique(self.old, &self.forward);
+        let new_bodies = unique(self.new, &self.backward);
+        let mut pairs: Vec<(u32, u32)> = old_bodies.iter().filter_map(|(body, old)| {
+            match (old, new_bodies.get(body)) {
+                (Some(old), Some(Some(new))) => Some((*old, *new)),
+                _ => None
+            }
+        }).collect();
+        pairs.sort();
+        for (old, new) in pairs {
+            self.pair(old, new, MatchKind::Identical);
+        }
+    }
+
+    // what matched functions call from the same place are likely to match too, if they look
+    // anything alike.
+    fn match_calls(&mut self) {
+        loop {
+            let mut changed = false;
+            let matched: Vec<(u32, u32)> = self.forward.iter().map(|(old, new)| (*old, *new)).collect();
+            for (old, new) in matched {
+                let old_calls = &self.old[&old].calls;
+                let new_calls = &self.new[&new].calls;
+                for (old_callee, new_callee) in old_calls.iter().zip(new_calls.iter()) {
+                    let (old_callee, new_callee) = match (self.old.get(old_callee), self.new.get(new_callee)) {
+                        (Some(old_callee), Some(new_callee)) => (old_callee, new_callee),
+                        _ => { continue; }
+                    };
+                    if similarity(old_callee, new_callee) >= SIMILARITY_THRESHOLD {
+                        changed |= self.pair(old_callee.entry, new_callee.entry, MatchKind::CallGraph);
+                    }
+                }
+            }
+            if !changed {
+                return;
+            }
+        }
+    }
+
+    // the rest by how alike they are, best pairs first.
+    fn match_similar(&mut self) {
+        let mut scored = Vec::new();
+        for (old_entry, old) in self.old.iter().filter(|(entry, _)| !self.forward.contains_key(entry)) {
+            for (new_entry, new) in self.new.iter().filter(|(entry, _)| !self.backward.contains_key(entry)) {
+                // too different in size to reach the threshold anyway
+                let (short, long) = (old.keys.len().min(new.keys.len()), old.keys.len().max(new.keys.len()));
+                if (2 * short) as f32 / ((short + long) as f32) < SIMILARITY_THRESHOLD {
+                    continue;
+                }
+                let score = similarity(old, new);
+                if score >= SIMILARITY_THRESHOLD {
+                    scored.push((score, *old_entry, *new_entry));
+                }
+            }
+        }
+        scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal).then((a.1, a.2).cmp(&(b.1, b.2))));
+        for (_, old, new) in scored {
+            self.pair(old, new, MatchKind::Similar);
+        }
+    }
+
+    // targets outside the function agree if they go to functions matched with each other, or
+    // both go somewhere nothing was matched with.
+    fn same_target(&self, old: Option<u32>, new: Option<u32>) -> bool {
+        match (old, new) {
+            (Some(old), Some(new)) => match (self.forward.get(&old), self.backward.get(&new)) {
+                (Some(to), _) => *to == new,
+                (None, None) => true,
+                (None, Some(_)) => false,
+            },
+            (old, new) => old == new,
+        }
+    }
+
+    fn lines(&self, old: &Summary, new: &Summary) -> Vec<Line> {
+        let same = |i: usize, j: usize| old.keys[i] == new.keys[j] && self.same_target(old.outside[i], new.outside[j]);
+        let table = lcs_table(old.keys.len(), new.keys.len(), same);
+        let mut lines = Vec::new();
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < old.keys.len() || j < new.keys.len() {
+            if i < old.keys.len() && j < new.keys.len() && same(i, j) {
+                pair_runs(&mut removed, &mut added, &mut lines);
+                lines.push(Line::Same(old.instructions[i], new.instructions[j]));
+                i += 1;
+                j += 1;
+            } else if j == new.keys.len() || (i < old.keys.len() && table[i + 1][j] >= table[i][j + 1]) {
+                removed.push(old.instructions[i]);
+                i += 1;
+            } else {
+                added.push(new.instructions[j]);
+                j += 1;
+            }
+        }
+        pair_runs(&mut removed, &mut added, &mut lines);
+        lines
+    }
+}
+
+impl Display for Diff {
+    // each matched function, with what changed in it, then what only one side has.
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for m in self.matches.iter() {
+            if m.is_identical() {
+                writeln!(f, "fn_{:04x} -> fn_{:04x}: unchanged ({})", m.old, m.new, m.kind.name())?;
+                continue;
+            }
+            writeln!(f, "fn_{:04x} -> fn_{:04x}: {:.0}% similar ({})", m.old, m.new, m.similarity * 100.0, m.kind.name())?;
+            for line in m.lines.iter() {
+                match line {
+                    Line::Same(_, _) => {},
+                    Line::Changed((old_addr, old), (new_addr, new)) => {
+                        writeln!(f, "  ~ {:04x}: {} => {:04x}: {}", old_addr, old, new_addr, new)?;
+                    },
+                    Line::Removed((addr, inst)) => {
+                        writeln!(f, "  - {:04x}: {}", addr, inst)?;
+                    },
+                    Line::Added((addr, inst)) => {
+                        writeln!(f, "  + {:04x}: {}", addr, inst)?;
+                    },
+                }
+            }
+        }
+        for entry in self.removed.iter() {
+            writeln!(f, "fn_{:04x}: removed", entry)?;
+        }
+        for entry in self.added.iter() {
+            writeln!(f, "fn_{:04x}: added", entry)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,366 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+use yaxpeax_arch::{Decoder, LengthedInstruction, U8Reader};
+
+use crate::{consts, InstDecoder, Instruction, Opcode, Operand};
+use crate::analysis::{file_written, FileRef};
+
+pub const RESET_VECTOR: u32 = 0x0000;
+pub const HIGH_PRIORITY_VECTOR: u32 = 0x0008;
+pub const LOW_PRIORITY_VECTOR: u32 = 0x0018;
+
+// program memory is at most 2MB, and PC wraps at that width.
+const PC_MASK: u32 = 0x1f_ffff;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Flow {
+    Next,
+    // continue at the next instruction, or skip over it
+    Skip,
+    // conditional branch: continue at the next instruction or the target
+    Branch(u32),
+    Jump(u32),
+    Call(u32),
+    // CALLW: target comes from PCLATU:PCLATH:W
+    IndirectCall,
+    // a write to PCL: target comes from PCLATU:PCLATH:<written value>
+    IndirectJump,
+    Return,
+    // RESET never comes back to the next instruction
+    Halt,
+}
+
+pub fn instruction_len(inst: &Instruction) -> u32 {
+    inst.len().to_const()
+}
+
+pub fn relative_target(addr: u32, inst: &Instruction) -> Option<u32> {
+    let words = match (inst.opcode, inst.operands[0]) {
+        (Opcode::BRA, Operand::ImmediateU32(n)) |
+        (Opcode::RCALL, Operand::ImmediateU32(n)) => {
+            // 11-bit signed word offset
+            ((n << 21) as i32) >> 21
+        },
+        (Opcode::BZ, Operand::ImmediateU8(n)) |
+        (Opcode::BNZ, Operand::ImmediateU8(n)) |
+        (Opcode::BC, Operand::ImmediateU8(n)) |
+        (Opcode::BNC, Operand::ImmediateU8(n)) |
+        (Opcode::BOV, Operand::ImmediateU8(n)) |
+        (Opcode::BNOV, Operand::ImmediateU8(n)) |
+        (Opcode::BN, Operand::ImmediateU8(n)) |
+        (Opcode::BNN, Operand::ImmediateU8(n)) => {
+            n as i8 as i32
+        },
+        _ => { return None; }
+    };
+    Some(addr.wrapping_add(2).wrapping_add((words * 2) as u32) & PC_MASK)
+}
+
+pub fn is_skip(opcode: Opcode) -> bool {
+    matches!(opcode,
+        Opcode::CPFSEQ |
+        Opcode::CPFSGT |
+        Opcode::CPFSLT |
+        Opcode::TSTFSZ |
+        Opcode::BTFSC |
+        Opcode::BTFSS |
+        Opcode::DECFSZ |
+        Opcode::INCFSZ |
+        Opcode::DCFSNZ |
+        Opcode::INFSNZ
+    )
+}
+
+pub fn flow(addr: u32, inst: &Instruction) -> Flow {
+    match inst.opcode {
+        Opcode::GOTO => {
+            match inst.operands[0] {
+                Operand::ImmediateU32(target) => Flow::Jump(target),
+                _ => Flow::Halt
+            }
+        },
+        Opcode::CALL |
+        Opcode::CALL_FAST => {
+            match inst.operands[0] {
+                Operand::ImmediateU32(target) => Flow::Call(target),
+                _ => Flow::Halt
+            }
+        },
+        Opcode::BRA => {
+            relative_target(addr, inst).map(Flow::Jump).unwrap_or(Flow::Halt)
+        },
+        Opcode::RCALL => {
+            relative_target(addr, inst).map(Flow::Call).unwrap_or(Flow::Halt)
+        },
+        Opcode::BZ |
+        Opcode::BNZ |
+        Opcode::BC |
+        Opcode::BNC |
+        Opcode::BOV |
+        Opcode::BNOV |
+        Opcode::BN |
+        Opcode::BNN => {
+            relative_target(addr, inst).map(Flow::Branch).unwrap_or(Flow::Halt)
+        },
+        Opcode::CALLW => Flow::IndirectCall,
+        Opcode::RETURN |
+        Opcode::RETURN_FAST |
+        Opcode::RETFIE |
+        Opcode::RETFIE_FAST |
+        Opcode::RETLW => Flow::Return,
+        Opcode::RESET |
+        Opcode::Invalid(_, _) => Flow::Halt,
+        opc if is_skip(opc) => Flow::Skip,
+        _ => {
+            if file_written(inst) == Some(FileRef::Absolute(consts::SFR_BASE + consts::SFRS::PCL)) {
+                Flow::IndirectJump
+            } else {
+                Flow::Next
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RegionKind {
+    Code,
+    Data,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub range: Range<u32>,
+    pub kind: RegionKind,
+}
+
+pub struct Disassembler {
+    image: Vec<u8>,
+    base: u32,
+    entries: BTreeSet<u32>,
+    // ranges known to hold data, which control flow is not followed into
+    data: Vec<Range<u32>>,
+    // where writes to PCL are known to go
+    indirect_targets: BTreeMap<u32, BTreeSet<u32>>,
+}
+
+impl Disassembler {
+    // an image loaded at address zero, explored from the reset and interrupt vectors.
+    pub fn new(image: &[u8]) -> Disassembler {
+        Disassembler::at(0, image)
+    }
+
+    pub fn at(base: u32, image: &[u8]) -> Disassembler {
+        let mut disassembler = Disassembler {
+            image: image.to_vec(),
+            base,
+            entries: BTreeSet::new(),
+            data: Vec::new(),
+            indirect_targets: BTreeMap::new(),
+        };
+        for vector in [RESET_VECTOR, HIGH_PRIORITY_VECTOR, LOW_PRIORITY_VECTOR].iter() {
+            if disassembler.contains(*vector) {
+                disassembler.add_entry(*vector);
+            }
+        }
+        disassembler
+    }
+
+    // returns false if `addr` was already an entry.
+    pub fn add_entry(&mut self, addr: u32) -> bool {
+        self.entries.insert(addr)
+    }
+
+    // returns false if `target` was already known.
+    pub fn add_indirect_target(&mut self, at: u32, target: u32) -> bool {
+        self.indirect_targets.entry(at).or_default().insert(target)
+    }
+
+    // returns false if `range` was already marked.
+    pub fn mark_data(&mut self, range: Range<u32>) -> bool {
+        if range.start >= range.end || self.data.iter().any(|known| known.start <= range.start && range.end <= known.end) {
+            return false;
+        }
+        self.data.push(range);
+        true
+    }
+
+    fn contains(&self, addr: u32) -> bool {
+        addr >= self.base && ((addr - self.base) as usize) < self.image.len()
+    }
+
+    pub fn disassemble(&self) -> Disassembly {
+        let mut disassembly = Disassembly {
+            image: self.image.clone(),
+            base: self.base,
+            entries: self.entries.clone(),
+            data: self.data.clone(),
+            indirect_targets: self.indirect_targets.clone(),
+            instructions: BTreeMap::new(),
+            undecodable: BTreeSet::new(),
+        };
+
+        let mut worklist: Vec<u32> = self.entries.iter().rev().cloned().collect();
+        while let Some(addr) = worklist.pop() {
+            if disassembly.instructions.contains_key(&addr) || disassembly.undecodable.contains(&addr) {
+                continue;
+            }
+            if disassembly.is_data(addr) {
+                continue;
+            }
+            let inst = match disassembly.decode_at(addr) {
+                Some(inst) => inst,
+                None => {
+                    disassembly.undecodable.insert(addr);
+                    continue;
+                }
+            };
+            disassembly.instructions.insert(addr, inst);
+            let next = addr + instruction_len(&inst);
+
+            match flow(addr, &inst) {
+                Flow::Next |
+                Flow::IndirectCall => {
+                    worklist.push(next);
+                },
+                Flow::Skip => {
+                    worklist.push(disassembly.skip_target(next));
+                    worklist.push(next);
+                },
+                Flow::Branch(target) |
+                Flow::Call(target) => {
+                    worklist.push(target);
+                    worklist.push(next);
+                },
+                Flow::Jump(target) => {
+                    worklist.push(target);
+                },
+                Flow::IndirectJump => {
+                    worklist.extend(disassembly.indirect_targets(addr).iter().rev());
+                },
+                Flow::Return |
+                Flow::Halt => {}
+            }
+        }
+
+        disassembly
+    }
+}
+
+pub struct Disassembly {
+    image: Vec<u8>,
+    base: u32,
+    entries: BTreeSet<u32>,
+    data: Vec<Range<u32>>,
+    indirect_targets: BTreeMap<u32, BTreeSet<u32>>,
+    instructions: BTreeMap<u32, Instruction>,
+    // addresses control flow reached, but that hold no valid instruction
+    undecodable: BTreeSet<u32>,
+}
+
+impl Disassembly {
+    pub fn image(&self) -> &[u8] {
+        &self.image
+    }
+
+    pub fn base(&self) -> u32 {
+        self.base
+    }
+
+    pub fn end(&self) -> u32 {
+        self.base + self.image.len() as u32
+    }
+
+    pub fn entries(&self) -> &BTreeSet<u32> {
+        &self.entries
+    }
+
+    pub fn instruction(&self, addr: u32) -> Option<&Instruction> {
+        self.instructions.get(&addr)
+    }
+
+    pub fn instructions(&self) -> impl Iterator<Item=(u32, &Instruction)> {
+        self.instructions.iter().map(|(addr, inst)| (*addr, inst))
+    }
+
+    // known targets of the PCL write at `at`.
+    pub fn indirect_targets(&self, at: u32) -> Vec<u32> {
+        match self.indirect_targets.get(&at) {
+            Some(targets) => targets.iter().cloned().collect(),
+            None => Vec::new()
+        }
+    }
+
+    pub fn data(&self) -> &[Range<u32>] {
+        &self.data
+    }
+
+    pub fn is_data(&self, addr: u32) -> bool {
+        self.data.iter().any(|range| range.contains(&addr))
+    }
+
+    pub fn undecodable(&self) -> &BTreeSet<u32> {
+        &self.undecodable
+    }
+
+    pub fn byte(&self, addr: u32) -> Option<u8> {
+        if addr < self.base {
+            return None;
+        }
+        self.image.get((addr - self.base) as usize).cloned()
+    }
+
+    // decode whatever is at `addr`, whether or not control flow reaches it.
+    pub fn decode_at(&self, addr: u32) -> Option<Instruction> {
+        if addr < self.base || addr & 1 != 0 {
+            return None;
+        }
+        let offset = (addr - self.base) as usize;
+        if offset >= self.image.len() {
+            return None;
+        }
+        let mut reader = U8Reader::new(&self.image[offset..]);
+        InstDecoder::default().decode(&mut reader).ok()
+    }
+
+    // where a skip lands, given the address of the instruction being skipped. two-word
+    // instructions are skipped in their entirety.
+    pub fn skip_target(&self, next: u32) -> u32 {
+        match self.decode_at(next) {
+            Some(skipped) => next + instruction_len(&skipped),
+            None => next + 2,
+        }
+    }
+
+    pub fn is_code(&self, addr: u32) -> bool {
+        match self.instructions.range(..=addr).next_back() {
+            Some((start, inst)) => addr < start + instruction_len(inst),
+            None => false
+        }
+    }
+
+    pub fn regions(&self) -> Vec<Region> {
+        let mut regions: Vec<Region> = Vec::new();
+        let mut cursor = self.base;
+        for (addr, inst) in self.instructions.iter() {
+            let end = addr + instruction_len(inst);
+            if *addr > cursor {
+                regions.push(Region { range: cursor..*addr, kind: RegionKind::Data });
+            }
+            let start = std::cmp::max(*addr, cursor);
+            match regions.last_mut() {
+                Some(Region { range, kind: RegionKind::Code }) if range.end >= start => {
+                    range.end = std::cmp::max(range.end, end);
+                },
+                _ => {
+                    regions.push(Region { range: start..end, kind: RegionKind::Code });
+                }
+            }
+            cursor = std::cmp::max(cursor, end);
+        }
+        if cursor < self.end() {
+            regions.push(Region { range: cursor..self.end(), kind: RegionKind::Data });
+        }
+        regions
+    }
+}
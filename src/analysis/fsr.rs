@@ -0,0 +1,295 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{consts, Instruction, Opcode, Operand};
+use crate::analysis::{file_read, file_written, writes_w, FileRef};
+use crate::analysis::cfg::{BasicBlock, Cfg, Edge, EdgeKind};
+use crate::analysis::dataflow::{self, Analysis};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IndirectMode {
+    Indf,
+    PostInc,
+    PostDec,
+    PreInc,
+    PlusW,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum FsrRegister {
+    Low,
+    High,
+    Indirect(IndirectMode),
+}
+
+// FSRnL, FSRnH and the five indirect registers for each of FSR0, FSR1, FSR2.
+pub(crate) fn fsr_register(addr: u16) -> Option<(usize, FsrRegister)> {
+    use self::consts::SFRS;
+    use self::FsrRegister::*;
+    use self::IndirectMode::*;
+
+    let table = [
+        (SFRS::FSR0L, 0, Low), (SFRS::FSR0H, 0, High),
+        (SFRS::INDF0, 0, Indirect(Indf)), (SFRS::POSTINC0, 0, Indirect(PostInc)),
+        (SFRS::POSTDEC0, 0, Indirect(PostDec)), (SFRS::PREINC0, 0, Indirect(PreInc)),
+        (SFRS::PLUSW0, 0, Indirect(PlusW)),
+        (SFRS::FSR1L, 1, Low), (SFRS::FSR1H, 1, High),
+        (SFRS::INDF1, 1, Indirect(Indf)), (SFRS::POSTINC1, 1, Indirect(PostInc)),
+        (SFRS::POSTDEC1, 1, Indirect(PostDec)), (SFRS::PREINC1, 1, Indirect(PreInc)),
+        (SFRS::PLUSW1, 1, Indirect(PlusW)),
+        (SFRS::FSR2L, 2, Low), (SFRS::FSR2H, 2, High),
+        (SFRS::INDF2, 2, Indirect(Indf)), (SFRS::POSTINC2, 2, Indirect(PostInc)),
+        (SFRS::POSTDEC2, 2, Indirect(PostDec)), (SFRS::PREINC2, 2, Indirect(PreInc)),
+        (SFRS::PLUSW2, 2, Indirect(PlusW)),
+    ];
+    table.iter()
+        .find(|(sfr, _, _)| consts::SFR_BASE + *sfr == addr)
+        .map(|(_, fsr, register)| (*fsr, *register))
+}
+
+// INDFn, POSTINCn, POSTDECn, PREINCn or PLUSWn.
+pub fn is_indirect(addr: u16) -> bool {
+    matches!(fsr_register(addr), Some((_, FsrRegister::Indirect(_))))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IndirectAccess {
+    pub fsr: u8,
+    pub mode: IndirectMode,
+    // the data address accessed, when the pointer (and for PLUSWn, W) is known
+    pub address: Option<u16>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct FsrState {
+    // FSRnL and FSRnH tracked separately, since code often sets them one at a time
+    fsrs: [(Option<u8>, Option<u8>); 3],
+    w: Option<u8>,
+}
+
+impl FsrState {
+    fn value(&self, fsr: usize) -> Option<u16> {
+        match self.fsrs[fsr] {
+            (Some(low), Some(high)) => Some((((high & 0x0f) as u16) << 8) | low as u16),
+            _ => None
+        }
+    }
+
+    fn set(&mut self, fsr: usize, value: Option<u16>) {
+        self.fsrs[fsr] = match value {
+            Some(value) => (Some(value as u8), Some(((value >> 8) & 0x0f) as u8)),
+            None => (None, None)
+        };
+    }
+
+    fn byte(&self, addr: u16) -> Option<u8> {
+        match fsr_register(addr) {
+            Some((fsr, FsrRegister::Low)) => self.fsrs[fsr].0,
+            Some((fsr, FsrRegister::High)) => self.fsrs[fsr].1,
+            _ => None
+        }
+    }
+
+    // perform the indirect access through `addr`, if it is an indirect register, adjusting the
+    // pointer as the access mode does.
+    fn access(&mut self, addr: u16) -> Option<IndirectAccess> {
+        let (fsr, mode) = match fsr_register(addr) {
+            Some((fsr, FsrRegister::Indirect(mode))) => (fsr, mode),
+            _ => { return None; }
+        };
+        let pointer = self.value(fsr);
+        let address = match mode {
+            IndirectMode::Indf |
+            IndirectMode::PostInc |
+            IndirectMode::PostDec => pointer,
+            IndirectMode::PreInc => pointer.map(|p| (p + 1) & 0xfff),
+            IndirectMode::PlusW => {
+                match (pointer, self.w) {
+                    (Some(p), Some(w)) => Some((p as i32 + w as i8 as i32) as u16 & 0xfff),
+                    _ => None
+                }
+            }
+        };
+        match mode {
+            IndirectMode::PostInc |
+            IndirectMode::PreInc => self.set(fsr, pointer.map(|p| (p + 1) & 0xfff)),
+            IndirectMode::PostDec => self.set(fsr, pointer.map(|p| p.wrapping_sub(1) & 0xfff)),
+            _ => {}
+        }
+        Some(IndirectAccess { fsr: fsr as u8, mode, address })
+    }
+}
+
+fn absolute_files(inst: &Instruction) -> Vec<u16> {
+    let mut files = Vec::new();
+    for file in [file_read(inst), file_written(inst)].iter() {
+        if let Some(FileRef::Absolute(addr)) = file {
+            if !files.contains(addr) {
+                files.push(*addr);
+            }
+        }
+    }
+    files
+}
+
+fn writes_fsr(fsr: usize, inst: &Instruction) -> bool {
+    if let (Opcode::LFSR, Operand::FileFSR(f)) = (inst.opcode, inst.operands[0]) {
+        return f as usize == fsr;
+    }
+    absolute_files(inst).iter().any(|addr| {
+        match fsr_register(*addr) {
+            // plain indirect accesses leave the pointer alone
+            Some((_, FsrRegister::Indirect(IndirectMode::Indf))) |
+            Some((_, FsrRegister::Indirect(IndirectMode::PlusW))) => false,
+            Some((n, FsrRegister::Indirect(_))) => n == fsr,
+            Some((n, _)) => n == fsr && file_written(inst) == Some(FileRef::Absolute(*addr)),
+            None => false
+        }
+    })
+}
+
+struct FsrAnalysis {
+    // per FSR, functions that may change it
+    clobbers: [BTreeSet<u32>; 3],
+}
+
+impl Analysis for FsrAnalysis {
+    type State = FsrState;
+
+    fn entry_state(&self, _entry: u32) -> FsrState {
+        FsrState {
+            fsrs: [(None, None); 3],
+            w: None,
+        }
+    }
+
+    fn transfer(&self, _addr: u32, inst: &Instruction, state: &mut FsrState) {
+        let before = *state;
+        for addr in absolute_files(inst) {
+            state.access(addr);
+        }
+
+        match (inst.opcode, inst.operands[0], inst.operands[1]) {
+            (Opcode::LFSR, Operand::FileFSR(fsr), Operand::ImmediateU32(k)) => {
+                if (fsr as usize) < 3 {
+                    state.set(fsr as usize, Some(k as u16 & 0xfff));
+                }
+            },
+            _ => {
+                if let Some(FileRef::Absolute(dest)) = file_written(inst) {
+                    let value = match inst.opcode {
+                        Opcode::MOVWF => before.w,
+                        Opcode::CLRF => Some(0),
+                        Opcode::SETF => Some(0xff),
+                        Opcode::MOVFF => {
+                            match inst.operands[0] {
+                                Operand::AbsoluteFile(src) => before.byte(src),
+                                _ => None
+                            }
+                        },
+                        _ => None
+                    };
+                    match fsr_register(dest) {
+                        Some((fsr, FsrRegister::Low)) => { state.fsrs[fsr].0 = value; },
+                        Some((fsr, FsrRegister::High)) => { state.fsrs[fsr].1 = value.map(|v| v & 0x0f); },
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        if writes_w(inst) {
+            state.w = match (inst.opcode, inst.operands[0]) {
+                (Opcode::MOVLW, Operand::ImmediateU8(k)) => Some(k),
+                _ => None
+            };
+        }
+    }
+
+    fn join(&self, into: &mut FsrState, other: &FsrState) {
+        fn meet(ours: &mut Option<u8>, theirs: Option<u8>) {
+            if *ours != theirs {
+                *ours = None;
+            }
+        }
+        for fsr in 0..3 {
+            meet(&mut into.fsrs[fsr].0, other.fsrs[fsr].0);
+            meet(&mut into.fsrs[fsr].1, other.fsrs[fsr].1);
+        }
+        meet(&mut into.w, other.w);
+    }
+
+    fn edge(&self, block: &BasicBlock, edge: &Edge, state: &FsrState) -> FsrState {
+        let mut state = *state;
+        if edge.kind == EdgeKind::CallReturn {
+            for fsr in 0..3 {
+                let preserved = match block.call {
+                    Some(target) => !self.clobbers[fsr].contains(&target),
+                    None => false
+                };
+                if !preserved {
+                    state.set(fsr, None);
+                }
+            }
+            state.w = None;
+        }
+        state
+    }
+
+    fn call(&self, _target: u32, state: &FsrState) -> Option<FsrState> {
+        let mut state = *state;
+        state.w = None;
+        Some(state)
+    }
+}
+
+pub struct FsrTracking {
+    pointers: BTreeMap<u32, [Option<u16>; 3]>,
+    accesses: BTreeMap<u32, Vec<IndirectAccess>>,
+}
+
+impl FsrTracking {
+    pub fn analyze(cfg: &Cfg) -> FsrTracking {
+        let analysis = FsrAnalysis {
+            clobbers: [
+                dataflow::functions_executing(cfg, |inst| writes_fsr(0, inst)),
+                dataflow::functions_executing(cfg, |inst| writes_fsr(1, inst)),
+                dataflow::functions_executing(cfg, |inst| writes_fsr(2, inst)),
+            ],
+        };
+        let solution = dataflow::solve(cfg, &analysis);
+
+        let mut pointers = BTreeMap::new();
+        let mut accesses = BTreeMap::new();
+        for block in cfg.blocks() {
+            for (addr, inst) in block.instructions.iter() {
+                let mut state = match solution.before(*addr) {
+                    Some(state) => *state,
+                    None => { continue; }
+                };
+                pointers.insert(*addr, [state.value(0), state.value(1), state.value(2)]);
+                let found: Vec<IndirectAccess> = absolute_files(inst).into_iter()
+                    .filter_map(|file| state.access(file))
+                    .collect();
+                if !found.is_empty() {
+                    accesses.insert(*addr, found);
+                }
+            }
+        }
+
+        FsrTracking { pointers, accesses }
+    }
+
+    // the value of FSR`fsr` reaching the instruction at `addr`.
+    pub fn fsr_before(&self, addr: u32, fsr: u8) -> Option<u16> {
+        self.pointers.get(&addr).and_then(|fsrs| fsrs.get(fsr as usize).cloned().flatten())
+    }
+
+    // indirect accesses made by the instruction at `addr`, in the order they happen.
+    pub fn accesses(&self, addr: u32) -> &[IndirectAccess] {
+        self.accesses.get(&addr).map(|found| found.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn all_accesses(&self) -> impl Iterator<Item=(u32, &IndirectAccess)> {
+        self.accesses.iter().flat_map(|(addr, found)| found.iter().map(move |access| (*addr, access)))
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+
+use crate::{consts, Instruction, Opcode, Operand};
+use crate::analysis::cfg::Cfg;
+use crate::analysis::disasm::{flow, instruction_len, Disassembler, Disassembly, Flow};
+
+// how far code found in a gap is followed looking for the end of a function.
+const MAX_SCAN: usize = 256;
+
+// entries on this boundary are what linkers tend to leave behind when placing sections.
+const ALIGNMENT: u32 = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Evidence {
+    // a vector, or an entry the disassembler was given
+    Entry,
+    // the target of a CALL or RCALL
+    CallTarget,
+    // the first code after a RETURN, RETLW, GOTO or BRA that nothing falls into
+    AfterTerminator,
+    // the software stack frame setup reentrant functions start with:
+    // `movff FSR2L, POSTINC1; movff FSR1L, FSR2L`
+    FramePrologue,
+    // `movlb k` as the first instruction, as XC8 emits before touching a function's locals
+    BankSelect,
+    Aligned,
+}
+
+impl Evidence {
+    // how likely this alone makes it that a function starts here.
+    pub fn weight(&self) -> f32 {
+        match self {
+            Evidence::Entry => 1.0,
+            Evidence::CallTarget => 0.95,
+            Evidence::FramePrologue => 0.9,
+            Evidence::AfterTerminator => 0.5,
+            Evidence::BankSelect => 0.15,
+            Evidence::Aligned => 0.1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub addr: u32,
+    pub evidence: Vec<Evidence>,
+    // 0 to 1, combining `evidence` as independent signals
+    pub confidence: f32,
+}
+
+// erased flash, and NOPs a linker filled a gap with.
+fn is_padding(disassembly: &Disassembly, addr: u32) -> bool {
+    matches!((disassembly.byte(addr), disassembly.byte(addr + 1)),
+        (Some(0xff), Some(0xff)) | (Some(0x00), Some(0x00)))
+}
+
+fn has_frame_prologue(disassembly: &Disassembly, addr: u32) -> bool {
+    let fsr1l = consts::SFR_BASE + consts::SFRS::FSR1L;
+    let fsr2l = consts::SFR_BASE + consts::SFRS::FSR2L;
+    let postinc1 = consts::SFR_BASE + consts::SFRS::POSTINC1;
+    let first = disassembly.decode_at(addr);
+    let second = disassembly.decode_at(addr + 4);
+    match (first, second) {
+        (Some(first), Some(second)) => {
+            first.opcode == Opcode::MOVFF && second.opcode == Opcode::MOVFF &&
+                first.operands[0] == Operand::AbsoluteFile(fsr2l) &&
+                first.operands[1] == Operand::AbsoluteFile(postinc1) &&
+                second.operands[0] == Operand::AbsoluteFile(fsr1l) &&
+                second.operands[1] == Operand::AbsoluteFile(fsr2l)
+        },
+        _ => false
+    }
+}
+
+// whether decoding from `addr` looks like a function: valid instructions up to something that
+// leaves, without running into known code or data first.
+fn plausible_code(disassembly: &Disassembly, addr: u32) -> bool {
+    let mut cursor = addr;
+    for _ in 0..MAX_SCAN {
+        if cursor != addr && (disassembly.is_code(cursor) || disassembly.is_data(cursor)) {
+            // ran into code or data already known about, which is as good a place to end as any
+            return true;
+        }
+        if is_padding(disassembly, cursor) {
+            return false;
+        }
+        let inst = match disassembly.decode_at(cursor) {
+            Some(inst) => inst,
+            None => { return false; }
+        };
+        if let Opcode::Invalid(_, _) = inst.opcode {
+            return false;
+        }
+        match flow(cursor, &inst) {
+            Flow::Return | Flow::Jump(_) | Flow::IndirectJump => { return true; },
+            Flow::Halt => { return false; },
+            _ => {}
+        }
+        cursor += instruction_len(&inst);
+    }
+    false
+}
+
+fn gap_starts(disassembly: &Disassembly) -> Vec<u32> {
+    let mut starts = Vec::new();
+    for (addr, inst) in disassembly.instructions() {
+        let leaves = matches!(flow(addr, inst), Flow::Return | Flow::Jump(_) | Flow::IndirectJump);
+        if !leaves {
+            continue;
+        }
+        let mut cursor = addr + instruction_len(inst);
+        while disassembly.byte(cursor).is_some() && !disassembly.is_code(cursor) && !disassembly.is_data(cursor) {
+            if !is_padding(disassembly, cursor) {
+                starts.push(cursor);
+                break;
+            }
+            cursor += 2;
+        }
+    }
+    starts
+}
+
+// guess where functions start, from what the disassembly already found and what is left
+// in the gaps between it.
+pub fn detect(disassembly: &Disassembly) -> Vec<Candidate> {
+    let mut evidence: BTreeMap<u32, Vec<Evidence>> = BTreeMap::new();
+
+    for entry in disassembly.entries().iter() {
+        evidence.entry(*entry).or_default().push(Evidence::Entry);
+    }
+    for (addr, inst) in disassembly.instructions() {
+        if let Flow::Call(target) = flow(addr, inst) {
+            let found = evidence.entry(target).or_default();
+            if !found.contains(&Evidence::CallTarget) {
+                found.push(Evidence::CallTarget);
+            }
+        }
+    }
+    for start in gap_starts(disassembly) {
+        if plausible_code(disassembly, start) {
+            evidence.entry(start).or_default().push(Evidence::AfterTerminator);
+        }
+    }
+    // frame prologues count wherever they are that isn't the middle of known data
+    let mut addr = disassembly.base();
+    while addr + 8 <= disassembly.end() {
+        if !disassembly.is_data(addr) && has_frame_prologue(disassembly, addr) {
+            evidence.entry(addr).or_default().push(Evidence::FramePrologue);
+        }
+        addr += 2;
+    }
+
+    let mut candidates = Vec::new();
+    for (addr, mut found) in evidence.into_iter() {
+        if let Some(Instruction { opcode: Opcode::MOVLB, .. }) = disassembly.decode_at(addr) {
+            found.push(Evidence::BankSelect);
+        }
+        if addr % ALIGNMENT == 0 {
+            found.push(Evidence::Aligned);
+        }
+        let doubt: f32 = found.iter().map(|evidence| 1.0 - evidence.weight()).product();
+        candidates.push(Candidate {
+            addr,
+            evidence: found,
+            confidence: 1.0 - doubt,
+        });
+    }
+    candidates
+}
+
+// add candidates at least `threshold` confident as disassembler entries, so code in gaps gets
+// disassembled. true if any were new.
+pub fn add_entries(disassembler: &mut Disassembler, candidates: &[Candidate], threshold: f32) -> bool {
+    let mut added = false;
+    for candidate in candidates.iter().filter(|candidate| candidate.confidence >= threshold) {
+        added |= disassembler.add_entry(candidate.addr);
+    }
+    added
+}
+
+// make candidates at least `threshold` confident into functions of `cfg`, where they start a
+// block.
+pub fn add_functions(cfg: &mut Cfg, candidates: &[Candidate], threshold: f32) {
+    for candidate in candidates.iter().filter(|candidate| candidate.confidence >= threshold) {
+        if cfg.function(candidate.addr).is_none() && cfg.block(candidate.addr).is_some() {
+            cfg.add_function(candidate.addr);
+        }
+    }
+}
@@ -0,0 +1,628 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display, Formatter};
+
+use crate::{consts, Instruction, Opcode, Operand};
+use crate::analysis::{file_written, FileRef};
+use crate::analysis::bsr::BsrTracking;
+use crate::analysis::callgraph::CallGraph;
+use crate::analysis::cfg::{BasicBlock, Cfg, Edge, EdgeKind};
+use crate::analysis::constprop::{function_writes, reg_cell};
+use crate::analysis::dataflow::{self, Analysis, Solution};
+use crate::analysis::disasm::{Disassembly, HIGH_PRIORITY_VECTOR, LOW_PRIORITY_VECTOR, RESET_VECTOR};
+use crate::analysis::fsr::FsrTracking;
+use crate::analysis::xref::{XrefKind, Xrefs};
+use crate::ir::{self, BinOp, Expr, Reg, Stmt};
+
+const WREG: u16 = consts::SFR_BASE + consts::SFRS::WREG;
+const STATUS: u16 = consts::SFR_BASE + consts::SFRS::STATUS;
+const BSR: u16 = consts::SFR_BASE + consts::SFRS::BSR;
+const INTCON: u16 = consts::SFR_BASE + consts::SFRS::INTCON;
+const TBLPTRL: u16 = consts::SFR_BASE + consts::SFRS::TBLPTRL;
+const TBLPTRH: u16 = consts::SFR_BASE + consts::SFRS::TBLPTRH;
+const TBLPTRU: u16 = consts::SFR_BASE + consts::SFRS::TBLPTRU;
+
+// INTCON bits. without IPEN these are GIE and PEIE, which mask the same interrupts as far as
+// the single vector is concerned.
+const GIEH: u8 = 7;
+const GIEL: u8 = 6;
+
+// registers main code expects to find as it left them after an interrupt.
+pub const CONTEXT: [u16; 17] = [
+    WREG,
+    STATUS,
+    BSR,
+    consts::SFR_BASE + consts::SFRS::FSR0L,
+    consts::SFR_BASE + consts::SFRS::FSR0H,
+    consts::SFR_BASE + consts::SFRS::FSR1L,
+    consts::SFR_BASE + consts::SFRS::FSR1H,
+    consts::SFR_BASE + consts::SFRS::FSR2L,
+    consts::SFR_BASE + consts::SFRS::FSR2H,
+    consts::SFR_BASE + consts::SFRS::PRODL,
+    consts::SFR_BASE + consts::SFRS::PRODH,
+    TBLPTRL,
+    TBLPTRH,
+    TBLPTRU,
+    consts::SFR_BASE + consts::SFRS::TABLAT,
+    consts::SFR_BASE + consts::SFRS::PCLATH,
+    consts::SFR_BASE + consts::SFRS::PCLATU,
+];
+
+// what RETFIE FAST restores from the shadow registers.
+const SHADOWED: [u16; 3] = [WREG, STATUS, BSR];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    High,
+    Low,
+}
+
+impl Priority {
+    pub fn vector(&self) -> u32 {
+        match self {
+            Priority::High => HIGH_PRIORITY_VECTOR,
+            Priority::Low => LOW_PRIORITY_VECTOR,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Priority::High => "high priority",
+            Priority::Low => "low priority",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Isr {
+    pub priority: Priority,
+    // the vector's function and everything it calls
+    pub functions: BTreeSet<u32>,
+    // context registers written and not put back by every return
+    pub clobbered: BTreeSet<u16>,
+    // context registers written, but restored before every return
+    pub saved: BTreeSet<u16>,
+    // RETFIE FAST instructions
+    pub fast_returns: Vec<u32>,
+}
+
+impl Isr {
+    // RETFIE FAST in a low priority handler restores whatever a high priority interrupt last left
+    // in the shadow registers, which is not what the low priority interrupt found.
+    pub fn corrupts_shadow(&self) -> bool {
+        self.priority == Priority::Low && !self.fast_returns.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedVariable {
+    pub address: u16,
+    // the vector of the interrupt it is shared with
+    pub vector: u32,
+    // (instruction, whether it writes) in code the interrupt runs
+    pub isr_accesses: Vec<(u32, bool)>,
+    // (instruction, whether it writes) in code the interrupt can preempt, where it isn't masked
+    pub unprotected: Vec<(u32, bool)>,
+}
+
+// context registers the instruction at `addr` may write.
+fn context_written(addr: u32, inst: &Instruction, bsr: &BsrTracking) -> Vec<u16> {
+    let mut written = Vec::new();
+    for stmt in ir::lift(addr, inst).iter() {
+        match stmt {
+            Stmt::Store(Expr::Const(dest), _) => written.push(*dest as u16),
+            Stmt::Store(_, _) => {
+                if let Some(file @ FileRef::Banked(_)) = file_written(inst) {
+                    written.extend(bsr.resolve(addr, file));
+                }
+            },
+            Stmt::SetReg(Reg::Tblptr, _) => {
+                written.extend(&[TBLPTRL, TBLPTRH, TBLPTRU]);
+            },
+            Stmt::SetReg(reg, _) => written.push(reg_cell(*reg)),
+            Stmt::SetFlag(_, _) => written.push(STATUS),
+            _ => {}
+        }
+    }
+    written.retain(|addr| CONTEXT.contains(addr));
+    written
+}
+
+// context registers each function may write, itself or through what it calls.
+fn context_writes(cfg: &Cfg, calls: &CallGraph, bsr: &BsrTracking) -> BTreeMap<u32, BTreeSet<u16>> {
+    let mut writes: BTreeMap<u32, BTreeSet<u16>> = BTreeMap::new();
+    for function in cfg.functions() {
+        let written = writes.entry(function.entry).or_default();
+        for start in function.blocks.iter() {
+            for (addr, inst) in cfg.block(*start).unwrap().instructions.iter() {
+                written.extend(context_written(*addr, inst, bsr));
+            }
+        }
+    }
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for function in calls.functions() {
+            let mut merged = writes.get(&function).cloned().unwrap_or_default();
+            for site in calls.calls(function).iter() {
+                match site.target.and_then(|target| writes.get(&target)) {
+                    Some(theirs) => merged.extend(theirs),
+                    // CALLW, or a call to something that isn't a function
+                    None => merged.extend(CONTEXT.iter()),
+                }
+            }
+            if Some(&merged) != writes.get(&function) {
+                writes.insert(function, merged);
+                changed = true;
+            }
+        }
+    }
+    writes
+}
+
+// which context register's value on entry a location holds, if any, and whether its nibbles
+// are swapped: SWAPF is how W and STATUS are restored without touching STATUS.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Origin {
+    reg: u16,
+    swapped: bool,
+}
+
+type Holding = BTreeMap<u16, Origin>;
+
+fn origin(expr: &Expr, holding: &Holding, temps: &BTreeMap<u8, Origin>) -> Option<Origin> {
+    match expr {
+        Expr::Load(addr) => match **addr {
+            Expr::Const(addr) => holding.get(&(addr as u16)).cloned(),
+            _ => None
+        },
+        Expr::Reg(reg) => holding.get(&reg_cell(*reg)).cloned(),
+        Expr::Temp(t) => temps.get(t).cloned(),
+        // ((x << 4) | (x >> 4)) & 0xff
+        Expr::Binary(BinOp::And, swap, mask) if **mask == Expr::Const(0xff) => match &**swap {
+            Expr::Binary(BinOp::Or, high, low) => match (&**high, &**low) {
+                (Expr::Binary(BinOp::Shl, a, four), Expr::Binary(BinOp::Shr, b, four_again))
+                    if a == b && **four == Expr::Const(4) && **four_again == Expr::Const(4) => {
+                    origin(a, holding, temps).map(|origin| Origin { swapped: !origin.swapped, ..origin })
+                },
+                _ => None
+            },
+            _ => None
+        },
+        _ => None
+    }
+}
+
+fn forget_ram(holding: &mut Holding) {
+    holding.retain(|addr, _| *addr >= consts::SFR_BASE);
+}
+
+// follow where the entry values of context registers go through one instruction.
+fn hold(addr: u32, inst: &Instruction, bsr: &BsrTracking, holding: &mut Holding) {
+    let mut temps = BTreeMap::new();
+    for stmt in ir::lift(addr, inst).iter() {
+        match stmt {
+            Stmt::Let(t, value) => {
+                match origin(value, holding, &temps) {
+                    Some(origin) => { temps.insert(*t, origin); },
+                    None => { temps.remove(t); }
+                }
+            },
+            Stmt::Store(Expr::Const(dest), value) => {
+                match origin(value, holding, &temps) {
+                    Some(origin) => { holding.insert(*dest as u16, origin); },
+                    None => { holding.remove(&(*dest as u16)); }
+                }
+            },
+            Stmt::Store(_, _) => {
+                let resolved = match file_written(inst) {
+                    Some(file @ FileRef::Banked(_)) => bsr.resolve(addr, file),
+                    _ => Vec::new()
+                };
+                if resolved.is_empty() {
+                    forget_ram(holding);
+                }
+                for dest in resolved {
+                    holding.remove(&dest);
+                }
+            },
+            Stmt::SetReg(Reg::Tblptr, _) => {
+                for reg in [TBLPTRL, TBLPTRH, TBLPTRU].iter() {
+                    holding.remove(reg);
+                }
+            },
+            Stmt::SetReg(reg, value) => {
+                match origin(value, holding, &temps) {
+                    Some(origin) => { holding.insert(reg_cell(*reg), origin); },
+                    None => { holding.remove(&reg_cell(*reg)); }
+                }
+            },
+            Stmt::SetFlag(_, _) => {
+                holding.remove(&STATUS);
+            },
+            _ => {}
+        }
+    }
+}
+
+fn intersect(into: &mut Holding, other: &Holding) {
+    into.retain(|addr, origin| other.get(addr) == Some(origin));
+}
+
+// the blocks the handler at `vector` runs, following tail calls and fallthrough into other
+// functions; what it calls is left to the callees' summaries.
+fn handler_blocks(cfg: &Cfg, vector: u32) -> BTreeSet<u32> {
+    let mut blocks = BTreeSet::new();
+    let mut worklist = vec![vector];
+    while let Some(start) = worklist.pop() {
+        if let Some(block) = cfg.block(start).filter(|_| blocks.insert(start)) {
+            worklist.extend(block.successors.iter().map(|edge| edge.target));
+        }
+    }
+    blocks
+}
+
+// context registers holding their entry value at every return from the handler at `vector`.
+// the handler's returns may be in functions it jumps or falls into, as single-vector firmware
+// running on from 0x08 through 0x18 does.
+fn preserved(
+    cfg: &Cfg,
+    vector: u32,
+    priority: Priority,
+    bsr: &BsrTracking,
+    ram_writes: &BTreeMap<u32, Option<BTreeSet<u16>>>,
+    context_writes: &BTreeMap<u32, BTreeSet<u16>>,
+) -> BTreeSet<u16> {
+    if cfg.block(vector).is_none() {
+        return BTreeSet::new();
+    }
+    let entry: Holding = CONTEXT.iter().map(|reg| (*reg, Origin { reg: *reg, swapped: false })).collect();
+    let mut block_in: BTreeMap<u32, Holding> = BTreeMap::new();
+    block_in.insert(vector, entry);
+    let mut worklist = vec![vector];
+    let mut at_returns: Option<BTreeSet<u16>> = None;
+    while let Some(start) = worklist.pop() {
+        let block = cfg.block(start).unwrap();
+        let mut holding = block_in[&start].clone();
+        for (addr, inst) in block.instructions.iter() {
+            hold(*addr, inst, bsr, &mut holding);
+        }
+        let (_, last) = block.terminator();
+        if let Opcode::RETFIE | Opcode::RETFIE_FAST | Opcode::RETURN | Opcode::RETURN_FAST | Opcode::RETLW = last.opcode {
+            let fast = matches!(last.opcode, Opcode::RETFIE_FAST | Opcode::RETURN_FAST);
+            let kept: BTreeSet<u16> = CONTEXT.iter().filter(|reg| {
+                holding.get(reg) == Some(&Origin { reg: **reg, swapped: false }) ||
+                    (fast && priority == Priority::High && SHADOWED.contains(reg))
+            }).cloned().collect();
+            at_returns = Some(match at_returns {
+                Some(so_far) => so_far.intersection(&kept).cloned().collect(),
+                None => kept
+            });
+        }
+        if last.opcode == Opcode::CALLW {
+            holding.clear();
+        }
+        if let Some(target) = block.call {
+            match ram_writes.get(&target) {
+                Some(Some(written)) => {
+                    for addr in written.iter() {
+                        holding.remove(addr);
+                    }
+                },
+                _ => forget_ram(&mut holding)
+            }
+            match context_writes.get(&target) {
+                Some(written) => {
+                    for reg in written.iter() {
+                        holding.remove(reg);
+                    }
+                },
+                None => holding.retain(|addr, _| !CONTEXT.contains(addr))
+            }
+        }
+        for edge in block.successors.iter() {
+            match block_in.get_mut(&edge.target) {
+                Some(existing) => {
+                    let before = existing.len();
+                    intersect(existing, &holding);
+                    if existing.len() != before {
+                        worklist.push(edge.target);
+                    }
+                },
+                None => {
+                    block_in.insert(edge.target, holding.clone());
+                    worklist.push(edge.target);
+                }
+            }
+        }
+    }
+    // with no return to be found, nothing is known to be put back
+    at_returns.unwrap_or_default()
+}
+
+// whether the global interrupt enable bits are known clear or set.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Masking {
+    pub gieh: Option<bool>,
+    pub giel: Option<bool>,
+}
+
+impl Masking {
+    // whether an interrupt of this priority can't happen here.
+    pub fn masks(&self, priority: Priority) -> bool {
+        match priority {
+            Priority::High => self.gieh == Some(false),
+            Priority::Low => self.gieh == Some(false) || self.giel == Some(false),
+        }
+    }
+}
+
+fn writes_intcon(inst: &Instruction) -> bool {
+    match file_written(inst) {
+        Some(FileRef::Absolute(INTCON)) => true,
+        Some(FileRef::Banked(file)) => file as u16 == INTCON & 0xff,
+        _ => false
+    }
+}
+
+struct MaskAnalysis {
+    // functions that may change INTCON
+    changes: BTreeSet<u32>,
+    // 0x18 is part of the handler at 0x08 rather than a vector of its own
+    single_vector: bool,
+}
+
+impl Analysis for MaskAnalysis {
+    type State = Masking;
+
+    fn entry_state(&self, entry: u32) -> Masking {
+        match entry {
+            // INTCON clears on reset
+            RESET_VECTOR => Masking { gieh: Some(false), giel: Some(false) },
+            // taking an interrupt clears the enable bit for its priority
+            HIGH_PRIORITY_VECTOR => Masking { gieh: Some(false), giel: None },
+            LOW_PRIORITY_VECTOR if self.single_vector => Masking { gieh: Some(false), giel: None },
+            LOW_PRIORITY_VECTOR => Masking { gieh: None, giel: Some(false) },
+            _ => Masking { gieh: None, giel: None },
+        }
+    }
+
+    fn transfer(&self, _addr: u32, inst: &Instruction, state: &mut Masking) {
+        if !writes_intcon(inst) {
+            return;
+        }
+        let banked = matches!(file_written(inst), Some(FileRef::Banked(_)));
+        *state = match (inst.opcode, inst.operands[1]) {
+            _ if banked => Masking { gieh: None, giel: None },
+            (Opcode::BCF, Operand::ImmediateU8(bit)) |
+            (Opcode::BSF, Operand::ImmediateU8(bit)) => {
+                let set = inst.opcode == Opcode::BSF;
+                match bit {
+                    GIEH => Masking { gieh: Some(set), ..*state },
+                    GIEL => Masking { giel: Some(set), ..*state },
+                    _ => *state
+                }
+            },
+            (Opcode::CLRF, _) => Masking { gieh: Some(false), giel: Some(false) },
+            (Opcode::SETF, _) => Masking { gieh: Some(true), giel: Some(true) },
+            _ => Masking { gieh: None, giel: None },
+        };
+    }
+
+    fn join(&self, into: &mut Masking, other: &Masking) {
+        if into.gieh != other.gieh {
+            into.gieh = None;
+        }
+        if into.giel != other.giel {
+            into.giel = None;
+        }
+    }
+
+    fn edge(&self, block: &BasicBlock, edge: &Edge, state: &Masking) -> Masking {
+        if edge.kind != EdgeKind::CallReturn {
+            return *state;
+        }
+        match block.call {
+            Some(target) if !self.changes.contains(&target) => *state,
+            _ => Masking { gieh: None, giel: None }
+        }
+    }
+
+    fn call(&self, _target: u32, state: &Masking) -> Option<Masking> {
+        Some(*state)
+    }
+}
+
+// who can be running a function: main code, or an interrupt handler.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Context {
+    Main,
+    Interrupt(Priority),
+}
+
+impl Context {
+    fn preempted_by(&self, priority: Priority) -> bool {
+        match self {
+            Context::Main => true,
+            Context::Interrupt(Priority::Low) => priority == Priority::High,
+            Context::Interrupt(Priority::High) => false,
+        }
+    }
+}
+
+pub struct IsrAnalysis {
+    isrs: Vec<Isr>,
+    shared: Vec<SharedVariable>,
+    masking: Solution<Masking>,
+}
+
+impl IsrAnalysis {
+    pub fn analyze(cfg: &Cfg, disassembly: &Disassembly) -> IsrAnalysis {
+        let calls = CallGraph::build(cfg);
+        let bsr = BsrTracking::analyze(cfg);
+        let ram_writes = function_writes(cfg, &bsr);
+        let context_writes = context_writes(cfg, &calls, &bsr);
+        // without IPEN there is one vector, and its handler usually runs on past 0x18
+        let single_vector = handler_blocks(cfg, HIGH_PRIORITY_VECTOR).contains(&LOW_PRIORITY_VECTOR);
+
+        // everything each entry point runs
+        let mut contexts: BTreeMap<u32, BTreeSet<Context>> = BTreeMap::new();
+        for entry in cfg.entries().iter() {
+            let context = match *entry {
+                HIGH_PRIORITY_VECTOR => Context::Interrupt(Priority::High),
+                LOW_PRIORITY_VECTOR if single_vector => { continue; },
+                LOW_PRIORITY_VECTOR => Context::Interrupt(Priority::Low),
+                _ => Context::Main,
+            };
+            let mut worklist = vec![*entry];
+            while let Some(function) = worklist.pop() {
+                if cfg.function(function).is_some() && contexts.entry(function).or_default().insert(context) {
+                    worklist.extend(calls.callees(function));
+                }
+            }
+        }
+
+        let mut isrs = Vec::new();
+        for priority in [Priority::High, Priority::Low].iter() {
+            let vector = priority.vector();
+            if cfg.function(vector).is_none() || (*priority == Priority::Low && single_vector) {
+                continue;
+            }
+            let functions: BTreeSet<u32> = contexts.iter()
+                .filter(|(_, contexts)| contexts.contains(&Context::Interrupt(*priority)))
+                .map(|(function, _)| *function)
+                .collect();
+            let written = context_writes.get(&vector).cloned().unwrap_or_default();
+            let kept = preserved(cfg, vector, *priority, &bsr, &ram_writes, &context_writes);
+            let mut fast_returns = Vec::new();
+            for function in functions.iter() {
+                for start in cfg.function(*function).unwrap().blocks.iter() {
+                    let (addr, inst) = cfg.block(*start).unwrap().terminator();
+                    if inst.opcode == Opcode::RETFIE_FAST {
+                        fast_returns.push(addr);
+                    }
+                }
+            }
+            fast_returns.sort();
+            fast_returns.dedup();
+            isrs.push(Isr {
+                priority: *priority,
+                functions,
+                clobbered: written.difference(&kept).cloned().collect(),
+                saved: written.intersection(&kept).cloned().collect(),
+                fast_returns,
+            });
+        }
+
+        let masking = dataflow::solve(cfg, &MaskAnalysis {
+            changes: dataflow::functions_executing(cfg, writes_intcon),
+            single_vector,
+        });
+
+        // which functions each block belongs to
+        let mut owners: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+        for function in cfg.functions() {
+            for start in function.blocks.iter() {
+                owners.entry(*start).or_default().push(function.entry);
+            }
+        }
+        let contexts_at = |addr: u32| -> BTreeSet<Context> {
+            let mut found = BTreeSet::new();
+            if let Some(block) = cfg.block_containing(addr) {
+                for function in owners.get(&block.start).map(|owners| owners.as_slice()).unwrap_or(&[]) {
+                    found.extend(contexts.get(function).iter().flat_map(|contexts| contexts.iter()));
+                }
+            }
+            found
+        };
+
+        let fsr = FsrTracking::analyze(cfg);
+        let xrefs = Xrefs::build(disassembly, Some(&bsr), Some(&fsr));
+        let mut shared = Vec::new();
+        for (address, refs) in xrefs.data_targets() {
+            if address >= consts::SFR_BASE {
+                continue;
+            }
+            for isr in isrs.iter() {
+                let mut isr_accesses = Vec::new();
+                let mut unprotected = Vec::new();
+                for xref in refs.iter() {
+                    let access = (xref.from, xref.kind == XrefKind::Write);
+                    let running = contexts_at(xref.from);
+                    if running.contains(&Context::Interrupt(isr.priority)) {
+                        isr_accesses.push(access);
+                    }
+                    let masked = masking.before(xref.from).map(|state| state.masks(isr.priority)).unwrap_or(false);
+                    if !masked && running.iter().any(|context| context.preempted_by(isr.priority)) {
+                        unprotected.push(access);
+                    }
+                }
+                let writes = isr_accesses.iter().chain(unprotected.iter()).any(|(_, write)| *write);
+                if !isr_accesses.is_empty() && !unprotected.is_empty() && writes {
+                    shared.push(SharedVariable {
+                        address,
+                        vector: isr.priority.vector(),
+                        isr_accesses,
+                        unprotected,
+                    });
+                }
+            }
+        }
+
+        IsrAnalysis { isrs, shared, masking }
+    }
+
+    pub fn isrs(&self) -> &[Isr] {
+        &self.isrs
+    }
+
+    pub fn isr(&self, priority: Priority) -> Option<&Isr> {
+        self.isrs.iter().find(|isr| isr.priority == priority)
+    }
+
+    // variables an interrupt handler and the code it interrupts both touch, at least one of them
+    // writing, where the interrupted side doesn't mask the interrupt first.
+    pub fn shared(&self) -> &[SharedVariable] {
+        &self.shared
+    }
+
+    // the interrupt enable bits as known just before the instruction at `addr`.
+    pub fn masking_before(&self, addr: u32) -> Option<&Masking> {
+        self.masking.before(addr)
+    }
+}
+
+fn names(registers: &BTreeSet<u16>) -> String {
+    registers.iter().map(|reg| consts::named_file(*reg)).collect::<Vec<&str>>().join(", ")
+}
+
+fn accesses(accesses: &[(u32, bool)]) -> String {
+    accesses.iter()
+        .map(|(addr, write)| format!("{:04x} ({})", addr, if *write { "write" } else { "read" }))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+impl Display for IsrAnalysis {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for isr in self.isrs.iter() {
+            writeln!(f, "{} isr at {:04x}, {} functions", isr.priority.name(), isr.priority.vector(), isr.functions.len())?;
+            if !isr.saved.is_empty() {
+                writeln!(f, "  saves: {}", names(&isr.saved))?;
+            }
+            if !isr.clobbered.is_empty() {
+                writeln!(f, "  clobbers: {}", names(&isr.clobbered))?;
+            }
+            if isr.corrupts_shadow() {
+                for addr in isr.fast_returns.iter() {
+                    writeln!(f, "  {:04x}: retfie fast restores shadow registers a high priority interrupt may have overwritten", addr)?;
+                }
+            }
+        }
+        for shared in self.shared.iter() {
+            writeln!(f, "{} shared with isr at {:04x}", consts::named_file(shared.address), shared.vector)?;
+            writeln!(f, "  isr: {}", accesses(&shared.isr_accesses))?;
+            writeln!(f, "  unprotected: {}", accesses(&shared.unprotected))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{consts, Instruction, Opcode, Operand};
+use crate::analysis::{file_read, file_written, writes_w, FileRef};
+use crate::analysis::cfg::{BasicBlock, Cfg, Edge, EdgeKind};
+use crate::analysis::dataflow::{self, Analysis};
+use crate::analysis::disasm::{flow, instruction_len, Disassembly, Flow};
+
+const PCL: u16 = consts::SFR_BASE + consts::SFRS::PCL;
+const PCLATH: u16 = consts::SFR_BASE + consts::SFRS::PCLATH;
+const PCLATU: u16 = consts::SFR_BASE + consts::SFRS::PCLATU;
+
+const MAX_ENTRIES: usize = 256;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TableKind {
+    // entries are BRA or GOTO
+    Jump,
+    // entries are RETLW, so the table is a byte lookup
+    Lookup,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JumpTable {
+    // the instruction writing PCL
+    pub at: u32,
+    pub kind: TableKind,
+    // every address the write to PCL may land on
+    pub entries: Vec<u32>,
+    // for lookup tables, what each entry returns
+    pub values: Vec<u8>,
+}
+
+// what the instructions leading up to a `movwf PCL` say about where it goes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Computed {
+    // W, when it is exactly known
+    w: Option<u8>,
+    // W is this plus some index
+    w_base: Option<u8>,
+    pclath: Option<u8>,
+    pclatu: Option<u8>,
+    // PCLATH is bumped when the index carries, so the table may run past the end of its page
+    carry_fixup: bool,
+}
+
+impl Computed {
+    fn step(&mut self, addr: u32, inst: &Instruction) {
+        if file_read(inst) == Some(FileRef::Absolute(PCL)) {
+            // reading PCL latches the rest of the PC, already past this instruction, into PCLATH
+            // and PCLATU
+            let pc = addr + instruction_len(inst);
+            self.pclath = Some((pc >> 8) as u8);
+            self.pclatu = Some((pc >> 16) as u8);
+        }
+        if let Some(FileRef::Absolute(dest)) = file_written(inst) {
+            let value = match inst.opcode {
+                Opcode::MOVWF => self.w,
+                Opcode::CLRF => Some(0),
+                _ => None
+            };
+            match dest {
+                // `btfsc STATUS, C; incf PCLATH` carry fixups leave the table page alone
+                PCLATH if inst.opcode == Opcode::INCF => { self.carry_fixup = true; },
+                PCLATH => {
+                    self.pclath = value;
+                    self.carry_fixup = false;
+                },
+                PCLATU => { self.pclatu = value; },
+                _ => {}
+            }
+        }
+        if writes_w(inst) {
+            match (inst.opcode, inst.operands[0]) {
+                (Opcode::MOVLW, Operand::ImmediateU8(k)) => {
+                    self.w = Some(k);
+                    self.w_base = Some(k);
+                },
+                (Opcode::ADDLW, Operand::ImmediateU8(k)) => {
+                    self.w_base = Some(self.w.unwrap_or(0).wrapping_add(k));
+                    self.w = self.w.map(|w| w.wrapping_add(k));
+                },
+                (Opcode::ADDWF, _) => {
+                    // base + index, whichever of the two W held
+                    self.w = None;
+                },
+                _ => {
+                    self.w = None;
+                    self.w_base = None;
+                }
+            }
+        }
+    }
+}
+
+// the carry fixup sits behind a skip, so the `movwf PCL` starts a block of its own and the
+// PCLATH setup is only found by following it back through its predecessors.
+struct ComputedAnalysis;
+
+impl Analysis for ComputedAnalysis {
+    type State = Computed;
+
+    fn entry_state(&self, _entry: u32) -> Computed {
+        Computed::default()
+    }
+
+    fn transfer(&self, addr: u32, inst: &Instruction, state: &mut Computed) {
+        state.step(addr, inst);
+    }
+
+    fn join(&self, into: &mut Computed, other: &Computed) {
+        if into.w != other.w {
+            into.w = None;
+        }
+        if into.w_base != other.w_base {
+            into.w_base = None;
+        }
+        if into.pclath != other.pclath {
+            into.pclath = None;
+        }
+        if into.pclatu != other.pclatu {
+            into.pclatu = None;
+        }
+        into.carry_fixup |= other.carry_fixup;
+    }
+
+    fn edge(&self, _block: &BasicBlock, edge: &Edge, state: &Computed) -> Computed {
+        match edge.kind {
+            // nothing is known about what a callee leaves behind
+            EdgeKind::CallReturn => Computed::default(),
+            _ => state.clone()
+        }
+    }
+}
+
+fn scan(disassembly: &Disassembly, base: u32, end: u32) -> Option<(TableKind, Vec<u32>, Vec<u8>)> {
+    let mut kind = None;
+    let mut stride = None;
+    let mut entries = Vec::new();
+    let mut values = Vec::new();
+    let mut addr = base;
+    while addr < end && entries.len() < MAX_ENTRIES {
+        if addr != base && disassembly.entries().contains(&addr) {
+            break;
+        }
+        let inst = match disassembly.decode_at(addr) {
+            Some(inst) => inst,
+            None => { break; }
+        };
+        let (entry_kind, value) = match (inst.opcode, inst.operands[0]) {
+            (Opcode::BRA, _) |
+            (Opcode::GOTO, _) => (TableKind::Jump, None),
+            (Opcode::RETLW, Operand::ImmediateU8(k)) => (TableKind::Lookup, Some(k)),
+            _ => { break; }
+        };
+        if kind.map(|kind| kind != entry_kind).unwrap_or(false) {
+            break;
+        }
+        let len = instruction_len(&inst);
+        if stride.map(|stride| stride != len).unwrap_or(false) {
+            break;
+        }
+        kind = Some(entry_kind);
+        stride = Some(len);
+        entries.push(addr);
+        values.extend(value);
+        addr += len;
+    }
+    kind.map(|kind| (kind, entries, values))
+}
+
+// computed gotos: `addwf PCL` followed by a table of BRA, GOTO or RETLW, or `movwf PCL` after
+// PCLATH (and PCLATU) have been pointed at such a table.
+pub fn find(cfg: &Cfg, disassembly: &Disassembly) -> Vec<JumpTable> {
+    let solution = dataflow::solve(cfg, &ComputedAnalysis);
+    let mut tables = Vec::new();
+    for block in cfg.blocks() {
+        let (at, inst) = block.terminator();
+        if flow(at, &inst) != Flow::IndirectJump {
+            continue;
+        }
+
+        let mut carry_fixup = false;
+        let base = match inst.opcode {
+            Opcode::ADDWF => at + instruction_len(&inst),
+            Opcode::MOVWF => {
+                let computed = match solution.before(at) {
+                    Some(computed) => computed,
+                    None => { continue; }
+                };
+                carry_fixup = computed.carry_fixup;
+                let pclatu = match computed.pclatu {
+                    Some(pclatu) => pclatu,
+                    // nothing above 64k to go to anyway
+                    None if disassembly.end() <= 0x1_0000 => 0,
+                    None => { continue; }
+                };
+                let pclath = match computed.pclath {
+                    Some(pclath) => pclath,
+                    None => { continue; }
+                };
+                let page = ((pclatu as u32) << 16) | ((pclath as u32) << 8);
+                match (computed.w, computed.w_base) {
+                    (Some(w), _) => {
+                        tables.push(JumpTable {
+                            at,
+                            kind: TableKind::Jump,
+                            entries: vec![page | w as u32],
+                            values: Vec::new(),
+                        });
+                        continue;
+                    },
+                    (None, Some(base)) => page | base as u32,
+                    (None, None) => { continue; }
+                }
+            },
+            _ => { continue; }
+        };
+
+        // PCL arithmetic doesn't carry into PCLATH, so without a fixup a table ends with its page.
+        let end = if carry_fixup { disassembly.end() } else { (base & !0xff) + 0x100 };
+        if let Some((kind, entries, values)) = scan(disassembly, base, end) {
+            tables.push(JumpTable { at, kind, entries, values });
+        }
+    }
+    tables
+}
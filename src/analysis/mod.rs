@@ -0,0 +1,126 @@
+use crate::{consts, Instruction, Opcode, Operand};
+use crate::analysis::cfg::Cfg;
+use crate::analysis::disasm::{Disassembler, Disassembly};
+use crate::analysis::tblptr::TableReads;
+
+pub mod bsr;
+pub mod callgraph;
+pub mod cfg;
+pub mod constprop;
+pub mod dataflow;
+pub mod diff;
+pub mod disasm;
+pub mod fsr;
+pub mod isr;
+pub mod functions;
+pub mod jumptable;
+pub mod signature;
+pub mod tblptr;
+pub mod xref;
+
+// disassemble, then feed back what table reads and computed gotos reveal until nothing new
+// turns up: TBLPTR targets become data, jump table entries become code.
+pub fn explore(disassembler: &mut Disassembler) -> Disassembly {
+    loop {
+        let disassembly = disassembler.disassemble();
+        let cfg = Cfg::build(&disassembly);
+        let mut changed = false;
+        for range in TableReads::analyze(&cfg).data_ranges(&disassembly) {
+            changed |= disassembler.mark_data(range);
+        }
+        for table in jumptable::find(&cfg, &disassembly) {
+            for entry in table.entries {
+                changed |= disassembler.add_indirect_target(table.at, entry);
+            }
+        }
+        if !changed {
+            return disassembly;
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FileRef {
+    // access-bank and MOVFF operands name a data address outright
+    Absolute(u16),
+    // a = 1 operands are an offset into whichever bank BSR selects
+    Banked(u8),
+}
+
+impl FileRef {
+    pub fn resolve(&self, bsr: u8) -> u16 {
+        match self {
+            FileRef::Absolute(addr) => *addr,
+            FileRef::Banked(file) => ((bsr as u16 & 0x0f) << 8) | *file as u16,
+        }
+    }
+
+    pub fn absolute(&self) -> Option<u16> {
+        match self {
+            FileRef::Absolute(addr) => Some(*addr),
+            FileRef::Banked(_) => None,
+        }
+    }
+}
+
+fn file_ref(operand: &Operand) -> Option<FileRef> {
+    match operand {
+        Operand::File(file, banked) |
+        Operand::RedirectableFile(file, banked, _) => {
+            if *banked {
+                Some(FileRef::Banked(*file))
+            } else {
+                Some(FileRef::Absolute(consts::access_address(*file)))
+            }
+        },
+        Operand::AbsoluteFile(addr) => Some(FileRef::Absolute(*addr)),
+        _ => None
+    }
+}
+
+pub fn file_read(inst: &Instruction) -> Option<FileRef> {
+    match inst.opcode {
+        Opcode::MOVWF |
+        Opcode::CLRF |
+        Opcode::SETF => None,
+        _ => file_ref(&inst.operands[0]),
+    }
+}
+
+pub fn file_written(inst: &Instruction) -> Option<FileRef> {
+    match inst.opcode {
+        Opcode::MOVFF => file_ref(&inst.operands[1]),
+        Opcode::MOVWF |
+        Opcode::CLRF |
+        Opcode::SETF |
+        Opcode::NEGF |
+        Opcode::BSF |
+        Opcode::BCF |
+        Opcode::BTG => file_ref(&inst.operands[0]),
+        _ => {
+            match inst.operands[0] {
+                Operand::RedirectableFile(_, _, true) => file_ref(&inst.operands[0]),
+                _ => None
+            }
+        }
+    }
+}
+
+pub fn writes_w(inst: &Instruction) -> bool {
+    match inst.opcode {
+        Opcode::MOVLW |
+        Opcode::ADDLW |
+        Opcode::SUBLW |
+        Opcode::IORLW |
+        Opcode::ANDLW |
+        Opcode::XORLW |
+        Opcode::RETLW |
+        Opcode::DAW => true,
+        _ => {
+            if let Operand::RedirectableFile(_, _, false) = inst.operands[0] {
+                return true;
+            }
+            file_written(inst) == Some(FileRef::Absolute(consts::SFR_BASE + consts::SFRS::WREG))
+        }
+    }
+}
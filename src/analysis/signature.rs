@@ -0,0 +1,268 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::ops::Range;
+
+use crate::{consts, Opcode, Operand};
+use crate::analysis::cfg::Cfg;
+use crate::analysis::disasm::{instruction_len, Disassembly};
+
+// shorter than this, a pattern matches too much to say anything.
+pub const MIN_SIGNATURE_LEN: usize = 6;
+pub const MAX_SIGNATURE_LEN: usize = 128;
+
+// a routine as a byte pattern. the text form is `name pattern`, the pattern being the bytes in
+// memory order as hex, with `.` for any nibble that differs wherever the routine is linked:
+// call and goto targets, RAM addresses, bank selections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    bytes: Vec<u8>,
+    // set bits must match
+    mask: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+// bits of each instruction word that depend on where things were linked.
+fn relocatable(opcode: Opcode, operands: &[Operand; 2]) -> [u16; 2] {
+    let ram = |addr: u16| addr < consts::SFR_BASE;
+    match (opcode, operands[0], operands[1]) {
+        (Opcode::CALL, _, _) |
+        (Opcode::CALL_FAST, _, _) |
+        (Opcode::GOTO, _, _) => [0x00ff, 0x0fff],
+        (Opcode::RCALL, _, _) => [0x07ff, 0],
+        (Opcode::LFSR, _, _) => [0x000f, 0x00ff],
+        (Opcode::MOVLB, _, _) => [0x000f, 0],
+        (Opcode::MOVFF, Operand::AbsoluteFile(src), Operand::AbsoluteFile(dest)) => {
+            [if ram(src) { 0x0fff } else { 0 }, if ram(dest) { 0x0fff } else { 0 }]
+        },
+        (_, Operand::File(_, true), _) |
+        (_, Operand::RedirectableFile(_, true, _), _) => [0x00ff, 0],
+        (_, Operand::File(file, false), _) |
+        (_, Operand::RedirectableFile(file, false, _), _) if ram(consts::access_address(file)) => [0x00ff, 0],
+        _ => [0, 0]
+    }
+}
+
+impl Signature {
+    // a signature for the code in `range`, which is decoded from its start. `None` if it
+    // doesn't decode or comes out shorter than `MIN_SIGNATURE_LEN`.
+    pub fn from_code(name: &str, disassembly: &Disassembly, range: Range<u32>) -> Option<Signature> {
+        let end = range.end.min(range.start + MAX_SIGNATURE_LEN as u32);
+        let mut bytes = Vec::new();
+        let mut mask = Vec::new();
+        let mut addr = range.start;
+        while addr < end {
+            let inst = match disassembly.decode_at(addr) {
+                Some(inst) => inst,
+                None => { break; }
+            };
+            if let Opcode::Invalid(_, _) = inst.opcode {
+                break;
+            }
+            let len = instruction_len(&inst);
+            if addr + len > end {
+                break;
+            }
+            let masks = relocatable(inst.opcode, &inst.operands);
+            for word in 0..(len / 2) {
+                let word_mask = !masks[word as usize];
+                for byte in 0..2 {
+                    let at = addr + word * 2 + byte;
+                    bytes.push(disassembly.byte(at)?);
+                    // wildcards are whole nibbles, as the text form has them
+                    let bits = (word_mask >> (byte * 8)) as u8;
+                    let high = if bits & 0xf0 == 0xf0 { 0xf0 } else { 0 };
+                    let low = if bits & 0x0f == 0x0f { 0x0f } else { 0 };
+                    mask.push(high | low);
+                }
+            }
+            addr += len;
+        }
+        if bytes.len() < MIN_SIGNATURE_LEN {
+            return None;
+        }
+        for (byte, mask) in bytes.iter_mut().zip(mask.iter()) {
+            *byte &= mask;
+        }
+        Some(Signature { name: name.to_string(), bytes, mask })
+    }
+
+    pub fn parse(line: &str, line_number: usize) -> Result<Signature, ParseError> {
+        let error = |message: &str| ParseError { line: line_number, message: message.to_string() };
+        let mut parts = line.split_whitespace();
+        let name = parts.next().ok_or_else(|| error("missing name"))?;
+        let nibbles: Vec<char> = parts.flat_map(|part| part.chars()).collect();
+        if nibbles.is_empty() {
+            return Err(error("missing pattern"));
+        }
+        // not `is_multiple_of`, which needs a newer compiler than the rest of the crate
+        #[allow(clippy::manual_is_multiple_of)]
+        if nibbles.len() % 2 != 0 {
+            return Err(error("pattern is not a whole number of bytes"));
+        }
+        let mut bytes = Vec::new();
+        let mut mask = Vec::new();
+        for pair in nibbles.chunks(2) {
+            let mut byte = 0;
+            let mut byte_mask = 0;
+            for c in pair.iter() {
+                byte <<= 4;
+                byte_mask <<= 4;
+                if *c != '.' {
+                    byte |= c.to_digit(16).ok_or_else(|| error(&format!("bad pattern character '{}'", c)))? as u8;
+                    byte_mask |= 0x0f;
+                }
+            }
+            bytes.push(byte);
+            mask.push(byte_mask);
+        }
+        Ok(Signature { name: name.to_string(), bytes, mask })
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn matches(&self, disassembly: &Disassembly, addr: u32) -> bool {
+        self.bytes.iter().zip(self.mask.iter()).enumerate().all(|(i, (byte, mask))| {
+            match disassembly.byte(addr + i as u32) {
+                Some(actual) => actual & mask == *byte,
+                None => false
+            }
+        })
+    }
+}
+
+impl Display for Signature {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} ", self.name)?;
+        for (byte, mask) in self.bytes.iter().zip(self.mask.iter()) {
+            for shift in [4u8, 0].iter() {
+                if (mask >> shift) & 0xf == 0 {
+                    write!(f, ".")?;
+                } else {
+                    write!(f, "{:x}", (byte >> shift) & 0xf)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub entry: u32,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Signatures {
+    signatures: Vec<Signature>,
+}
+
+impl Signatures {
+    pub fn new() -> Signatures {
+        Signatures::default()
+    }
+
+    // one signature per line; blank lines and lines starting with `#` are skipped.
+    pub fn parse(text: &str) -> Result<Signatures, ParseError> {
+        let mut signatures = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            signatures.push(Signature::parse(line, i + 1)?);
+        }
+        Ok(Signatures { signatures })
+    }
+
+    pub fn add(&mut self, signature: Signature) {
+        self.signatures.push(signature);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&Signature> {
+        self.signatures.iter()
+    }
+
+    // the longest signature matching at `addr`, the first of them if several are as long.
+    pub fn match_at(&self, disassembly: &Disassembly, addr: u32) -> Option<&Signature> {
+        let mut best: Option<&Signature> = None;
+        for signature in self.signatures.iter() {
+            if signature.matches(disassembly, addr) && best.map(|best| signature.len() > best.len()).unwrap_or(true) {
+                best = Some(signature);
+            }
+        }
+        best
+    }
+
+    // name what functions of `cfg` match.
+    pub fn identify(&self, cfg: &Cfg, disassembly: &Disassembly) -> Vec<Match> {
+        cfg.functions().filter_map(|function| {
+            self.match_at(disassembly, function.entry).map(|signature| Match {
+                entry: function.entry,
+                name: signature.name.clone(),
+            })
+        }).collect()
+    }
+}
+
+impl Display for Signatures {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for signature in self.signatures.iter() {
+            writeln!(f, "{}", signature)?;
+        }
+        Ok(())
+    }
+}
+
+// symbols from `name address` lines, the address in hex with or without `0x`. anything else on
+// a line is ignored, as are lines that don't start that way.
+pub fn parse_symbols(text: &str) -> Vec<(String, u32)> {
+    let mut symbols = Vec::new();
+    for line in text.lines() {
+        let mut parts = line.split_whitespace();
+        if let (Some(name), Some(addr)) = (parts.next(), parts.next()) {
+            let digits = addr.trim_start_matches("0x").trim_start_matches("0X");
+            if let Ok(addr) = u32::from_str_radix(digits, 16) {
+                symbols.push((name.to_string(), addr));
+            }
+        }
+    }
+    symbols
+}
+
+// signatures for each symbol in an image that has them. a routine is taken to run up to the
+// next symbol, or the end of the image.
+pub fn generate(disassembly: &Disassembly, symbols: &[(String, u32)]) -> Signatures {
+    let mut by_addr: BTreeMap<u32, &str> = BTreeMap::new();
+    for (name, addr) in symbols.iter() {
+        if disassembly.byte(*addr).is_some() {
+            by_addr.entry(*addr).or_insert(name);
+        }
+    }
+    let mut signatures = Signatures::new();
+    for (addr, name) in by_addr.iter() {
+        let end = by_addr.range(addr + 1..).next().map(|(next, _)| *next).unwrap_or_else(|| disassembly.end());
+        if let Some(signature) = Signature::from_code(name, disassembly, *addr..end) {
+            signatures.add(signature);
+        }
+    }
+    signatures
+}
@@ -0,0 +1,272 @@
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+use crate::{consts, Instruction, Opcode, Operand};
+use crate::analysis::{file_written, writes_w, FileRef};
+use crate::analysis::cfg::{BasicBlock, Cfg, Edge, EdgeKind};
+use crate::analysis::dataflow::{self, Analysis};
+use crate::analysis::disasm::Disassembly;
+
+const TBLPTRL: u16 = consts::SFR_BASE + consts::SFRS::TBLPTRL;
+const TBLPTRH: u16 = consts::SFR_BASE + consts::SFRS::TBLPTRH;
+const TBLPTRU: u16 = consts::SFR_BASE + consts::SFRS::TBLPTRU;
+
+// without a terminator or neighbouring code to go by, a table is assumed to be at most this long.
+const MAX_TABLE_LEN: u32 = 256;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Step {
+    None,
+    PreIncrement,
+    PostIncrement,
+    PostDecrement,
+}
+
+fn table_op(opcode: Opcode) -> Option<Step> {
+    match opcode {
+        Opcode::TBLRD_S | Opcode::TBLWT_S => Some(Step::None),
+        Opcode::TBLRD_S_I | Opcode::TBLWT_S_I => Some(Step::PostIncrement),
+        Opcode::TBLRD_S_D | Opcode::TBLWT_S_D => Some(Step::PostDecrement),
+        Opcode::TBLRD_I_S | Opcode::TBLWT_I_S => Some(Step::PreIncrement),
+        _ => None
+    }
+}
+
+fn tblptr_byte(addr: u16) -> Option<usize> {
+    match addr {
+        TBLPTRL => Some(0),
+        TBLPTRH => Some(1),
+        TBLPTRU => Some(2),
+        _ => None
+    }
+}
+
+fn writes_tblptr(inst: &Instruction) -> bool {
+    match table_op(inst.opcode) {
+        Some(Step::None) => false,
+        Some(_) => true,
+        None => {
+            match file_written(inst) {
+                Some(FileRef::Absolute(addr)) => tblptr_byte(addr).is_some(),
+                _ => false
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct TblptrState {
+    // TBLPTRL, TBLPTRH, TBLPTRU
+    bytes: [Option<u8>; 3],
+    // the pointer as it was last loaded, before any post-increment or decrement stepped it. a
+    // `tblrd*+` loop joins TBLPTRL l and l+1 at its head, but the value on loop entry survives.
+    base: Option<u32>,
+    w: Option<u8>,
+}
+
+impl TblptrState {
+    fn pointer(&self) -> Option<u32> {
+        match self.bytes {
+            [Some(l), Some(h), Some(u)] => Some((((u & 0x3f) as u32) << 16) | ((h as u32) << 8) | l as u32),
+            _ => None
+        }
+    }
+
+    fn step(&mut self, delta: i32) {
+        match self.pointer() {
+            Some(ptr) => {
+                let ptr = (ptr as i32 + delta) as u32 & 0x3f_ffff;
+                self.bytes = [Some(ptr as u8), Some((ptr >> 8) as u8), Some((ptr >> 16) as u8)];
+            },
+            None => {
+                // without the whole pointer, only a step that can't carry or borrow is followed
+                self.bytes[0] = match (self.bytes[0], delta) {
+                    (Some(l), 1) if l != 0xff => Some(l + 1),
+                    (Some(l), -1) if l != 0x00 => Some(l - 1),
+                    _ => {
+                        self.bytes[1] = None;
+                        self.bytes[2] = None;
+                        None
+                    }
+                };
+            }
+        }
+    }
+}
+
+struct TblptrAnalysis {
+    clobbers: BTreeSet<u32>,
+}
+
+impl Analysis for TblptrAnalysis {
+    type State = TblptrState;
+
+    fn entry_state(&self, _entry: u32) -> TblptrState {
+        TblptrState { bytes: [None; 3], base: None, w: None }
+    }
+
+    fn transfer(&self, _addr: u32, inst: &Instruction, state: &mut TblptrState) {
+        match table_op(inst.opcode) {
+            Some(Step::PreIncrement) |
+            Some(Step::PostIncrement) => state.step(1),
+            Some(Step::PostDecrement) => state.step(-1),
+            Some(Step::None) => {},
+            None => {
+                if let Some(FileRef::Absolute(dest)) = file_written(inst) {
+                    if let Some(byte) = tblptr_byte(dest) {
+                        state.bytes[byte] = match inst.opcode {
+                            Opcode::MOVWF => state.w,
+                            Opcode::CLRF => Some(0),
+                            Opcode::SETF => Some(0xff),
+                            _ => None
+                        };
+                        state.base = state.pointer();
+                    }
+                }
+            }
+        }
+
+        if writes_w(inst) {
+            state.w = match (inst.opcode, inst.operands[0]) {
+                (Opcode::MOVLW, Operand::ImmediateU8(k)) => Some(k),
+                _ => None
+            };
+        }
+    }
+
+    fn join(&self, into: &mut TblptrState, other: &TblptrState) {
+        for i in 0..3 {
+            if into.bytes[i] != other.bytes[i] {
+                into.bytes[i] = None;
+            }
+        }
+        if into.base != other.base {
+            into.base = None;
+        }
+        if into.w != other.w {
+            into.w = None;
+        }
+    }
+
+    fn edge(&self, block: &BasicBlock, edge: &Edge, state: &TblptrState) -> TblptrState {
+        if edge.kind != EdgeKind::CallReturn {
+            return *state;
+        }
+        let preserved = match block.call {
+            Some(target) => !self.clobbers.contains(&target),
+            None => false
+        };
+        if preserved {
+            TblptrState { w: None, ..*state }
+        } else {
+            TblptrState { bytes: [None; 3], base: None, w: None }
+        }
+    }
+
+    fn call(&self, _target: u32, state: &TblptrState) -> Option<TblptrState> {
+        Some(TblptrState { w: None, ..*state })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TableRead {
+    // the TBLRD instruction
+    pub at: u32,
+    // the program memory address it reads
+    pub address: u32,
+}
+
+pub struct TableReads {
+    reads: Vec<TableRead>,
+}
+
+impl TableReads {
+    pub fn analyze(cfg: &Cfg) -> TableReads {
+        let analysis = TblptrAnalysis {
+            clobbers: dataflow::functions_executing(cfg, writes_tblptr),
+        };
+        let solution = dataflow::solve(cfg, &analysis);
+
+        let mut reads = Vec::new();
+        for block in cfg.blocks() {
+            for (addr, inst) in block.instructions.iter() {
+                let read = matches!(inst.opcode,
+                    Opcode::TBLRD_S | Opcode::TBLRD_S_I | Opcode::TBLRD_S_D | Opcode::TBLRD_I_S);
+                if !read {
+                    continue;
+                }
+                let mut state = match solution.before(*addr) {
+                    Some(state) => *state,
+                    None => { continue; }
+                };
+                if let Some(Step::PreIncrement) = table_op(inst.opcode) {
+                    state.step(1);
+                }
+                // a read whose pointer was stepped around a loop is taken to start the table the
+                // loop was entered with
+                if let Some(address) = state.pointer().or(state.base) {
+                    reads.push(TableRead { at: *addr, address });
+                }
+            }
+        }
+
+        TableReads { reads }
+    }
+
+    pub fn reads(&self) -> &[TableRead] {
+        &self.reads
+    }
+
+    // reads from program memory that also decoded as reachable code. either the pointer is wrong
+    // or the code reads its own instructions, and neither is for `data_ranges` to decide.
+    pub fn conflicts(&self, disassembly: &Disassembly) -> Vec<TableRead> {
+        self.reads.iter().filter(|read| disassembly.is_code(read.address)).cloned().collect()
+    }
+
+    // the program memory each table read appears to be reading from. NUL-terminated strings end
+    // at their terminator, anything else runs until the next code or table, or `MAX_TABLE_LEN`.
+    // nothing that decoded as reachable code is ever included; see `conflicts`. `analysis::explore`
+    // marks these as data and disassembles again until nothing changes.
+    pub fn data_ranges(&self, disassembly: &Disassembly) -> Vec<Range<u32>> {
+        let bases: BTreeSet<u32> = self.reads.iter().map(|read| read.address).collect();
+        let mut ranges = Vec::new();
+        for base in bases.iter() {
+            if disassembly.byte(*base).is_none() || disassembly.is_code(*base) {
+                continue;
+            }
+            let string_end = {
+                let mut cursor = *base;
+                loop {
+                    if disassembly.is_code(cursor) {
+                        break None;
+                    }
+                    match disassembly.byte(cursor) {
+                        Some(0) if cursor > *base + 1 => break Some(cursor + 1),
+                        Some(b) if (0x20..0x7f).contains(&b) || b == b'\r' || b == b'\n' || b == b'\t' => {
+                            cursor += 1;
+                        },
+                        _ => break None
+                    }
+                }
+            };
+            let end = match string_end {
+                Some(string_end) => string_end,
+                None => {
+                    let next_base = bases.range(base + 1..).next().cloned().unwrap_or(u32::MAX);
+                    let mut cursor = base + 1;
+                    while cursor < base + MAX_TABLE_LEN && cursor < next_base &&
+                            disassembly.byte(cursor).is_some() && !disassembly.is_code(cursor) {
+                        cursor += 1;
+                    }
+                    cursor
+                }
+            };
+            // program memory is word-addressed for instructions, so round out to whole words
+            let range = (base & !1)..((end + 1) & !1);
+            if range.start < range.end {
+                ranges.push(range);
+            }
+        }
+        ranges
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+
+use crate::consts;
+use crate::analysis::{file_read, file_written, FileRef};
+use crate::analysis::bsr::BsrTracking;
+use crate::analysis::disasm::{flow, Disassembly, Flow};
+use crate::analysis::fsr::{is_indirect, FsrTracking};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum XrefKind {
+    Call,
+    Jump,
+    Branch,
+    // a computed goto, through PCL
+    Computed,
+    Read,
+    Write,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Xref {
+    // the referencing instruction
+    pub from: u32,
+    pub kind: XrefKind,
+}
+
+pub struct Xrefs {
+    code: BTreeMap<u32, Vec<Xref>>,
+    data: BTreeMap<u16, Vec<Xref>>,
+    // banked operands no BSR value could be found for
+    unresolved: Vec<(u32, FileRef)>,
+}
+
+impl Xrefs {
+    // `bsr` and `fsr`, when provided, resolve banked and indirect accesses; without them those
+    // are recorded as unresolved or left at the indirect register respectively.
+    pub fn build(disassembly: &Disassembly, bsr: Option<&BsrTracking>, fsr: Option<&FsrTracking>) -> Xrefs {
+        let mut xrefs = Xrefs {
+            code: BTreeMap::new(),
+            data: BTreeMap::new(),
+            unresolved: Vec::new(),
+        };
+
+        for (addr, inst) in disassembly.instructions() {
+            let code = |kind| Xref { from: addr, kind };
+            match flow(addr, inst) {
+                Flow::Call(target) => xrefs.add_code(target, code(XrefKind::Call)),
+                Flow::Jump(target) => xrefs.add_code(target, code(XrefKind::Jump)),
+                Flow::Branch(target) => xrefs.add_code(target, code(XrefKind::Branch)),
+                Flow::IndirectJump => {
+                    for target in disassembly.indirect_targets(addr) {
+                        xrefs.add_code(target, code(XrefKind::Computed));
+                    }
+                },
+                _ => {}
+            }
+
+            // distinct files touched, in the order they are accessed, as FSR tracking sees them
+            let mut files: Vec<(FileRef, bool, bool)> = Vec::new();
+            if let Some(file) = file_read(inst) {
+                files.push((file, true, false));
+            }
+            if let Some(file) = file_written(inst) {
+                match files.iter_mut().find(|(seen, _, _)| *seen == file) {
+                    Some(seen) => { seen.2 = true; },
+                    None => { files.push((file, false, true)); }
+                }
+            }
+
+            let mut indirect = fsr.map(|fsr| fsr.accesses(addr)).unwrap_or(&[]).iter();
+            for (file, read, write) in files.iter() {
+                let addresses = match (file, bsr) {
+                    (FileRef::Absolute(address), _) => vec![*address],
+                    (FileRef::Banked(_), Some(bsr)) => bsr.resolve(addr, *file),
+                    (FileRef::Banked(_), None) => Vec::new(),
+                };
+                if addresses.is_empty() {
+                    xrefs.unresolved.push((addr, *file));
+                }
+                for address in addresses.iter() {
+                    xrefs.add_data(*address, addr, *read, *write);
+                }
+                if let FileRef::Absolute(address) = file {
+                    if !is_indirect(*address) {
+                        continue;
+                    }
+                    if let Some(target) = indirect.next().and_then(|access| access.address) {
+                        xrefs.add_data(target, addr, *read, *write);
+                    }
+                }
+            }
+        }
+
+        xrefs
+    }
+
+    fn add_code(&mut self, target: u32, xref: Xref) {
+        self.code.entry(target).or_default().push(xref);
+    }
+
+    fn add_data(&mut self, target: u16, from: u32, read: bool, write: bool) {
+        let refs = self.data.entry(target).or_default();
+        if read {
+            refs.push(Xref { from, kind: XrefKind::Read });
+        }
+        if write {
+            refs.push(Xref { from, kind: XrefKind::Write });
+        }
+    }
+
+    pub fn to_code(&self, addr: u32) -> &[Xref] {
+        self.code.get(&addr).map(|refs| refs.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn to_data(&self, addr: u16) -> &[Xref] {
+        self.data.get(&addr).map(|refs| refs.as_slice()).unwrap_or(&[])
+    }
+
+    // references to the SFR named `name`, as `consts` names it.
+    pub fn to_sfr(&self, name: &str) -> &[Xref] {
+        match consts::file_named(name) {
+            Some(addr) => self.to_data(addr),
+            None => &[]
+        }
+    }
+
+    pub fn callers(&self, addr: u32) -> Vec<u32> {
+        self.to_code(addr).iter().filter(|xref| xref.kind == XrefKind::Call).map(|xref| xref.from).collect()
+    }
+
+    pub fn readers(&self, addr: u16) -> Vec<u32> {
+        self.to_data(addr).iter().filter(|xref| xref.kind == XrefKind::Read).map(|xref| xref.from).collect()
+    }
+
+    pub fn writers(&self, addr: u16) -> Vec<u32> {
+        self.to_data(addr).iter().filter(|xref| xref.kind == XrefKind::Write).map(|xref| xref.from).collect()
+    }
+
+    pub fn code_targets(&self) -> impl Iterator<Item=(u32, &[Xref])> {
+        self.code.iter().map(|(addr, refs)| (*addr, refs.as_slice()))
+    }
+
+    pub fn data_targets(&self) -> impl Iterator<Item=(u16, &[Xref])> {
+        self.data.iter().map(|(addr, refs)| (*addr, refs.as_slice()))
+    }
+
+    pub fn unresolved(&self) -> &[(u32, FileRef)] {
+        &self.unresolved
+    }
+}
@@ -2,11 +2,42 @@
 pub mod SFRS {
     pub const EECON2: u16 = 0xfa7 - 0xf60;
     pub const EECON1: u16 = 0xfa6 - 0xf60;
+    pub const PORTA: u16 = 0xf80 - 0xf60;
+    pub const PORTB: u16 = 0xf81 - 0xf60;
+    pub const PORTC: u16 = 0xf82 - 0xf60;
+    pub const PORTD: u16 = 0xf83 - 0xf60;
+    pub const PORTE: u16 = 0xf84 - 0xf60;
+    pub const LATA: u16 = 0xf89 - 0xf60;
+    pub const LATB: u16 = 0xf8a - 0xf60;
+    pub const LATC: u16 = 0xf8b - 0xf60;
+    pub const LATD: u16 = 0xf8c - 0xf60;
+    pub const LATE: u16 = 0xf8d - 0xf60;
+    pub const TRISA: u16 = 0xf92 - 0xf60;
+    pub const TRISB: u16 = 0xf93 - 0xf60;
+    pub const TRISC: u16 = 0xf94 - 0xf60;
+    pub const TRISD: u16 = 0xf95 - 0xf60;
+    pub const TRISE: u16 = 0xf96 - 0xf60;
+    pub const PIE1: u16 = 0xf9d - 0xf60;
+    pub const PIR1: u16 = 0xf9e - 0xf60;
+    pub const IPR1: u16 = 0xf9f - 0xf60;
+    pub const PIE2: u16 = 0xfa0 - 0xf60;
+    pub const PIR2: u16 = 0xfa1 - 0xf60;
+    pub const IPR2: u16 = 0xfa2 - 0xf60;
+    pub const EEDATA: u16 = 0xfa8 - 0xf60;
+    pub const EEADR: u16 = 0xfa9 - 0xf60;
+    pub const RCSTA: u16 = 0xfab - 0xf60;
+    pub const TXSTA: u16 = 0xfac - 0xf60;
+    pub const TXREG: u16 = 0xfad - 0xf60;
+    pub const RCREG: u16 = 0xfae - 0xf60;
+    pub const SPBRG: u16 = 0xfaf - 0xf60;
+    pub const SPBRGH: u16 = 0xfb0 - 0xf60;
+    pub const BAUDCON: u16 = 0xfb8 - 0xf60;
 /*
  *
  *  THE UNIMPLEMENTED VOID
  *
  */
+    pub const RCON: u16 = 0xfd0 - 0xf60;
     pub const T0CON: u16 = 0xfd5 - 0xf60;
     pub const TMR0L: u16 = 0xfd6 - 0xf60;
     pub const TMR0H: u16 = 0xfd7 - 0xf60;
@@ -52,6 +83,30 @@ pub mod SFRS {
     pub const TOSU: u16 = 0xfff - 0xf60;
 }
 
+pub const SFR_BASE: u16 = 0xf60;
+
+// where the access bank splits between bank 0 GPRs and SFRs on the parts `access_address` follows.
+pub const ACCESS_SPLIT: u8 = 0x80;
+
+// access-bank file addresses below 0x80 are bank 0 GPRs, the rest are the SFRs at the top of bank 15.
+pub fn access_address(file: u8) -> u16 {
+    split_access_address(file, ACCESS_SPLIT)
+}
+
+// `access_address` on a part whose access bank splits at `split`.
+pub fn split_access_address(file: u8, split: u8) -> u16 {
+    if file < split {
+        file as u16
+    } else {
+        (file as u16) | 0xf00u16
+    }
+}
+
+// the SFR with name `name`, the reverse of `named_file`.
+pub fn file_named(name: &str) -> Option<u16> {
+    (SFR_BASE..=0xfff).find(|file| named_file(*file) == name)
+}
+
 pub fn named_file(file: u16) -> &'static str {
     match file {
         0x0 => "0x0",
@@ -4022,55 +4077,55 @@ pub fn named_file(file: u16) -> &'static str {
         0xf7d => "0xf7d",
         0xf7e => "0xf7e",
         0xf7f => "0xf7f",
-        0xf80 => "0xf80",
-        0xf81 => "0xf81",
-        0xf82 => "0xf82",
-        0xf83 => "0xf83",
-        0xf84 => "0xf84",
+        0xf80 => "PORTA",
+        0xf81 => "PORTB",
+        0xf82 => "PORTC",
+        0xf83 => "PORTD",
+        0xf84 => "PORTE",
         0xf85 => "0xf85",
         0xf86 => "0xf86",
         0xf87 => "0xf87",
         0xf88 => "0xf88",
-        0xf89 => "0xf89",
-        0xf8a => "0xf8a",
-        0xf8b => "0xf8b",
-        0xf8c => "0xf8c",
-        0xf8d => "0xf8d",
+        0xf89 => "LATA",
+        0xf8a => "LATB",
+        0xf8b => "LATC",
+        0xf8c => "LATD",
+        0xf8d => "LATE",
         0xf8e => "0xf8e",
         0xf8f => "0xf8f",
         0xf90 => "0xf90",
         0xf91 => "0xf91",
-        0xf92 => "0xf92",
-        0xf93 => "0xf93",
-        0xf94 => "0xf94",
-        0xf95 => "0xf95",
-        0xf96 => "0xf96",
+        0xf92 => "TRISA",
+        0xf93 => "TRISB",
+        0xf94 => "TRISC",
+        0xf95 => "TRISD",
+        0xf96 => "TRISE",
         0xf97 => "0xf97",
         0xf98 => "0xf98",
         0xf99 => "0xf99",
         0xf9a => "0xf9a",
         0xf9b => "0xf9b",
         0xf9c => "0xf9c",
-        0xf9d => "0xf9d",
-        0xf9e => "0xf9e",
-        0xf9f => "0xf9f",
-        0xfa0 => "0xfa0",
-        0xfa1 => "0xfa1",
-        0xfa2 => "0xfa2",
+        0xf9d => "PIE1",
+        0xf9e => "PIR1",
+        0xf9f => "IPR1",
+        0xfa0 => "PIE2",
+        0xfa1 => "PIR2",
+        0xfa2 => "IPR2",
         0xfa3 => "0xfa3",
         0xfa4 => "0xfa4",
         0xfa5 => "0xfa5",
-        0xfa6 => "0xfa6",
-        0xfa7 => "0xfa7",
-        0xfa8 => "0xfa8",
-        0xfa9 => "0xfa9",
+        0xfa6 => "EECON1",
+        0xfa7 => "EECON2",
+        0xfa8 => "EEDATA",
+        0xfa9 => "EEADR",
         0xfaa => "0xfaa",
-        0xfab => "0xfab",
-        0xfac => "0xfac",
-        0xfad => "0xfad",
-        0xfae => "0xfae",
-        0xfaf => "0xfaf",
-        0xfb0 => "0xfb0",
+        0xfab => "RCSTA",
+        0xfac => "TXSTA",
+        0xfad => "TXREG",
+        0xfae => "RCREG",
+        0xfaf => "SPBRG",
+        0xfb0 => "SPBRGH",
         0xfb1 => "0xfb1",
         0xfb2 => "0xfb2",
         0xfb3 => "0xfb3",
@@ -4078,7 +4133,7 @@ pub fn named_file(file: u16) -> &'static str {
         0xfb5 => "0xfb5",
         0xfb6 => "0xfb6",
         0xfb7 => "0xfb7",
-        0xfb8 => "0xfb8",
+        0xfb8 => "BAUDCON",
         0xfb9 => "0xfb9",
         0xfba => "0xfba",
         0xfbb => "0xfbb",
@@ -4102,7 +4157,7 @@ pub fn named_file(file: u16) -> &'static str {
         0xfcd => "0xfcd",
         0xfce => "0xfce",
         0xfcf => "0xfcf",
-        0xfd0 => "0xfd0",
+        0xfd0 => "RCON",
         0xfd1 => "0xfd1",
         0xfd2 => "0xfd2",
         0xfd3 => "0xfd3",
@@ -0,0 +1,1150 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{consts, Instruction, Opcode, Operand};
+use crate::analysis::FileRef;
+use crate::analysis::bsr::BsrTracking;
+use crate::analysis::cfg::{BasicBlock, Cfg, EdgeKind};
+use crate::analysis::disasm::{flow, Flow, HIGH_PRIORITY_VECTOR, LOW_PRIORITY_VECTOR, RESET_VECTOR};
+use crate::analysis::fsr::{fsr_register, FsrRegister, IndirectMode};
+use crate::ir::{self, BinOp, Expr, Flag, Reg, Stmt};
+
+// pseudo-C for the functions of a cfg. it's meant to be read, not compiled: W, STATUS and the
+// SFRs appear as variables, RAM is `ram[addr]`, and `WORD(x)` is the 16-bit little-endian value
+// starting at x.
+
+pub fn function_name(entry: u32) -> String {
+    match entry {
+        RESET_VECTOR => "reset".to_string(),
+        HIGH_PRIORITY_VECTOR => "isr_high".to_string(),
+        LOW_PRIORITY_VECTOR => "isr_low".to_string(),
+        _ => format!("func_{:04x}", entry),
+    }
+}
+
+fn label(addr: u32) -> String {
+    format!("L_{:04x}", addr)
+}
+
+fn data_name(addr: u16) -> String {
+    if let Some((fsr, FsrRegister::Indirect(mode))) = fsr_register(addr) {
+        return match mode {
+            IndirectMode::Indf => format!("*FSR{}", fsr),
+            IndirectMode::PostInc => format!("*FSR{}++", fsr),
+            IndirectMode::PostDec => format!("*FSR{}--", fsr),
+            IndirectMode::PreInc => format!("*++FSR{}", fsr),
+            IndirectMode::PlusW => format!("FSR{}[W]", fsr),
+        };
+    }
+    let name = consts::named_file(addr);
+    if !name.starts_with("0x") {
+        name.to_string()
+    } else if addr >= consts::SFR_BASE {
+        format!("SFR(0x{:03x})", addr)
+    } else {
+        format!("ram[0x{:03x}]", addr)
+    }
+}
+
+fn hex(k: u32) -> String {
+    if k <= 0xff {
+        format!("0x{:02x}", k)
+    } else if k <= 0xfff {
+        format!("0x{:03x}", k)
+    } else {
+        format!("0x{:x}", k)
+    }
+}
+
+fn bin(op: BinOp, left: Expr, right: Expr) -> Expr {
+    Expr::Binary(op, Box::new(left), Box::new(right))
+}
+
+// `x` and `n`, if `e` is bit `n` of `x`.
+fn bit_of(e: &Expr) -> Option<(&Expr, u32)> {
+    match e {
+        Expr::Binary(BinOp::And, shifted, one) if **one == Expr::Const(1) => match &**shifted {
+            Expr::Binary(BinOp::Shr, x, n) => match **n {
+                Expr::Const(n) => Some((&**x, n)),
+                _ => None
+            },
+            _ => None
+        },
+        _ => None
+    }
+}
+
+// the name of a 16-bit pair `e` reads as a whole: an FSR, or PROD.
+fn pair_name(e: &Expr) -> Option<String> {
+    let (high, low) = match e {
+        Expr::Binary(BinOp::Or, shifted, low) => match &**shifted {
+            Expr::Binary(BinOp::Shl, high, eight) if **eight == Expr::Const(8) => (&**high, &**low),
+            _ => { return None; }
+        },
+        _ => { return None; }
+    };
+    match (high, low) {
+        (Expr::Reg(Reg::ProdH), Expr::Reg(Reg::ProdL)) => Some("PROD".to_string()),
+        (Expr::Load(high), Expr::Load(low)) => match (&**high, &**low) {
+            (Expr::Const(high), Expr::Const(low)) => match (fsr_register(*high as u16), fsr_register(*low as u16)) {
+                (Some((h, FsrRegister::High)), Some((l, FsrRegister::Low))) if h == l => Some(format!("FSR{}", l)),
+                _ => None
+            },
+            _ => None
+        },
+        _ => None
+    }
+}
+
+// a 16-bit pair set a byte at a time, as `ir` sets FSRs and PROD: the pair, the locations of its
+// halves, and the value.
+fn pair(stmts: &[Stmt]) -> Option<(Expr, Vec<Expr>, Expr)> {
+    let value = |low: &Expr, high: &Expr| -> Option<Expr> {
+        match (low, high) {
+            (Expr::Binary(BinOp::And, value, mask), Expr::Binary(BinOp::And, shifted, _)) if **mask == Expr::Const(0xff) => match &**shifted {
+                Expr::Binary(BinOp::Shr, high, eight) if high == value && **eight == Expr::Const(8) => Some((**value).clone()),
+                _ => None
+            },
+            _ => None
+        }
+    };
+    let (halves, value) = match stmts {
+        [Stmt::SetReg(Reg::ProdL, low), Stmt::SetReg(Reg::ProdH, high), ..] => {
+            (vec![Expr::Reg(Reg::ProdH), Expr::Reg(Reg::ProdL)], value(low, high)?)
+        },
+        [Stmt::Store(low_addr @ Expr::Const(_), low), Stmt::Store(high_addr @ Expr::Const(_), high), ..] => {
+            let halves = vec![Expr::Load(Box::new(high_addr.clone())), Expr::Load(Box::new(low_addr.clone()))];
+            (halves, value(low, high)?)
+        },
+        _ => { return None; }
+    };
+    let whole = bin(BinOp::Or, bin(BinOp::Shl, halves[0].clone(), Expr::Const(8)), halves[1].clone());
+    pair_name(&whole)?;
+    Some((whole, halves, value))
+}
+
+// whether `e` reads any of `locations`, or with `memory`, any data memory at all.
+fn reads(e: &Expr, locations: &[Expr], memory: bool) -> bool {
+    if locations.contains(e) || (memory && matches!(e, Expr::Load(_))) {
+        return true;
+    }
+    match e {
+        Expr::Load(a) | Expr::ProgramLoad(a) | Expr::Not(a) => reads(a, locations, memory),
+        Expr::Binary(_, a, b) => reads(a, locations, memory) || reads(b, locations, memory),
+        Expr::Select(a, b, c) => reads(a, locations, memory) || reads(b, locations, memory) || reads(c, locations, memory),
+        _ => false
+    }
+}
+
+// `e` with each flag replaced by what set it, where that is known.
+fn with_flags(e: &Expr, flags: &[Option<Expr>; 5]) -> Expr {
+    let go = |e: &Expr| Box::new(with_flags(e, flags));
+    match e {
+        Expr::Flag(flag) => flags[flag.bit() as usize].clone().unwrap_or_else(|| e.clone()),
+        Expr::Load(a) => Expr::Load(go(a)),
+        Expr::ProgramLoad(a) => Expr::ProgramLoad(go(a)),
+        Expr::Not(a) => Expr::Not(go(a)),
+        Expr::Binary(op, a, b) => Expr::Binary(*op, go(a), go(b)),
+        Expr::Select(a, b, c) => Expr::Select(go(a), go(b), go(c)),
+        _ => e.clone()
+    }
+}
+
+// `e` the way it would be written in C: byte masks dropped where the arithmetic makes them
+// obvious, and adding a complement written as the subtraction it is.
+fn simplify(e: Expr) -> Expr {
+    use self::BinOp::*;
+    let (op, a, b) = match e {
+        Expr::Binary(op, a, b) => (op, simplify(*a), simplify(*b)),
+        Expr::Load(a) => { return Expr::Load(Box::new(simplify(*a))); },
+        Expr::ProgramLoad(a) => { return Expr::ProgramLoad(Box::new(simplify(*a))); },
+        Expr::Not(a) => { return Expr::Not(Box::new(simplify(*a))); },
+        Expr::Select(a, b, c) => { return Expr::Select(Box::new(simplify(*a)), Box::new(simplify(*b)), Box::new(simplify(*c))); },
+        e => { return e; }
+    };
+    // `x + ~y`, as `x` and `y`
+    let complement_sum = |e: &Expr| -> Option<(Expr, Expr)> {
+        match e {
+            Expr::Binary(Add, x, inverted) => match &**inverted {
+                Expr::Binary(Xor, y, mask) if **mask == Expr::Const(0xff) => Some(((**x).clone(), (**y).clone())),
+                _ => None
+            },
+            _ => None
+        }
+    };
+    match (op, &a, &b) {
+        (Add, _, Expr::Const(0)) => a,
+        (Add, sum, Expr::Const(1)) if complement_sum(sum).is_some() => {
+            let (x, y) = complement_sum(sum).unwrap();
+            bin(Sub, x, y)
+        },
+        (Add, sum, Expr::Flag(Flag::C)) if complement_sum(sum).is_some() => {
+            let (x, y) = complement_sum(sum).unwrap();
+            bin(Sub, bin(Sub, x, y), bin(Eq, Expr::Flag(Flag::C), Expr::Const(0)))
+        },
+        (And, Expr::Binary(Add, x, k), Expr::Const(0xff)) if matches!(**k, Expr::Const(0x80..=0xff)) => {
+            let k = if let Expr::Const(k) = **k { k } else { 0 };
+            bin(Sub, (**x).clone(), Expr::Const(0x100 - k))
+        },
+        (And, Expr::Binary(Add, _, _), Expr::Const(0xff)) |
+        (And, Expr::Binary(Sub, _, _), Expr::Const(0xff)) |
+        (And, Expr::Binary(Or, _, _), Expr::Const(0xff)) |
+        (And, Expr::Binary(Xor, _, _), Expr::Const(0xff)) |
+        (And, Expr::Binary(Mul, _, _), Expr::Const(0xff)) |
+        (And, Expr::Binary(Add, _, _), Expr::Const(0xfff)) |
+        (And, Expr::Binary(Sub, _, _), Expr::Const(0xfff)) |
+        (And, Expr::Binary(Add, _, _), Expr::Const(0x3f_ffff)) |
+        (And, Expr::Binary(Sub, _, _), Expr::Const(0x3f_ffff)) => a,
+        (Eq, Expr::Binary(Xor, x, y), Expr::Const(0)) |
+        (Eq, Expr::Binary(Sub, x, y), Expr::Const(0)) => bin(Eq, (**x).clone(), (**y).clone()),
+        (Ne, Expr::Binary(Xor, x, y), Expr::Const(0)) |
+        (Ne, Expr::Binary(Sub, x, y), Expr::Const(0)) => bin(Ne, (**x).clone(), (**y).clone()),
+        _ => bin(op, a, b)
+    }
+}
+
+// a condition, and its negation, spelled out so neither needs a `!(...)` around it.
+#[derive(Debug, Clone)]
+struct Cond {
+    holds: String,
+    fails: String,
+    // whether testing it changes something too, as `--x == 0` does
+    effect: bool,
+}
+
+impl Cond {
+    fn new(holds: String, fails: String) -> Cond {
+        Cond { holds, fails, effect: false }
+    }
+
+    fn invert(self) -> Cond {
+        Cond { holds: self.fails, fails: self.holds, effect: self.effect }
+    }
+}
+
+// what the flags and W hold in terms of the code that set them, as far as the current block knows.
+#[derive(Debug, Clone, Default)]
+struct BlockState {
+    // by `Flag::bit`
+    flags: [Option<Expr>; 5],
+    // a constant, or a file W was loaded from
+    w: Option<Expr>,
+}
+
+// an instruction's temporaries: what each was bound to, and where it was stored once it has been.
+#[derive(Debug, Default)]
+struct Temps {
+    values: BTreeMap<u8, Expr>,
+    homes: BTreeMap<u8, Expr>,
+}
+
+// a temporary to render as the assignment that produced it, folded into a condition.
+type Folded = Option<(u8, String)>;
+
+// how control leaves a block.
+#[derive(Debug, Clone)]
+enum Exit {
+    Fall(u32),
+    Jump(u32),
+    // `cond` holds when `taken` is where control goes
+    Branch { cond: Cond, taken: u32, fall: u32 },
+    Stop,
+}
+
+pub struct Decompiler<'a> {
+    cfg: &'a Cfg,
+    bsr: BsrTracking,
+}
+
+impl<'a> Decompiler<'a> {
+    pub fn new(cfg: &'a Cfg) -> Decompiler<'a> {
+        Decompiler {
+            cfg,
+            bsr: BsrTracking::analyze(cfg),
+        }
+    }
+
+    // the single data address `operand` refers to at `addr`, if there is one.
+    fn address(&self, addr: u32, operand: &Operand) -> Option<u16> {
+        let file = match operand {
+            Operand::File(file, true) |
+            Operand::RedirectableFile(file, true, _) => FileRef::Banked(*file),
+            Operand::File(file, false) |
+            Operand::RedirectableFile(file, false, _) => FileRef::Absolute(consts::access_address(*file)),
+            Operand::AbsoluteFile(file) => FileRef::Absolute(*file),
+            _ => { return None; }
+        };
+        let resolved = self.bsr.resolve(addr, file);
+        if resolved.len() == 1 { Some(resolved[0]) } else { None }
+    }
+
+    fn call(&self, target: u32) -> String {
+        format!("{}();", function_name(target))
+    }
+
+    // `e`, a banked address as `ir` computes it, as the one address BSR makes it at `addr`.
+    fn banked(&self, addr: u32, e: Expr) -> Expr {
+        if let Expr::Binary(BinOp::Or, bank, file) = &e {
+            if let (Expr::Binary(BinOp::Shl, bsr, _), Expr::Const(file)) = (&**bank, &**file) {
+                if let Expr::Binary(BinOp::And, reg, _) = &**bsr {
+                    if **reg == Expr::Reg(Reg::Bsr) {
+                        let resolved = self.bsr.resolve(addr, FileRef::Banked(*file as u8));
+                        if resolved.len() == 1 {
+                            return Expr::Const(resolved[0] as u32);
+                        }
+                    }
+                }
+            }
+        }
+        e
+    }
+
+    // `e` without temporaries, for a statement: a temporary already stored somewhere reads from
+    // there, except `keep`, and W is what it was known to hold before the instruction.
+    fn resolve(&self, addr: u32, e: &Expr, temps: &Temps, w: Option<&Expr>, keep: Option<u8>) -> Expr {
+        let go = |e: &Expr| Box::new(self.resolve(addr, e, temps, w, keep));
+        match e {
+            Expr::Temp(t) if Some(*t) == keep => e.clone(),
+            Expr::Temp(t) => match temps.homes.get(t) {
+                Some(home) => home.clone(),
+                None => temps.values.get(t).map(|value| self.resolve(addr, value, temps, w, keep)).unwrap_or_else(|| e.clone()),
+            },
+            Expr::Reg(Reg::W) => w.cloned().unwrap_or_else(|| e.clone()),
+            Expr::Load(a) => Expr::Load(go(a)),
+            Expr::ProgramLoad(a) => Expr::ProgramLoad(go(a)),
+            Expr::Not(a) => Expr::Not(go(a)),
+            Expr::Binary(op, a, b) => self.banked(addr, Expr::Binary(*op, go(a), go(b))),
+            Expr::Select(a, b, c) => Expr::Select(go(a), go(b), go(c)),
+            _ => e.clone()
+        }
+    }
+
+    // `e` without temporaries, for a flag: in terms of what the instruction read where that
+    // still holds afterwards, else of where its result went, else not at all.
+    fn settled(&self, addr: u32, e: &Expr, temps: &Temps, w: Option<&Expr>, written: &[Expr]) -> Option<Expr> {
+        let go = |e: &Expr| self.settled(addr, e, temps, w, written).map(Box::new);
+        let e = match e {
+            Expr::Temp(t) => {
+                return temps.values.get(t).and_then(|value| self.settled(addr, value, temps, w, written))
+                    .or_else(|| temps.homes.get(t).cloned());
+            },
+            Expr::Reg(Reg::W) if w.is_some() => { return w.cloned(); },
+            Expr::Load(a) => Expr::Load(go(a)?),
+            Expr::ProgramLoad(a) => Expr::ProgramLoad(go(a)?),
+            Expr::Not(a) => Expr::Not(go(a)?),
+            Expr::Binary(op, a, b) => self.banked(addr, Expr::Binary(*op, go(a)?, go(b)?)),
+            Expr::Select(a, b, c) => Expr::Select(go(a)?, go(b)?, go(c)?),
+            _ => e.clone()
+        };
+        if written.contains(&e) { None } else { Some(e) }
+    }
+
+    fn location(&self, addr: &Expr, folded: &Folded) -> String {
+        if let Expr::Const(addr) = addr {
+            return data_name(*addr as u16);
+        }
+        if let Some(name) = pair_name(addr) {
+            return format!("*{}", name);
+        }
+        if let Expr::Binary(op, a, b) = addr {
+            if let (BinOp::Add, Some(name)) = (op, pair_name(a)) {
+                return format!("{}[{}]", name, self.render(b, folded));
+            }
+            if let (BinOp::Or, Expr::Const(file)) = (op, &**b) {
+                if reads(a, &[Expr::Reg(Reg::Bsr)], false) {
+                    return format!("ram[BSR:0x{:02x}]", file);
+                }
+            }
+        }
+        format!("ram[{}]", self.render(addr, folded))
+    }
+
+    // whether `e` can be an operand as it is, without brackets.
+    fn atomic(e: &Expr) -> bool {
+        match e {
+            Expr::Binary(BinOp::Eq, flag, zero) if matches!(**flag, Expr::Flag(_)) && **zero == Expr::Const(0) => true,
+            Expr::Binary(BinOp::Xor, _, mask) if **mask == Expr::Const(0xff) => true,
+            Expr::Binary(BinOp::Sub, zero, _) if **zero == Expr::Const(0) => true,
+            Expr::Binary(_, _, _) => pair_name(e).is_some() || Decompiler::sign_extended(e).is_some(),
+            Expr::Select(_, _, _) => false,
+            _ => true
+        }
+    }
+
+    // `x`, if `e` is the byte `x` sign-extended, as PLUSWn indexes by W.
+    fn sign_extended(e: &Expr) -> Option<&Expr> {
+        if let Expr::Binary(BinOp::Add, x, extension) = e {
+            if let Expr::Binary(BinOp::Mul, sign, _) = &**extension {
+                if let Expr::Binary(BinOp::Shr, y, _) = &**sign {
+                    if x == y {
+                        return Some(&**x);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn operand(&self, e: &Expr, folded: &Folded) -> String {
+        let text = self.render(e, folded);
+        if Decompiler::atomic(e) { text } else { format!("({})", text) }
+    }
+
+    fn render(&self, e: &Expr, folded: &Folded) -> String {
+        match e {
+            Expr::Const(k) => hex(*k),
+            Expr::Reg(reg) => reg.to_string(),
+            Expr::Flag(flag) => format!("STATUS.{}", flag),
+            Expr::Temp(t) => match folded {
+                Some((folded, text)) if folded == t => text.clone(),
+                _ => format!("t{}", t),
+            },
+            Expr::Load(addr) => self.location(addr, folded),
+            Expr::ProgramLoad(addr) => format!("prog[{}]", self.render(addr, folded)),
+            Expr::Not(a) => format!("!{}", self.operand(a, folded)),
+            Expr::Select(a, b, c) => format!("{} ? {} : {}", self.operand(a, folded), self.operand(b, folded), self.operand(c, folded)),
+            Expr::Binary(op, a, b) => {
+                if let Some(name) = pair_name(e) {
+                    return name;
+                }
+                if let Some(x) = Decompiler::sign_extended(e) {
+                    return format!("(int8_t){}", self.operand(x, folded));
+                }
+                match (op, &**a, &**b) {
+                    (BinOp::Eq, Expr::Flag(flag), Expr::Const(0)) => { return format!("!STATUS.{}", flag); },
+                    (BinOp::Sub, Expr::Const(0), x) => { return format!("-{}", self.operand(x, folded)); },
+                    (BinOp::Xor, x, Expr::Const(0xff)) => { return format!("~{}", self.operand(x, folded)); },
+                    (BinOp::Shl, x, Expr::Const(n)) |
+                    (BinOp::Shr, x, Expr::Const(n)) => { return format!("{} {} {}", self.operand(x, folded), op, n); },
+                    _ => {}
+                }
+                // `a | b | c` rather than `(a | b) | c`
+                let left = match &**a {
+                    Expr::Binary(inner, _, _) if inner == op && matches!(op, BinOp::Add | BinOp::And | BinOp::Or | BinOp::Xor) => {
+                        self.render(a, folded)
+                    },
+                    _ => self.operand(a, folded),
+                };
+                format!("{} {} {}", left, op, self.operand(b, folded))
+            }
+        }
+    }
+
+    // `e` as a condition.
+    fn cond(&self, e: &Expr, folded: &Folded) -> Cond {
+        let side = |e: &Expr| self.operand(e, folded);
+        match e {
+            Expr::Binary(BinOp::Eq, x, zero) if **zero == Expr::Const(0) => self.cond(x, folded).invert(),
+            Expr::Binary(BinOp::Ne, x, zero) if **zero == Expr::Const(0) => self.cond(x, folded),
+            Expr::Binary(op @ BinOp::Eq, a, b) |
+            Expr::Binary(op @ BinOp::Ne, a, b) |
+            Expr::Binary(op @ BinOp::Lt, a, b) |
+            Expr::Binary(op @ BinOp::Gt, a, b) => {
+                let (holds, fails) = match op {
+                    BinOp::Eq => ("==", "!="),
+                    BinOp::Ne => ("!=", "=="),
+                    BinOp::Lt => ("<", ">="),
+                    _ => (">", "<="),
+                };
+                let (a, b) = (side(a), side(b));
+                Cond::new(format!("{} {} {}", a, holds, b), format!("{} {} {}", a, fails, b))
+            },
+            Expr::Flag(flag) => Cond::new(format!("STATUS.{}", flag), format!("!STATUS.{}", flag)),
+            _ => match bit_of(e) {
+                // a carry out of a subtraction is the absence of a borrow
+                Some((Expr::Binary(BinOp::Sub, a, b), 8)) => {
+                    let (a, b) = (side(a), side(b));
+                    Cond::new(format!("{} >= {}", a, b), format!("{} < {}", a, b))
+                },
+                Some((x, 8)) => Cond::new(format!("{} > 0xff", side(x)), format!("{} <= 0xff", side(x))),
+                Some((x, n)) => {
+                    let x = side(x);
+                    Cond::new(format!("{} & (1 << {})", x, n), format!("!({} & (1 << {}))", x, n))
+                },
+                None => Cond::new(format!("{} != 0", side(e)), format!("{} == 0", side(e))),
+            }
+        }
+    }
+
+    // `dest = value` as a statement, and as an expression for a condition to fold in.
+    fn assignment(&self, dest: &Expr, value: &Expr) -> (String, String) {
+        let name = self.render(dest, &None);
+        if let Expr::Binary(op, a, b) = value {
+            let sym = match op {
+                BinOp::Add | BinOp::Sub | BinOp::And | BinOp::Or | BinOp::Xor => Some(op.to_string()),
+                _ => None
+            };
+            let other = if **a == *dest {
+                Some(&**b)
+            } else if **b == *dest && *op != BinOp::Sub {
+                Some(&**a)
+            } else {
+                None
+            };
+            if let (Some(sym), Some(other)) = (sym, other) {
+                if *other == Expr::Const(1) && matches!(op, BinOp::Add | BinOp::Sub) {
+                    return (format!("{}{}{};", name, sym, sym), format!("{}{}{}", sym, sym, name));
+                }
+                // bit operations by bit number
+                let other = match (op, other) {
+                    (BinOp::Or, Expr::Const(k)) |
+                    (BinOp::Xor, Expr::Const(k)) if k.count_ones() == 1 => format!("(1 << {})", k.trailing_zeros()),
+                    (BinOp::And, Expr::Const(k)) if (!k & 0xff).count_ones() == 1 => format!("~(1 << {})", (!k & 0xff).trailing_zeros()),
+                    _ => self.render(other, &None),
+                };
+                return (format!("{} {}= {};", name, sym, other), format!("({} {}= {})", name, sym, other));
+            }
+        }
+        let value = self.render(value, &None);
+        (format!("{} = {};", name, value), format!("({} = {})", name, value))
+    }
+
+    // statements for the instruction at `addr`, from what `ir::lift` says it does, and the
+    // condition under which it skips or branches, if it does either.
+    fn instruction(&self, addr: u32, inst: &Instruction, state: &mut BlockState, statements: &mut Vec<String>) -> Option<Cond> {
+        let stmts = ir::lift(addr, inst);
+        let w = state.w.clone();
+        let mut temps = Temps::default();
+        // locations, and flags, this instruction sets
+        let mut written = Vec::new();
+        let mut flags = Vec::new();
+        let mut set_w = None;
+        let mut control = None;
+        let mut calls = false;
+        // the statement last rendered and the temporary it stored, for a skip to fold in
+        let mut stored: Option<(usize, u8, String)> = None;
+
+        let mut i = 0;
+        while i < stmts.len() {
+            let stmt = &stmts[i];
+            i += 1;
+            let (dest, halves, value) = match (pair(&stmts[i - 1..]), stmt) {
+                (Some(pair), _) => {
+                    i += 1;
+                    pair
+                },
+                (None, Stmt::SetReg(reg, value)) => {
+                    if *reg == Reg::W {
+                        set_w = Some(value.clone());
+                    }
+                    (Expr::Reg(*reg), vec![Expr::Reg(*reg)], value.clone())
+                },
+                (None, Stmt::Store(addr_expr, value)) => {
+                    let dest = Expr::Load(Box::new(simplify(self.resolve(addr, addr_expr, &temps, w.as_ref(), None))));
+                    (dest.clone(), vec![dest], value.clone())
+                },
+                (None, _) => {
+                    let text = match stmt {
+                        Stmt::Let(t, value) => {
+                            temps.values.insert(*t, value.clone());
+                            continue;
+                        },
+                        Stmt::SetFlag(flag, value) => {
+                            flags.push((*flag, value.clone()));
+                            written.push(Expr::Flag(*flag));
+                            continue;
+                        },
+                        Stmt::Skip(cond) | Stmt::Branch(cond, _) => {
+                            control = Some(cond.clone());
+                            continue;
+                        },
+                        Stmt::Jump(_) => { continue; },
+                        Stmt::ProgramStore(at, value) => {
+                            let at = simplify(self.resolve(addr, at, &temps, w.as_ref(), None));
+                            let value = simplify(self.resolve(addr, value, &temps, w.as_ref(), None));
+                            format!("prog[{}] = {};", self.render(&at, &None), self.render(&value, &None))
+                        },
+                        Stmt::Call { target, fast, .. } => {
+                            calls = true;
+                            let call = match simplify(self.resolve(addr, target, &temps, w.as_ref(), None)) {
+                                Expr::Const(target) => self.call(target),
+                                target => format!("(*({}))();", self.render(&target, &None)),
+                            };
+                            if *fast { format!("{} // saving W, STATUS and BSR", call) } else { call }
+                        },
+                        Stmt::Return { fast, .. } => {
+                            let ret = match (i.checked_sub(2).map(|j| &stmts[j]), statements.last()) {
+                                // RETLW
+                                (Some(Stmt::SetReg(Reg::W, value)), Some(_)) => {
+                                    statements.pop();
+                                    let value = simplify(self.resolve(addr, value, &temps, w.as_ref(), None));
+                                    format!("return {};", self.render(&value, &None))
+                                },
+                                _ => "return;".to_string(),
+                            };
+                            if *fast { format!("{} // restoring W, STATUS and BSR", ret) } else { ret }
+                        },
+                        Stmt::Push(_) => "push();".to_string(),
+                        Stmt::Pop => "pop();".to_string(),
+                        Stmt::Sleep => "sleep();".to_string(),
+                        Stmt::ClearWatchdog => "clrwdt();".to_string(),
+                        Stmt::Reset => "reset();".to_string(),
+                        _ => format!("asm(\"{}\");", inst),
+                    };
+                    statements.push(text);
+                    stored = None;
+                    continue;
+                }
+            };
+
+            let resolved = simplify(self.resolve(addr, &value, &temps, w.as_ref(), None));
+            stored = None;
+            // MOVF f, F stores what is already there: it only sets flags
+            if resolved != dest {
+                let (stmt, expr) = self.assignment(&dest, &resolved);
+                statements.push(stmt);
+                if let Expr::Temp(t) = value {
+                    stored = Some((statements.len() - 1, t, expr));
+                }
+            }
+            if let Expr::Temp(t) = value {
+                temps.homes.insert(t, dest);
+            }
+            written.extend(halves);
+        }
+
+        // what was known before, less what this overwrites
+        let through_pointer = written.iter().any(|e| matches!(e, Expr::Load(addr) if !matches!(**addr, Expr::Const(_))));
+        let stale = |e: &Option<Expr>| e.as_ref().map(|e| reads(e, &written, through_pointer)).unwrap_or(false);
+        if calls {
+            *state = BlockState::default();
+        }
+        for flag in state.flags.iter_mut() {
+            if stale(flag) {
+                *flag = None;
+            }
+        }
+        if stale(&state.w) {
+            state.w = None;
+        }
+        for (flag, value) in flags {
+            state.flags[flag.bit() as usize] = self.settled(addr, &value, &temps, w.as_ref(), &written)
+                .map(simplify)
+                .filter(|e| !matches!(e, Expr::Const(_)));
+        }
+        if let Some(value) = set_w {
+            state.w = self.settled(addr, &value, &temps, w.as_ref(), &written)
+                .map(simplify)
+                .filter(|e| match e {
+                    Expr::Const(_) => true,
+                    Expr::Load(addr) => matches!(**addr, Expr::Const(_)),
+                    _ => false
+                });
+        }
+
+        let cond = control?;
+        // `decfsz x` tests what it stores: `--x == 0`
+        let folded = match stored {
+            Some((index, t, expr)) if index + 1 == statements.len() && reads(&cond, &[Expr::Temp(t)], false) => {
+                statements.pop();
+                Some((t, expr))
+            },
+            _ => None
+        };
+        let resolved = self.resolve(addr, &cond, &temps, w.as_ref(), folded.as_ref().map(|(t, _)| *t));
+        let resolved = simplify(with_flags(&simplify(resolved), &state.flags));
+        let mut cond = self.cond(&resolved, &folded);
+        cond.effect = folded.is_some();
+        Some(cond)
+    }
+
+    // whether the instruction at `addr` returns a value in W, as RETLW does.
+    fn returns_value(addr: u32, inst: &Instruction) -> bool {
+        ir::lift(addr, inst).windows(2).any(|pair| matches!(pair, [Stmt::SetReg(Reg::W, _), Stmt::Return { .. }]))
+    }
+
+    // a 16-bit add or subtract at `instructions[i..]`, as a statement and how many instructions
+    // it covers:
+    //   movf a, W; addwf x, F; movf a+1, W; addwfc x+1, F      WORD(x) += WORD(a)
+    //   movf a, W; addwf b, W; movwf c; movf a+1, W; addwfc b+1, W; movwf c+1
+    //                                                          WORD(c) = WORD(b) + WORD(a)
+    // movlw works in place of movf, with the two literals making one constant.
+    fn wide(&self, instructions: &[(u32, Instruction)], i: usize) -> Option<(String, usize)> {
+        let at = |n: usize| instructions.get(i + n);
+        let source = |n: usize| -> Option<(Option<u16>, Option<u8>)> {
+            let (addr, inst) = at(n)?;
+            match (inst.opcode, inst.operands[0]) {
+                (Opcode::MOVF, Operand::RedirectableFile(_, _, false)) => Some((Some(self.address(*addr, &inst.operands[0])?), None)),
+                (Opcode::MOVLW, Operand::ImmediateU8(k)) => Some((None, Some(k))),
+                _ => None
+            }
+        };
+        let file = |n: usize, opcodes: &[Opcode], to_w: bool| -> Option<u16> {
+            let (addr, inst) = at(n)?;
+            let redirect = matches!(inst.operands[0], Operand::RedirectableFile(_, _, d) if d != to_w);
+            if !opcodes.contains(&inst.opcode) || !redirect {
+                return None;
+            }
+            self.address(*addr, &inst.operands[0])
+        };
+        let operand = |low: (Option<u16>, Option<u8>), high: (Option<u16>, Option<u8>)| -> Option<String> {
+            match (low, high) {
+                ((Some(low), _), (Some(high), _)) if high == low.wrapping_add(1) => Some(format!("WORD({})", data_name(low))),
+                ((_, Some(low)), (_, Some(high))) => Some(format!("0x{:04x}", ((high as u16) << 8) | low as u16)),
+                ((Some(low), _), (_, Some(0))) => Some(data_name(low)),
+                _ => None
+            }
+        };
+
+        let low_source = source(0)?;
+        for (first, second, sym) in [
+            (Opcode::ADDWF, Opcode::ADDWFC, "+"),
+            (Opcode::SUBWF, Opcode::SUBWFB, "-"),
+        ].iter() {
+            // in place
+            if let (Some(low), Some(high_source), Some(high)) = (file(1, &[*first], false), source(2), file(3, &[*second], false)) {
+                if high == low.wrapping_add(1) {
+                    if let Some(operand) = operand(low_source, high_source) {
+                        return Some((format!("WORD({}) {}= {};", data_name(low), sym, operand), 4));
+                    }
+                }
+            }
+            // into a third variable
+            let stored = |n: usize| -> Option<u16> {
+                let (addr, inst) = at(n)?;
+                if inst.opcode != Opcode::MOVWF { return None; }
+                self.address(*addr, &inst.operands[0])
+            };
+            if let (Some(low), Some(dest_low), Some(high_source), Some(high), Some(dest_high)) =
+                    (file(1, &[*first], true), stored(2), source(3), file(4, &[*second], true), stored(5)) {
+                if high == low.wrapping_add(1) && dest_high == dest_low.wrapping_add(1) {
+                    if let Some(operand) = operand(low_source, high_source) {
+                        return Some((format!("WORD({}) = WORD({}) {} {};", data_name(dest_low), data_name(low), sym, operand), 6));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    // statements for `block` and how it exits. `statements` is left without the exit itself.
+    fn render_block(&self, block: &BasicBlock, statements: &mut Vec<String>, labels: &mut BTreeSet<u32>) -> Exit {
+        let mut state = BlockState::default();
+        let mut i = 0;
+        while i < block.instructions.len() {
+            if let Some((stmt, len)) = self.wide(&block.instructions, i) {
+                statements.push(stmt);
+                state = BlockState::default();
+                i += len;
+                continue;
+            }
+            let (addr, inst) = &block.instructions[i];
+            let mut own = Vec::new();
+            let cond = self.instruction(*addr, inst, &mut state, &mut own);
+            if i + 1 == block.instructions.len() {
+                if let Some(exit) = self.exit(block, *addr, inst, cond, &mut own, labels) {
+                    statements.extend(own);
+                    return exit;
+                }
+            }
+            statements.extend(own);
+            i += 1;
+        }
+        // a block ended by something other than its own last instruction, or a call
+        match block.successors.iter().find(|edge| edge.kind == EdgeKind::Fallthrough || edge.kind == EdgeKind::CallReturn) {
+            Some(edge) => Exit::Fall(edge.target),
+            None => Exit::Stop
+        }
+    }
+
+    // how the block ends, given its last instruction, the condition that instruction tests if
+    // it tests one, and the statements it was rendered as.
+    fn exit(
+        &self,
+        block: &BasicBlock,
+        addr: u32,
+        inst: &Instruction,
+        cond: Option<Cond>,
+        statements: &mut Vec<String>,
+        labels: &mut BTreeSet<u32>
+    ) -> Option<Exit> {
+        let fall = block.end;
+        match flow(addr, inst) {
+            Flow::Jump(target) => Some(Exit::Jump(target)),
+            Flow::Branch(target) => Some(Exit::Branch {
+                cond: cond?,
+                taken: target,
+                fall,
+            }),
+            Flow::Skip => {
+                let taken = block.successors.iter()
+                    .find(|edge| edge.kind == EdgeKind::Skip)
+                    .map(|edge| edge.target)?;
+                Some(Exit::Branch {
+                    cond: cond?,
+                    taken,
+                    fall,
+                })
+            },
+            Flow::IndirectJump => {
+                let targets: Vec<u32> = block.successors.iter().map(|edge| edge.target).collect();
+                labels.extend(targets.iter().cloned());
+                let names: Vec<String> = targets.iter().map(|target| label(*target)).collect();
+                // what the instruction does to PCL goes in the comment
+                let mut comment: Vec<String> = statements.drain(..).map(|stmt| stmt.trim_end_matches(';').to_string()).collect();
+                comment.push(format!("one of {}", names.join(", ")));
+                statements.push(format!("goto *PC; // {}", comment.join("; ")));
+                Some(Exit::Stop)
+            },
+            _ => None
+        }
+    }
+
+    // pseudo-C for the function starting at `entry`.
+    pub fn function(&self, entry: u32) -> Option<String> {
+        let function = self.cfg.function(entry)?;
+        let blocks: Vec<&BasicBlock> = function.blocks.iter().filter_map(|start| self.cfg.block(*start)).collect();
+        let returns_value = blocks.iter().any(|block| {
+            block.instructions.iter().any(|(addr, inst)| Decompiler::returns_value(*addr, inst))
+        });
+
+        // once to find which blocks need labels, then for real
+        let mut labels = BTreeSet::new();
+        let mut body = String::new();
+        for _ in 0..2 {
+            let mut emitter = Emitter {
+                decompiler: self,
+                entry,
+                blocks: &blocks,
+                index: blocks.iter().enumerate().map(|(i, block)| (block.start, i)).collect(),
+                labels: labels.clone(),
+                gotos: BTreeSet::new(),
+                out: String::new(),
+                latch_exit: None,
+            };
+            let first = emitter.index[&entry];
+            emitter.emit_range(first, blocks.len(), None, None, None, 1);
+            if first > 0 {
+                emitter.emit_range(0, first, None, None, None, 1);
+            }
+            labels = emitter.gotos;
+            body = emitter.out;
+        }
+
+        let ret = if returns_value { "uint8_t" } else { "void" };
+        Some(format!("{} {}(void) {{\n{}}}\n", ret, function_name(entry), body))
+    }
+
+    // every function, in address order.
+    pub fn program(&self) -> String {
+        let mut out = String::new();
+        for function in self.cfg.functions() {
+            if let Some(text) = self.function(function.entry) {
+                if !out.is_empty() {
+                    out.push('\n');
+                }
+                out.push_str(&text);
+            }
+        }
+        out
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Loop {
+    head: u32,
+    // whether going back to `head` can be `continue`: not in a do-while, where that would test
+    // the condition first
+    continues: bool,
+    // where control goes when the loop is done, if anywhere in this function
+    exit: Option<u32>,
+}
+
+struct Emitter<'a, 'b> {
+    decompiler: &'a Decompiler<'b>,
+    entry: u32,
+    blocks: &'a [&'a BasicBlock],
+    index: BTreeMap<u32, usize>,
+    // blocks to label, found by a previous pass
+    labels: BTreeSet<u32>,
+    gotos: BTreeSet<u32>,
+    out: String,
+    // the exit of a loop's latch, for the loop to close itself with
+    latch_exit: Option<Exit>,
+}
+
+impl<'a, 'b> Emitter<'a, 'b> {
+    fn line(&mut self, indent: usize, text: &str) {
+        for _ in 0..indent {
+            self.out.push_str("    ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    // the last block in `from..to` to jump back to the block at `from`.
+    fn latch(&self, from: usize, to: usize) -> Option<usize> {
+        let head = self.blocks[from].start;
+        (from..to).rev().find(|i| {
+            self.blocks[*i].successors.iter().any(|edge| edge.target == head && edge.kind != EdgeKind::CallReturn)
+        })
+    }
+
+    fn jump(&mut self, target: u32, lp: Option<Loop>) -> Vec<String> {
+        if let Some(lp) = lp {
+            if lp.head == target && lp.continues {
+                return vec!["continue;".to_string()];
+            }
+            if lp.exit == Some(target) {
+                return vec!["break;".to_string()];
+            }
+        }
+        if target != self.entry && self.decompiler.cfg.function(target).is_some() {
+            return vec![self.decompiler.call(target), "return;".to_string()];
+        }
+        if !self.index.contains_key(&target) {
+            return vec![format!("goto *0x{:04x};", target)];
+        }
+        self.gotos.insert(target);
+        vec![format!("goto {};", label(target))]
+    }
+
+    fn emit_jump(&mut self, target: u32, lp: Option<Loop>, indent: usize) {
+        for stmt in self.jump(target, lp) {
+            self.line(indent, &stmt);
+        }
+    }
+
+    // emit blocks `from..to`, after which control reaches `follow`. `latch` is the block whose
+    // exit an enclosing loop renders; `in_loop` is the loop head already being emitted at `from`.
+    fn emit_range(&mut self, from: usize, to: usize, follow: Option<u32>, lp: Option<Loop>, latch: Option<usize>, indent: usize) {
+        let mut i = from;
+        while i < to {
+            let block = self.blocks[i];
+            let loop_here = latch.map(|latch| i == from && latch < to).unwrap_or(false) && lp.map(|lp| lp.head == block.start).unwrap_or(false);
+            if !loop_here {
+                if self.labels.contains(&block.start) {
+                    self.out.push_str(&format!("{}:\n", label(block.start)));
+                }
+                if let Some(end) = self.latch(i, to) {
+                    i = self.emit_loop(i, end, to, follow, indent);
+                    continue;
+                }
+            }
+
+            let mut statements = Vec::new();
+            let mut labels = BTreeSet::new();
+            let exit = self.decompiler.render_block(block, &mut statements, &mut labels);
+            self.gotos.extend(labels);
+            for stmt in statements.iter() {
+                self.line(indent, stmt);
+            }
+            if latch == Some(i) {
+                self.latch_exit = Some(exit);
+                i += 1;
+                continue;
+            }
+
+            let next = if i + 1 < to { Some(self.blocks[i + 1].start) } else { follow };
+            match exit {
+                Exit::Fall(target) |
+                Exit::Jump(target) => {
+                    if Some(target) != next {
+                        self.emit_jump(target, lp, indent);
+                    }
+                    i += 1;
+                },
+                Exit::Stop => { i += 1; },
+                Exit::Branch { cond, taken, fall } => {
+                    i = self.emit_branch(i, to, follow, lp, indent, cond, taken, fall, next);
+                }
+            }
+        }
+    }
+
+    // an if or if/else for the branch ending the block at `i`; returns the block to continue at.
+    #[allow(clippy::too_many_arguments)]
+    fn emit_branch(
+        &mut self,
+        i: usize,
+        to: usize,
+        follow: Option<u32>,
+        lp: Option<Loop>,
+        indent: usize,
+        cond: Cond,
+        taken: u32,
+        fall: u32,
+        next: Option<u32>
+    ) -> usize {
+        let region_end = |target: u32| -> Option<usize> {
+            if Some(target) == follow {
+                Some(to)
+            } else {
+                self.index.get(&target).cloned().filter(|j| *j > i && *j < to)
+            }
+        };
+        let structured = Some(fall) == next && lp.map(|lp| lp.head != taken && lp.exit != Some(taken)).unwrap_or(true);
+        if let (true, Some(then_end)) = (structured, region_end(taken)) {
+            // `btfss x; bra elsewhere`: the branch that matters is the one skipped over
+            let skipped = self.blocks[i + 1];
+            if then_end == i + 2 && skipped.instructions.len() == 1 && !self.labels.contains(&skipped.start) {
+                let (at, inst) = skipped.terminator();
+                if let Flow::Jump(target) = flow(at, &inst) {
+                    if target > taken && region_end(target).is_some() {
+                        return self.emit_branch(i + 1, to, follow, lp, indent, cond.invert(), target, taken, Some(taken));
+                    }
+                }
+            }
+            // an else, if the then-part ends by jumping over it
+            let else_end = if then_end > i + 1 && then_end < to {
+                let (at, inst) = self.blocks[then_end - 1].terminator();
+                match flow(at, &inst) {
+                    Flow::Jump(target) if target > taken => region_end(target).filter(|end| *end > then_end).map(|end| (end, target)),
+                    _ => None
+                }
+            } else {
+                None
+            };
+            let then_follow = match else_end {
+                Some((_, target)) => Some(target),
+                None => Some(taken),
+            };
+            if then_end == i + 1 {
+                // nothing to do when the condition fails: only worth saying when it has an effect
+                if cond.effect {
+                    self.line(indent, &format!("if ({}) {{}}", cond.holds));
+                }
+                return i + 1;
+            }
+            self.line(indent, &format!("if ({}) {{", cond.fails));
+            self.emit_range(i + 1, then_end, then_follow, lp, None, indent + 1);
+            match else_end {
+                Some((end, target)) => {
+                    self.line(indent, "} else {");
+                    let else_follow = if end < to { Some(self.blocks[end].start) } else { Some(target) };
+                    self.emit_range(then_end, end, else_follow, lp, None, indent + 1);
+                    self.line(indent, "}");
+                    end
+                },
+                None => {
+                    self.line(indent, "}");
+                    then_end
+                }
+            }
+        } else {
+            let jump = self.jump(taken, lp);
+            if jump.len() == 1 {
+                self.line(indent, &format!("if ({}) {}", cond.holds, jump[0]));
+            } else {
+                self.line(indent, &format!("if ({}) {{", cond.holds));
+                for stmt in jump.iter() {
+                    self.line(indent + 1, stmt);
+                }
+                self.line(indent, "}");
+            }
+            if Some(fall) != next {
+                self.emit_jump(fall, lp, indent);
+            }
+            i + 1
+        }
+    }
+
+    // `while (cond) { ... }`, for a loop whose head does nothing but decide whether to leave and
+    // whose latch goes straight back to it. false, having emitted nothing, for any other loop.
+    fn emit_while(&mut self, head: usize, end: usize, exit: Option<u32>, indent: usize) -> bool {
+        if end == head {
+            return false;
+        }
+        let mut statements = Vec::new();
+        let mut labels = BTreeSet::new();
+        let body_start = self.blocks[head + 1].start;
+        let cond = match self.decompiler.render_block(self.blocks[head], &mut statements, &mut labels) {
+            Exit::Branch { cond, taken, fall } if Some(taken) == exit && fall == body_start => cond.fails,
+            Exit::Branch { cond, taken, fall } if Some(fall) == exit && taken == body_start => cond.holds,
+            _ => { return false; }
+        };
+        if !statements.is_empty() {
+            return false;
+        }
+        let lp = Loop { head: self.blocks[head].start, continues: true, exit };
+        let mut body = Emitter {
+            decompiler: self.decompiler,
+            entry: self.entry,
+            blocks: self.blocks,
+            index: self.index.clone(),
+            labels: self.labels.clone(),
+            gotos: BTreeSet::new(),
+            out: String::new(),
+            latch_exit: None,
+        };
+        body.emit_range(head + 1, end + 1, Some(lp.head), Some(lp), Some(end), indent + 1);
+        match body.latch_exit {
+            Some(Exit::Jump(target)) |
+            Some(Exit::Fall(target)) if target == lp.head => {},
+            _ => { return false; }
+        }
+        self.gotos.extend(body.gotos);
+        self.gotos.extend(labels);
+        if body.out.is_empty() {
+            self.line(indent, &format!("while ({}) {{}}", cond));
+        } else {
+            self.line(indent, &format!("while ({}) {{", cond));
+            self.out.push_str(&body.out);
+            self.line(indent, "}");
+        }
+        true
+    }
+
+    // a loop from the block at `head` back from the block at `end`; returns the block after it.
+    fn emit_loop(&mut self, head: usize, end: usize, to: usize, follow: Option<u32>, indent: usize) -> usize {
+        let exit = if end + 1 < to { Some(self.blocks[end + 1].start) } else { follow };
+        if self.emit_while(head, end, exit, indent) {
+            return end + 1;
+        }
+        let (at, inst) = self.blocks[end].terminator();
+        let do_while = match flow(at, &inst) {
+            Flow::Branch(target) => target == self.blocks[head].start,
+            _ => false
+        };
+        let lp = Loop { head: self.blocks[head].start, continues: !do_while, exit };
+        let mut body = Emitter {
+            decompiler: self.decompiler,
+            entry: self.entry,
+            blocks: self.blocks,
+            index: self.index.clone(),
+            labels: self.labels.clone(),
+            gotos: BTreeSet::new(),
+            out: String::new(),
+            latch_exit: None,
+        };
+        body.emit_range(head, end + 1, Some(lp.head), Some(lp), Some(end), indent + 1);
+        self.gotos.extend(body.gotos.iter().cloned());
+
+        match body.latch_exit.take() {
+            Some(Exit::Branch { cond, taken, fall }) if taken == lp.head => {
+                self.line(indent, "do {");
+                self.out.push_str(&body.out);
+                self.line(indent, &format!("}} while ({});", cond.holds));
+                if Some(fall) != exit {
+                    self.emit_jump(fall, None, indent);
+                }
+            },
+            Some(Exit::Branch { cond, taken, fall }) => {
+                // the loop continues by falling through, and leaves by branching
+                self.line(indent, "for (;;) {");
+                self.out.push_str(&body.out);
+                let jump = body.jump(taken, Some(lp));
+                self.gotos.extend(body.gotos.iter().cloned());
+                self.line(indent + 1, &format!("if ({}) {}", cond.holds, jump.join(" ")));
+                if fall != lp.head {
+                    self.emit_jump(fall, Some(lp), indent + 1);
+                }
+                self.line(indent, "}");
+            },
+            Some(Exit::Jump(target)) |
+            Some(Exit::Fall(target)) => {
+                self.line(indent, "for (;;) {");
+                self.out.push_str(&body.out);
+                if target != lp.head {
+                    self.emit_jump(target, Some(lp), indent + 1);
+                }
+                self.line(indent, "}");
+            },
+            _ => {
+                self.line(indent, "for (;;) {");
+                self.out.push_str(&body.out);
+                self.line(indent, "}");
+            }
+        }
+        end + 1
+    }
+}
@@ -0,0 +1,190 @@
+use crate::consts;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Port {
+    // `'A'` for PORTA
+    pub name: char,
+    pub port: u16,
+    pub lat: u16,
+    pub tris: u16,
+}
+
+impl Port {
+    const fn new(name: char, index: u16) -> Port {
+        Port {
+            name,
+            port: consts::SFR_BASE + consts::SFRS::PORTA + index,
+            lat: consts::SFR_BASE + consts::SFRS::LATA + index,
+            tris: consts::SFR_BASE + consts::SFRS::TRISA + index,
+        }
+    }
+}
+
+const PORT_A: Port = Port::new('A', 0);
+const PORT_B: Port = Port::new('B', 1);
+const PORT_C: Port = Port::new('C', 2);
+const PORT_D: Port = Port::new('D', 3);
+const PORT_E: Port = Port::new('E', 4);
+
+// what sets one part apart from another, as far as reading its code goes. everything here uses
+// the classic SFR map `consts` describes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    pub name: &'static str,
+    // bytes of program flash
+    pub program_memory: u32,
+    // bytes of general purpose RAM, from address 0
+    pub data_memory: u16,
+    // bytes of data EEPROM
+    pub eeprom: u16,
+    // bytes of flash a row erase clears, and that one write programs from the holding registers
+    pub erase_block: u16,
+    pub write_block: u16,
+    // access-bank files below this are RAM in bank 0, the rest are SFRs at the top of bank 15.
+    // 0x80 on the older parts `consts::access_address` follows, 0x60 on the newer ones.
+    pub access_split: u8,
+    pub ports: &'static [Port],
+    // SFRs this part names differently from `consts`. the first table naming an address wins.
+    sfrs: &'static [&'static [(u16, &'static str)]],
+}
+
+// the K22 parts have two EUSARTs, and number the first
+const EUSART1: [(u16, &str); 7] = [
+    (0xfab, "RCSTA1"),
+    (0xfac, "TXSTA1"),
+    (0xfad, "TXREG1"),
+    (0xfae, "RCREG1"),
+    (0xfaf, "SPBRG1"),
+    (0xfb0, "SPBRGH1"),
+    (0xfb8, "BAUDCON1"),
+];
+
+pub const PIC18F252: Device = Device {
+    name: "PIC18F252",
+    program_memory: 0x8000,
+    data_memory: 1536,
+    eeprom: 256,
+    erase_block: 64,
+    write_block: 8,
+    access_split: 0x80,
+    ports: &[PORT_A, PORT_B, PORT_C],
+    sfrs: &[],
+};
+
+pub const PIC18F452: Device = Device {
+    name: "PIC18F452",
+    program_memory: 0x8000,
+    data_memory: 1536,
+    eeprom: 256,
+    erase_block: 64,
+    write_block: 8,
+    access_split: 0x80,
+    ports: &[PORT_A, PORT_B, PORT_C, PORT_D, PORT_E],
+    sfrs: &[],
+};
+
+pub const PIC18F2550: Device = Device {
+    name: "PIC18F2550",
+    program_memory: 0x8000,
+    data_memory: 2048,
+    eeprom: 256,
+    erase_block: 64,
+    write_block: 32,
+    access_split: 0x60,
+    ports: &[PORT_A, PORT_B, PORT_C, PORT_E],
+    sfrs: &[],
+};
+
+pub const PIC18F4550: Device = Device {
+    name: "PIC18F4550",
+    program_memory: 0x8000,
+    data_memory: 2048,
+    eeprom: 256,
+    erase_block: 64,
+    write_block: 32,
+    access_split: 0x60,
+    ports: &[PORT_A, PORT_B, PORT_C, PORT_D, PORT_E],
+    sfrs: &[],
+};
+
+pub const PIC18F25K22: Device = Device {
+    name: "PIC18F25K22",
+    program_memory: 0x8000,
+    data_memory: 1536,
+    eeprom: 256,
+    erase_block: 64,
+    write_block: 64,
+    access_split: 0x60,
+    ports: &[PORT_A, PORT_B, PORT_C, PORT_E],
+    sfrs: &[&EUSART1],
+};
+
+pub const PIC18F45K22: Device = Device {
+    name: "PIC18F45K22",
+    program_memory: 0x8000,
+    data_memory: 1536,
+    eeprom: 256,
+    erase_block: 64,
+    write_block: 64,
+    access_split: 0x60,
+    ports: &[PORT_A, PORT_B, PORT_C, PORT_D, PORT_E],
+    sfrs: &[&EUSART1],
+};
+
+pub const DEVICES: [&Device; 6] = [
+    &PIC18F252,
+    &PIC18F452,
+    &PIC18F2550,
+    &PIC18F4550,
+    &PIC18F25K22,
+    &PIC18F45K22,
+];
+
+impl Device {
+    // a known part by name, ignoring case.
+    pub fn named(name: &str) -> Option<&'static Device> {
+        DEVICES.iter().find(|device| device.name.eq_ignore_ascii_case(name)).cloned()
+    }
+
+    pub fn port(&self, name: char) -> Option<&Port> {
+        self.ports.iter().find(|port| port.name == name)
+    }
+
+    // the port whose PORTx register is at `addr`.
+    pub fn port_at(&self, addr: u16) -> Option<&Port> {
+        self.ports.iter().find(|port| port.port == addr)
+    }
+
+    // the name this part gives the SFR at `addr`. ports it doesn't have go unnamed.
+    pub fn sfr_name(&self, addr: u16) -> Option<&'static str> {
+        if let Some((_, name)) = self.sfrs.iter().flat_map(|table| table.iter()).find(|(sfr, _)| *sfr == addr) {
+            return Some(name);
+        }
+        let unimplemented_port = [consts::SFRS::PORTA, consts::SFRS::LATA, consts::SFRS::TRISA].iter().any(|base| {
+            let index = addr.wrapping_sub(consts::SFR_BASE + base);
+            index < 5 && !self.ports.iter().any(|port| port.name == (b'A' + index as u8) as char)
+        });
+        if !(consts::SFR_BASE..=0xfff).contains(&addr) || unimplemented_port {
+            return None;
+        }
+        let name = consts::named_file(addr);
+        if name.starts_with("0x") {
+            None
+        } else {
+            Some(name)
+        }
+    }
+
+    // the data address an access-bank file operand refers to on this part.
+    pub fn access_address(&self, file: u8) -> u16 {
+        consts::split_access_address(file, self.access_split)
+    }
+
+    // `sfr_name`, or the address in hex.
+    pub fn file_name(&self, addr: u16) -> String {
+        match self.sfr_name(addr) {
+            Some(name) => name.to_string(),
+            None => format!("0x{:03x}", addr)
+        }
+    }
+}
@@ -31,6 +31,7 @@ impl Display for Opcode {
             Opcode::MOVSF => { write!(f, "movsf") },
             Opcode::MOVSD => { write!(f, "movsd") },
             Opcode::CALL => { write!(f, "call") },
+            Opcode::CALL_FAST => { write!(f, "call_fast") },
             Opcode::LFSR => { write!(f, "lfsr") },
             Opcode::GOTO => { write!(f, "goto") },
             Opcode::CALLW => { write!(f, "callw") },
@@ -0,0 +1,591 @@
+use std::fmt::{self, Display, Formatter};
+
+use yaxpeax_arch::{Decoder, U8Reader};
+
+use crate::{consts, InstDecoder, Instruction, Opcode};
+use crate::analysis::disasm::instruction_len;
+use crate::analysis::isr::Priority;
+use crate::device::Device;
+use crate::emu::interrupt::InterruptController;
+use crate::emu::memory::DataMemory;
+use crate::emu::nvm::Nvm;
+use crate::emu::peripheral::Peripheral;
+use crate::emu::stack::{ReturnStack, StackFault};
+use crate::emu::timer0::Timer0;
+use crate::ir::{self, Flag, Machine, Outcome, Reg};
+
+const WREG: u16 = consts::SFR_BASE + consts::SFRS::WREG;
+const STATUS: u16 = consts::SFR_BASE + consts::SFRS::STATUS;
+const BSR: u16 = consts::SFR_BASE + consts::SFRS::BSR;
+const PRODL: u16 = consts::SFR_BASE + consts::SFRS::PRODL;
+const PRODH: u16 = consts::SFR_BASE + consts::SFRS::PRODH;
+const TABLAT: u16 = consts::SFR_BASE + consts::SFRS::TABLAT;
+const TBLPTRL: u16 = consts::SFR_BASE + consts::SFRS::TBLPTRL;
+const TBLPTRH: u16 = consts::SFR_BASE + consts::SFRS::TBLPTRH;
+const TBLPTRU: u16 = consts::SFR_BASE + consts::SFRS::TBLPTRU;
+
+// the configuration word holding STVREN
+const CONFIG4L: u32 = 0x30_0006;
+const STVREN: u8 = 0;
+
+// pushing PC and moving to the vector, before the first instruction of an interrupt runs
+const INTERRUPT_CYCLES: u32 = 2;
+
+// program memory is at most 2MB, and PC wraps at that width.
+const PC_MASK: u32 = 0x1f_ffff;
+
+// a return address nothing in program memory can be, for `Cpu::call` to know the routine it
+// called has returned.
+const CALL_SENTINEL: u32 = PC_MASK & !1;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Stop {
+    // SLEEP ran, or the core is still asleep; PC is at the instruction after it. an enabled
+    // interrupt wakes it
+    Sleep,
+    // RESET ran, or something else reset the core; PC is back at zero
+    Reset,
+    // nothing the core can run, at this address
+    Undefined(u32),
+    // with STVREN set, a call or push filling the last stack entry, at this address. the part
+    // has reset, keeping STKFUL
+    StackOverflow(u32),
+    // with STVREN set, a return or pop with the stack empty, at this address, and the part has
+    // reset, keeping STKUNF
+    StackUnderflow(u32),
+    // `run_until` reached its address
+    Breakpoint(u32),
+    // `call` saw the routine return
+    Returned,
+    // the cycle budget ran out
+    CycleLimit,
+}
+
+impl Display for Stop {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Stop::Sleep => write!(f, "sleep"),
+            Stop::Reset => write!(f, "reset"),
+            Stop::Undefined(addr) => write!(f, "undefined instruction at {:04x}", addr),
+            Stop::StackOverflow(addr) => write!(f, "return stack overflow at {:04x}", addr),
+            Stop::StackUnderflow(addr) => write!(f, "return stack underflow at {:04x}", addr),
+            Stop::Breakpoint(addr) => write!(f, "breakpoint at {:04x}", addr),
+            Stop::Returned => write!(f, "returned"),
+            Stop::CycleLimit => write!(f, "cycle limit"),
+        }
+    }
+}
+
+// what one instruction did.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub addr: u32,
+    pub instruction: Instruction,
+    // instruction cycles, four oscillator periods each
+    pub cycles: u32,
+    // an interrupt taken before the instruction ran, its cycles counted in `cycles`
+    pub interrupt: Option<Priority>,
+}
+
+// W, STATUS and BSR as CALL FAST and interrupts save them, for RETURN FAST and RETFIE FAST.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Shadow {
+    pub w: u8,
+    pub status: u8,
+    pub bsr: u8,
+}
+
+// the PIC18 core. everything with a data address, W, STATUS and BSR included, lives in data
+// memory as it does on the chip, as do PC and the return stack; the shadow registers are the
+// core's own. attached peripherals answer for their SFRs instead of memory, as program flash
+// and data EEPROM do for the EECON registers.
+pub struct Cpu {
+    nvm: Nvm,
+    memory: DataMemory,
+    interrupts: InterruptController,
+    peripherals: Vec<Box<dyn Peripheral>>,
+    shadow: Shadow,
+    cycles: u64,
+    sleeping: bool,
+}
+
+impl Cpu {
+    // a core with `image` in program memory from address zero, just out of reset. program memory
+    // past the image is erased, which runs as NOP.
+    pub fn new(image: &[u8]) -> Cpu {
+        Cpu::with_memory(image, DataMemory::new())
+    }
+
+    // a core with data memory, flash blocks and EEPROM as `device` has them.
+    pub fn for_device(image: &[u8], device: &Device) -> Cpu {
+        let mut cpu = Cpu::with_memory(image, DataMemory::for_device(device));
+        cpu.nvm = Nvm::for_device(image, device);
+        cpu
+    }
+
+    // a core with Timer0 attached, over data memory laid out some other way, `DataMemory::for_device` say.
+    pub fn with_memory(image: &[u8], mut memory: DataMemory) -> Cpu {
+        // an image carrying its configuration words says whether stack faults reset
+        if let Some(config4l) = image.get(CONFIG4L as usize) {
+            memory.stack_mut().set_stvren(config4l & (1 << STVREN) != 0);
+        }
+        let mut cpu = Cpu {
+            nvm: Nvm::new(image),
+            memory,
+            interrupts: InterruptController::new(),
+            peripherals: vec![Box::new(Timer0::new())],
+            shadow: Shadow::default(),
+            cycles: 0,
+            sleeping: false,
+        };
+        cpu.reset();
+        cpu
+    }
+
+    // what a reset does to the core: back to the reset vector with an empty stack, and the SFRs
+    // with defined reset values cleared. RAM keeps its contents.
+    pub fn reset(&mut self) {
+        self.memory.reset();
+        self.shadow = Shadow::default();
+        self.sleeping = false;
+        for peripheral in self.peripherals.iter_mut() {
+            peripheral.reset();
+        }
+        self.nvm.reset();
+    }
+
+    // have `peripheral` answer for its addresses, ahead of any attached before it.
+    pub fn attach(&mut self, peripheral: Box<dyn Peripheral>) {
+        self.peripherals.insert(0, peripheral);
+    }
+
+    pub fn peripherals(&self) -> impl Iterator<Item=&dyn Peripheral> {
+        self.peripherals.iter().map(|peripheral| peripheral.as_ref())
+    }
+
+    pub fn pc(&self) -> u32 {
+        self.memory.pc()
+    }
+
+    pub fn set_pc(&mut self, pc: u32) {
+        self.memory.set_pc(pc & PC_MASK & !1);
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn w(&self) -> u8 {
+        self.memory.peek(WREG)
+    }
+
+    pub fn set_w(&mut self, value: u8) {
+        self.memory.poke(WREG, value);
+    }
+
+    pub fn status(&self) -> u8 {
+        self.memory.peek(STATUS)
+    }
+
+    pub fn bsr(&self) -> u8 {
+        self.memory.peek(BSR)
+    }
+
+    pub fn sleeping(&self) -> bool {
+        self.sleeping
+    }
+
+    pub fn interrupts(&self) -> &InterruptController {
+        &self.interrupts
+    }
+
+    pub fn interrupts_mut(&mut self) -> &mut InterruptController {
+        &mut self.interrupts
+    }
+
+    // set the flag of the interrupt source named `name`, as its hardware would. false if there's
+    // no such source.
+    pub fn raise(&mut self, name: &str) -> bool {
+        match self.interrupts.source(name) {
+            Some(source) => {
+                source.raise(&mut self.memory);
+                true
+            },
+            None => false
+        }
+    }
+
+    pub fn memory(&self) -> &DataMemory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut DataMemory {
+        &mut self.memory
+    }
+
+    // the byte at data address `addr`, without any read side effects.
+    pub fn file(&self, addr: u16) -> u8 {
+        match self.peripherals.iter().find(|peripheral| answers(peripheral.as_ref(), addr)) {
+            Some(peripheral) => peripheral.peek(addr),
+            None if answers(&self.nvm, addr) => self.nvm.peek(addr),
+            None => self.memory.peek(addr)
+        }
+    }
+
+    // set the byte at `addr` without indirection or jumps. a peripheral's registers take it as
+    // a write.
+    pub fn set_file(&mut self, addr: u16, value: u8) {
+        match peripheral_at(&mut self.peripherals, &mut self.nvm, addr) {
+            Some(peripheral) => peripheral.write(addr, value, &mut self.memory),
+            None => self.memory.poke(addr, value)
+        }
+    }
+
+    pub fn program(&self) -> &[u8] {
+        self.nvm.program()
+    }
+
+    // program flash and data EEPROM.
+    pub fn nvm(&self) -> &Nvm {
+        &self.nvm
+    }
+
+    pub fn nvm_mut(&mut self) -> &mut Nvm {
+        &mut self.nvm
+    }
+
+    pub fn stack(&self) -> &ReturnStack {
+        self.memory.stack()
+    }
+
+    pub fn shadow(&self) -> Shadow {
+        self.shadow
+    }
+
+    fn word(&self, addr: u32) -> u16 {
+        (self.program_byte(addr) as u16) | ((self.program_byte(addr + 1) as u16) << 8)
+    }
+
+    fn program_byte(&self, addr: u32) -> u8 {
+        self.nvm.program_byte(addr)
+    }
+
+    // the instruction at `addr`. a word that doesn't decode but starts 0xf is the second word
+    // of a two-word instruction, which runs as NOP.
+    pub fn decode(&self, addr: u32) -> Option<Instruction> {
+        let bytes = [
+            self.program_byte(addr), self.program_byte(addr + 1),
+            self.program_byte(addr + 2), self.program_byte(addr + 3),
+        ];
+        let mut reader = U8Reader::new(&bytes);
+        match InstDecoder::default().decode(&mut reader) {
+            Ok(inst) => Some(inst),
+            Err(_) if self.word(addr) & 0xf000 == 0xf000 => Some(Instruction {
+                opcode: Opcode::NOP,
+                operands: [crate::Operand::Nothing, crate::Operand::Nothing],
+            }),
+            Err(_) => None
+        }
+    }
+
+    fn save_shadow(&mut self) {
+        self.shadow = Shadow { w: self.w(), status: self.status(), bsr: self.bsr() };
+    }
+
+    fn restore_shadow(&mut self) {
+        self.memory.poke(WREG, self.shadow.w);
+        self.memory.poke(STATUS, self.shadow.status);
+        self.memory.poke(BSR, self.shadow.bsr);
+    }
+
+    // a stack fault resets the part when STVREN is set, and is otherwise only recorded in STKPTR:
+    // the push is lost, or the pop returns zero.
+    fn stack_fault(&mut self, addr: u32, fault: StackFault) -> Result<(), Stop> {
+        if !self.memory.stack().stvren() {
+            return Ok(());
+        }
+        self.reset();
+        Err(match fault {
+            StackFault::Overflow => Stop::StackOverflow(addr),
+            StackFault::Underflow => Stop::StackUnderflow(addr),
+        })
+    }
+
+    fn push(&mut self, addr: u32, ret: u32) -> Result<(), Stop> {
+        match self.memory.stack_mut().push(ret) {
+            Ok(()) => Ok(()),
+            Err(fault) => self.stack_fault(addr, fault),
+        }
+    }
+
+    fn pop(&mut self, addr: u32) -> Result<u32, Stop> {
+        match self.memory.stack_mut().pop() {
+            Ok(ret) => Ok(ret),
+            Err(fault) => self.stack_fault(addr, fault).map(|()| 0),
+        }
+    }
+
+    // what the core does to take an interrupt: the return address goes on the stack and W,
+    // STATUS and BSR into the shadow registers, whatever the priority.
+    fn enter_interrupt(&mut self, priority: Priority) -> Result<(), Stop> {
+        let pc = self.pc();
+        self.push(pc, pc)?;
+        self.save_shadow();
+        InterruptController::enter(&mut self.memory, priority);
+        self.memory.set_pc(priority.vector());
+        Ok(())
+    }
+
+    // run one instruction, taking any interrupt that's pending first.
+    pub fn step(&mut self) -> Result<Step, Stop> {
+        let mut interrupt = None;
+        if self.sleeping {
+            if !self.interrupts.requesting(&self.memory) {
+                return Err(Stop::Sleep);
+            }
+            // the instruction after SLEEP runs before the interrupt is taken
+            self.sleeping = false;
+        } else if let Some(priority) = self.interrupts.pending(&self.memory) {
+            self.enter_interrupt(priority)?;
+            interrupt = Some(priority);
+        }
+
+        let addr = self.pc();
+        let inst = self.decode(addr).ok_or(Stop::Undefined(addr))?;
+        let next = (addr + instruction_len(&inst)) & PC_MASK;
+        // PC reads as the address of the next instruction while one runs
+        self.memory.set_pc(next);
+        self.memory.take_pc_write();
+
+        let outcome = ir::execute(&ir::lift_split(addr, &inst, self.memory.access_split()), self);
+        let mut cycles = base_cycles(&inst) + if interrupt.is_some() { INTERRUPT_CYCLES } else { 0 };
+        match outcome {
+            Outcome::Next => {},
+            Outcome::Skip => {
+                let skipped = self.decode(next).map(|inst| instruction_len(&inst)).unwrap_or(2);
+                self.memory.set_pc((next + skipped) & PC_MASK);
+                cycles += skipped / 2;
+            },
+            Outcome::Jump(target) => {
+                if is_conditional_branch(inst.opcode) {
+                    cycles += 1;
+                }
+                self.memory.set_pc(target & PC_MASK);
+            },
+            Outcome::Call { target, ret, fast } => {
+                self.push(addr, ret)?;
+                if fast {
+                    self.save_shadow();
+                }
+                self.memory.set_pc(target & PC_MASK & !1);
+            },
+            Outcome::Return { fast, enable_interrupts } => {
+                let ret = self.pop(addr)?;
+                self.memory.set_pc(ret);
+                if fast {
+                    self.restore_shadow();
+                }
+                if enable_interrupts {
+                    InterruptController::retfie(&mut self.memory);
+                }
+            },
+            Outcome::Push(ret) => {
+                self.push(addr, ret)?;
+            },
+            Outcome::Pop => {
+                self.pop(addr)?;
+            },
+            Outcome::Sleep => {
+                self.sleeping = true;
+                self.cycles += cycles as u64;
+                return Err(Stop::Sleep);
+            },
+            Outcome::ClearWatchdog => {},
+            Outcome::Reset => {
+                self.cycles += cycles as u64;
+                self.reset();
+                return Err(Stop::Reset);
+            },
+            Outcome::Undefined => {
+                self.memory.set_pc(addr);
+                return Err(Stop::Undefined(addr));
+            },
+        }
+        if let Some(target) = self.memory.take_pc_write() {
+            self.memory.set_pc(target & PC_MASK & !1);
+            cycles += 1;
+        }
+        // the core waits out a flash erase or write, though peripherals don't
+        cycles += self.nvm.take_stall();
+        self.cycles += cycles as u64;
+        for peripheral in self.peripherals.iter_mut() {
+            peripheral.tick(cycles, &mut self.memory);
+        }
+        self.nvm.tick(cycles, &mut self.memory);
+        Ok(Step { addr, instruction: inst, cycles, interrupt })
+    }
+
+    // run until something stops the core, or `max_cycles` more cycles have passed.
+    pub fn run(&mut self, max_cycles: u64) -> Stop {
+        let limit = self.cycles + max_cycles;
+        while self.cycles < limit {
+            if let Err(stop) = self.step() {
+                return stop;
+            }
+        }
+        Stop::CycleLimit
+    }
+
+    // `run`, stopping before the instruction at `addr` runs.
+    pub fn run_until(&mut self, addr: u32, max_cycles: u64) -> Stop {
+        let limit = self.cycles + max_cycles;
+        while self.cycles < limit {
+            if self.pc() == addr {
+                return Stop::Breakpoint(addr);
+            }
+            if let Err(stop) = self.step() {
+                return stop;
+            }
+        }
+        Stop::CycleLimit
+    }
+
+    // call the routine at `addr` as CALL would, and run until it returns. the cycles it took, or
+    // whatever stopped it first.
+    pub fn call(&mut self, addr: u32, max_cycles: u64) -> Result<u64, Stop> {
+        let start = self.cycles;
+        let depth = self.stack().depth();
+        self.push(self.pc(), CALL_SENTINEL)?;
+        self.set_pc(addr);
+        let limit = start + max_cycles;
+        while self.cycles < limit {
+            self.step()?;
+            if self.stack().depth() == depth && self.pc() == CALL_SENTINEL {
+                return Ok(self.cycles - start);
+            }
+        }
+        Err(Stop::CycleLimit)
+    }
+}
+
+fn answers(peripheral: &dyn Peripheral, addr: u16) -> bool {
+    peripheral.addresses().iter().any(|range| range.contains(&addr))
+}
+
+// the attached peripheral answering for `addr`, or flash and EEPROM if none does.
+fn peripheral_at<'a>(peripherals: &'a mut [Box<dyn Peripheral>], nvm: &'a mut Nvm, addr: u16) -> Option<&'a mut dyn Peripheral> {
+    match peripherals.iter_mut().find(|peripheral| answers(peripheral.as_ref(), addr)) {
+        Some(peripheral) => Some(peripheral.as_mut()),
+        None if answers(nvm, addr) => Some(nvm),
+        None => None
+    }
+}
+
+fn is_conditional_branch(opcode: Opcode) -> bool {
+    matches!(opcode,
+        Opcode::BZ | Opcode::BNZ | Opcode::BC | Opcode::BNC |
+        Opcode::BOV | Opcode::BNOV | Opcode::BN | Opcode::BNN)
+}
+
+// cycles an instruction takes when it doesn't branch or skip.
+fn base_cycles(inst: &Instruction) -> u32 {
+    match inst.opcode {
+        Opcode::MOVFF |
+        Opcode::MOVSF |
+        Opcode::MOVSD |
+        Opcode::CALL |
+        Opcode::CALL_FAST |
+        Opcode::LFSR |
+        Opcode::GOTO |
+        Opcode::BRA |
+        Opcode::RCALL |
+        Opcode::CALLW |
+        Opcode::RETURN |
+        Opcode::RETURN_FAST |
+        Opcode::RETFIE |
+        Opcode::RETFIE_FAST |
+        Opcode::RETLW |
+        Opcode::TBLRD_S |
+        Opcode::TBLRD_S_I |
+        Opcode::TBLRD_S_D |
+        Opcode::TBLRD_I_S |
+        Opcode::TBLWT_S |
+        Opcode::TBLWT_S_I |
+        Opcode::TBLWT_S_D |
+        Opcode::TBLWT_I_S => 2,
+        _ => 1
+    }
+}
+
+impl Machine for Cpu {
+    fn reg(&self, reg: Reg) -> u32 {
+        match reg {
+            Reg::W => self.memory.peek(WREG) as u32,
+            Reg::Bsr => (self.memory.peek(BSR) & 0x0f) as u32,
+            Reg::ProdL => self.memory.peek(PRODL) as u32,
+            Reg::ProdH => self.memory.peek(PRODH) as u32,
+            Reg::Tablat => self.memory.peek(TABLAT) as u32,
+            Reg::Tblptr => {
+                (self.memory.peek(TBLPTRL) as u32) |
+                    ((self.memory.peek(TBLPTRH) as u32) << 8) |
+                    ((self.memory.peek(TBLPTRU) as u32 & 0x3f) << 16)
+            }
+        }
+    }
+
+    fn set_reg(&mut self, reg: Reg, value: u32) {
+        match reg {
+            Reg::W => { self.memory.poke(WREG, value as u8); },
+            Reg::Bsr => { self.memory.poke(BSR, value as u8 & 0x0f); },
+            Reg::ProdL => { self.memory.poke(PRODL, value as u8); },
+            Reg::ProdH => { self.memory.poke(PRODH, value as u8); },
+            Reg::Tablat => { self.memory.poke(TABLAT, value as u8); },
+            Reg::Tblptr => {
+                self.memory.poke(TBLPTRL, value as u8);
+                self.memory.poke(TBLPTRH, (value >> 8) as u8);
+                self.memory.poke(TBLPTRU, (value >> 16) as u8 & 0x3f);
+            }
+        }
+    }
+
+    fn flag(&self, flag: Flag) -> bool {
+        self.memory.peek(STATUS) & (1 << flag.bit()) != 0
+    }
+
+    fn set_flag(&mut self, flag: Flag, value: bool) {
+        let status = self.memory.peek(STATUS);
+        let bit = 1 << flag.bit();
+        self.memory.poke(STATUS, if value { status | bit } else { status & !bit });
+    }
+
+    fn load(&mut self, addr: u16) -> u8 {
+        let addr = match self.memory.resolve(addr) {
+            Some(addr) => addr,
+            None => { return 0; }
+        };
+        match peripheral_at(&mut self.peripherals, &mut self.nvm, addr) {
+            Some(peripheral) => peripheral.read(addr, &mut self.memory),
+            None => self.memory.read_direct(addr)
+        }
+    }
+
+    fn store(&mut self, addr: u16, value: u8) {
+        let addr = match self.memory.resolve(addr) {
+            Some(addr) => addr,
+            None => { return; }
+        };
+        match peripheral_at(&mut self.peripherals, &mut self.nvm, addr) {
+            Some(peripheral) => peripheral.write(addr, value, &mut self.memory),
+            None => self.memory.write_direct(addr, value)
+        }
+    }
+
+    fn program_load(&mut self, addr: u32) -> u8 {
+        self.program_byte(addr & 0x3f_ffff)
+    }
+
+    // table writes only fill the holding registers: setting WR in EECON1 is what programs flash.
+    fn program_store(&mut self, addr: u32, value: u8) {
+        self.nvm.table_write(addr & 0x3f_ffff, value);
+    }
+}
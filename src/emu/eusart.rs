@@ -0,0 +1,254 @@
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::ops::RangeInclusive;
+
+use crate::consts;
+use crate::emu::interrupt;
+use crate::emu::memory::DataMemory;
+use crate::emu::peripheral::Peripheral;
+
+const RCSTA: u16 = consts::SFR_BASE + consts::SFRS::RCSTA;
+const TXSTA: u16 = consts::SFR_BASE + consts::SFRS::TXSTA;
+const TXREG: u16 = consts::SFR_BASE + consts::SFRS::TXREG;
+const RCREG: u16 = consts::SFR_BASE + consts::SFRS::RCREG;
+const SPBRG: u16 = consts::SFR_BASE + consts::SFRS::SPBRG;
+const SPBRGH: u16 = consts::SFR_BASE + consts::SFRS::SPBRGH;
+const BAUDCON: u16 = consts::SFR_BASE + consts::SFRS::BAUDCON;
+
+const ADDRESSES: [RangeInclusive<u16>; 2] = [RCSTA..=SPBRGH, BAUDCON..=BAUDCON];
+
+// TXSTA bits
+const TX9: u8 = 6;
+const TXEN: u8 = 5;
+const BRGH: u8 = 2;
+const TRMT: u8 = 1;
+// RCSTA bits
+const SPEN: u8 = 7;
+const RX9: u8 = 6;
+const CREN: u8 = 4;
+const OERR: u8 = 1;
+// BAUDCON bits
+const RCIDL: u8 = 6;
+const BRG16: u8 = 3;
+
+// received bytes RCREG holds before the receiver overruns
+const FIFO_DEPTH: usize = 2;
+
+// the EUSART in asynchronous mode, its line being a host byte stream each way: what firmware
+// transmits is written to `output`, and `input` supplies what it receives. bytes take a frame
+// time at the configured baud rate each way, and TXIF and RCIF follow TXREG and RCREG.
+//
+// `input` is read whenever the receiver is idle; a read that returns nothing, or fails, leaves
+// the line idle until next time, and a reader that blocks holds up emulation with it. the host
+// sees eight bits of a nine bit frame and sends a zero ninth bit, and there are never framing
+// errors.
+pub struct Eusart {
+    txsta: u8,
+    rcsta: u8,
+    baudcon: u8,
+    spbrg: u8,
+    spbrgh: u8,
+    txreg: Option<u8>,
+    // the byte in the transmit shift register, and oscillator periods until it's out
+    shifting: Option<(u8, u32)>,
+    // the byte coming in, and oscillator periods until it's all in
+    receiving: Option<(u8, u32)>,
+    rcreg: VecDeque<u8>,
+    overrun: bool,
+    input: Box<dyn Read>,
+    output: Box<dyn Write>,
+}
+
+impl Eusart {
+    pub fn new<R: Read + 'static, W: Write + 'static>(input: R, output: W) -> Eusart {
+        Eusart {
+            txsta: 1 << TRMT,
+            rcsta: 0,
+            baudcon: 1 << RCIDL,
+            spbrg: 0,
+            spbrgh: 0,
+            txreg: None,
+            shifting: None,
+            receiving: None,
+            rcreg: VecDeque::with_capacity(FIFO_DEPTH),
+            overrun: false,
+            input: Box::new(input),
+            output: Box::new(output),
+        }
+    }
+
+    // oscillator periods per bit: Fosc / (k * (n + 1)) baud, k being 64, 16 or 4 as BRGH and
+    // BRG16 say, and n SPBRG, or SPBRGH:SPBRG with BRG16.
+    pub fn bit_periods(&self) -> u32 {
+        let brgh = self.txsta & (1 << BRGH) != 0;
+        let brg16 = self.baudcon & (1 << BRG16) != 0;
+        let k = match (brg16, brgh) {
+            (false, false) => 64,
+            (false, true) | (true, false) => 16,
+            (true, true) => 4,
+        };
+        let n = if brg16 {
+            ((self.spbrgh as u32) << 8) | self.spbrg as u32
+        } else {
+            self.spbrg as u32
+        };
+        k * (n + 1)
+    }
+
+    // the baud rate with an `fosc` Hz oscillator.
+    pub fn baud(&self, fosc: u32) -> u32 {
+        fosc / self.bit_periods()
+    }
+
+    // start, data and stop bits
+    fn frame_periods(&self, nine_bit: bool) -> u32 {
+        self.bit_periods() * if nine_bit { 11 } else { 10 }
+    }
+
+    fn transmitting(&self) -> bool {
+        self.rcsta & (1 << SPEN) != 0 && self.txsta & (1 << TXEN) != 0
+    }
+
+    fn receiving_enabled(&self) -> bool {
+        self.rcsta & (1 << SPEN) != 0 && self.rcsta & (1 << CREN) != 0 && !self.overrun
+    }
+
+    fn load_shift_register(&mut self) {
+        if self.shifting.is_none() && self.transmitting() {
+            if let Some(byte) = self.txreg.take() {
+                self.shifting = Some((byte, self.frame_periods(self.txsta & (1 << TX9) != 0)));
+            }
+        }
+    }
+
+    fn next_input(&mut self) -> Option<u8> {
+        let mut byte = [0u8];
+        match self.input.read(&mut byte) {
+            Ok(1) => Some(byte[0]),
+            _ => None
+        }
+    }
+
+    // TXIF says TXREG has room, RCIF that RCREG has something
+    fn update_flags(&self, memory: &mut DataMemory) {
+        if self.transmitting() {
+            interrupt::TX.flag.set(memory, self.txreg.is_none());
+        }
+        interrupt::RC.flag.set(memory, !self.rcreg.is_empty());
+    }
+}
+
+impl Peripheral for Eusart {
+    fn name(&self) -> &'static str {
+        "EUSART"
+    }
+
+    fn addresses(&self) -> &[RangeInclusive<u16>] {
+        &ADDRESSES
+    }
+
+    fn read(&mut self, addr: u16, memory: &mut DataMemory) -> u8 {
+        if addr == RCREG {
+            let byte = self.rcreg.pop_front().unwrap_or(0);
+            self.update_flags(memory);
+            return byte;
+        }
+        self.peek(addr)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            RCSTA => self.rcsta | ((self.overrun as u8) << OERR),
+            TXSTA => {
+                let idle = self.shifting.is_none();
+                (self.txsta & !(1 << TRMT)) | ((idle as u8) << TRMT)
+            },
+            TXREG => self.txreg.unwrap_or(0),
+            RCREG => self.rcreg.front().cloned().unwrap_or(0),
+            SPBRG => self.spbrg,
+            SPBRGH => self.spbrgh,
+            BAUDCON => {
+                let idle = self.receiving.is_none();
+                (self.baudcon & !(1 << RCIDL)) | ((idle as u8) << RCIDL)
+            },
+            _ => 0
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8, memory: &mut DataMemory) {
+        match addr {
+            RCSTA => {
+                self.rcsta = value & !(1 << OERR);
+                // clearing CREN is how firmware gets out of an overrun
+                if value & (1 << CREN) == 0 {
+                    self.overrun = false;
+                }
+                if value & (1 << SPEN) == 0 {
+                    self.receiving = None;
+                    self.shifting = None;
+                }
+            },
+            TXSTA => {
+                self.txsta = value;
+                if value & (1 << TXEN) == 0 {
+                    self.shifting = None;
+                }
+            },
+            TXREG => { self.txreg = Some(value); },
+            SPBRG => { self.spbrg = value; },
+            SPBRGH => { self.spbrgh = value; },
+            BAUDCON => { self.baudcon = value; },
+            _ => {}
+        }
+        self.load_shift_register();
+        self.update_flags(memory);
+    }
+
+    fn tick(&mut self, cycles: u32, memory: &mut DataMemory) {
+        let periods = cycles * 4;
+
+        if let Some((byte, left)) = self.shifting {
+            if left > periods {
+                self.shifting = Some((byte, left - periods));
+            } else {
+                // a host that won't take the byte loses it, as a disconnected line would
+                let _ = self.output.write_all(&[byte]).and_then(|()| self.output.flush());
+                self.shifting = None;
+            }
+        }
+        self.load_shift_register();
+
+        if self.receiving.is_none() && self.receiving_enabled() {
+            if let Some(byte) = self.next_input() {
+                self.receiving = Some((byte, self.frame_periods(self.rcsta & (1 << RX9) != 0)));
+            }
+        }
+        if let Some((byte, left)) = self.receiving {
+            if left > periods {
+                self.receiving = Some((byte, left - periods));
+            } else {
+                self.receiving = None;
+                if self.rcreg.len() == FIFO_DEPTH {
+                    self.overrun = true;
+                } else {
+                    self.rcreg.push_back(byte);
+                }
+            }
+        }
+
+        self.update_flags(memory);
+    }
+
+    fn reset(&mut self) {
+        self.txsta = 1 << TRMT;
+        self.rcsta = 0;
+        self.baudcon = 1 << RCIDL;
+        self.spbrg = 0;
+        self.spbrgh = 0;
+        self.txreg = None;
+        self.shifting = None;
+        self.receiving = None;
+        self.rcreg.clear();
+        self.overrun = false;
+    }
+}
@@ -0,0 +1,208 @@
+use crate::consts;
+use crate::analysis::isr::Priority;
+use crate::emu::memory::DataMemory;
+
+const INTCON: u16 = consts::SFR_BASE + consts::SFRS::INTCON;
+const INTCON2: u16 = consts::SFR_BASE + consts::SFRS::INTCON2;
+const INTCON3: u16 = consts::SFR_BASE + consts::SFRS::INTCON3;
+const PIE1: u16 = consts::SFR_BASE + consts::SFRS::PIE1;
+const PIR1: u16 = consts::SFR_BASE + consts::SFRS::PIR1;
+const IPR1: u16 = consts::SFR_BASE + consts::SFRS::IPR1;
+const PIE2: u16 = consts::SFR_BASE + consts::SFRS::PIE2;
+const PIR2: u16 = consts::SFR_BASE + consts::SFRS::PIR2;
+const IPR2: u16 = consts::SFR_BASE + consts::SFRS::IPR2;
+const RCON: u16 = consts::SFR_BASE + consts::SFRS::RCON;
+
+// INTCON bits. without IPEN these are GIE and PEIE.
+const GIEH: u8 = 7;
+const GIEL: u8 = 6;
+// RCON bits
+const IPEN: u8 = 7;
+
+// one bit of an SFR.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Bit {
+    pub addr: u16,
+    pub bit: u8,
+}
+
+impl Bit {
+    pub const fn new(addr: u16, bit: u8) -> Bit {
+        Bit { addr, bit }
+    }
+
+    pub fn get(&self, memory: &DataMemory) -> bool {
+        memory.peek(self.addr) & (1 << self.bit) != 0
+    }
+
+    pub fn set(&self, memory: &mut DataMemory, value: bool) {
+        let byte = memory.peek(self.addr);
+        if value {
+            memory.poke(self.addr, byte | (1 << self.bit));
+        } else {
+            memory.poke(self.addr, byte & !(1 << self.bit));
+        }
+    }
+}
+
+// something that can interrupt: a flag it sets, the bit enabling it, and the bit giving it
+// high priority.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Source {
+    pub name: &'static str,
+    pub flag: Bit,
+    pub enable: Bit,
+    // `None` for INT0, which is always high priority
+    pub priority: Option<Bit>,
+    // peripheral interrupts also need PEIE when priorities are off
+    pub peripheral: bool,
+}
+
+impl Source {
+    const fn core(name: &'static str, flag: Bit, enable: Bit, priority: Option<Bit>) -> Source {
+        Source { name, flag, enable, priority, peripheral: false }
+    }
+
+    // a peripheral interrupt at the same bit of a PIRn, PIEn and IPRn.
+    pub const fn peripheral(name: &'static str, pir: u16, pie: u16, ipr: u16, bit: u8) -> Source {
+        Source {
+            name,
+            flag: Bit::new(pir, bit),
+            enable: Bit::new(pie, bit),
+            priority: Some(Bit::new(ipr, bit)),
+            peripheral: true,
+        }
+    }
+
+    // set the interrupt flag, as the hardware behind it does.
+    pub fn raise(&self, memory: &mut DataMemory) {
+        self.flag.set(memory, true);
+    }
+
+    pub fn clear(&self, memory: &mut DataMemory) {
+        self.flag.set(memory, false);
+    }
+
+    // flagged and enabled, whether or not anything lets it through.
+    pub fn requesting(&self, memory: &DataMemory) -> bool {
+        self.flag.get(memory) && self.enable.get(memory)
+    }
+
+    pub fn priority(&self, memory: &DataMemory) -> Priority {
+        match self.priority {
+            Some(bit) if !bit.get(memory) => Priority::Low,
+            _ => Priority::High,
+        }
+    }
+}
+
+pub const INT0: Source = Source::core("INT0", Bit::new(INTCON, 1), Bit::new(INTCON, 4), None);
+pub const INT1: Source = Source::core("INT1", Bit::new(INTCON3, 0), Bit::new(INTCON3, 3), Some(Bit::new(INTCON3, 6)));
+pub const INT2: Source = Source::core("INT2", Bit::new(INTCON3, 1), Bit::new(INTCON3, 4), Some(Bit::new(INTCON3, 7)));
+pub const TMR0: Source = Source::core("TMR0", Bit::new(INTCON, 2), Bit::new(INTCON, 5), Some(Bit::new(INTCON2, 2)));
+pub const RB: Source = Source::core("RB", Bit::new(INTCON, 0), Bit::new(INTCON, 3), Some(Bit::new(INTCON2, 0)));
+
+pub const TMR1: Source = Source::peripheral("TMR1", PIR1, PIE1, IPR1, 0);
+pub const TMR2: Source = Source::peripheral("TMR2", PIR1, PIE1, IPR1, 1);
+pub const CCP1: Source = Source::peripheral("CCP1", PIR1, PIE1, IPR1, 2);
+pub const SSP: Source = Source::peripheral("SSP", PIR1, PIE1, IPR1, 3);
+pub const TX: Source = Source::peripheral("TX", PIR1, PIE1, IPR1, 4);
+pub const RC: Source = Source::peripheral("RC", PIR1, PIE1, IPR1, 5);
+pub const AD: Source = Source::peripheral("AD", PIR1, PIE1, IPR1, 6);
+pub const CCP2: Source = Source::peripheral("CCP2", PIR2, PIE2, IPR2, 0);
+pub const TMR3: Source = Source::peripheral("TMR3", PIR2, PIE2, IPR2, 1);
+pub const BCL: Source = Source::peripheral("BCL", PIR2, PIE2, IPR2, 3);
+pub const EE: Source = Source::peripheral("EE", PIR2, PIE2, IPR2, 4);
+
+// the sources every part here has at the same bits.
+pub const SOURCES: [Source; 16] = [
+    INT0, INT1, INT2, TMR0, RB,
+    TMR1, TMR2, CCP1, SSP, TX, RC, AD, CCP2, TMR3, BCL, EE,
+];
+
+// works out, from INTCON, RCON and the sources' flags, which interrupt the core takes next. all
+// of its state is in data memory: sources set flags there, and firmware clears them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterruptController {
+    sources: Vec<Source>,
+}
+
+impl Default for InterruptController {
+    fn default() -> InterruptController {
+        InterruptController::new()
+    }
+}
+
+impl InterruptController {
+    pub fn new() -> InterruptController {
+        InterruptController { sources: SOURCES.to_vec() }
+    }
+
+    // a source some part or peripheral model has beyond `SOURCES`.
+    pub fn add(&mut self, source: Source) {
+        self.sources.push(source);
+    }
+
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+
+    pub fn source(&self, name: &str) -> Option<&Source> {
+        self.sources.iter().find(|source| source.name == name)
+    }
+
+    pub fn priorities_enabled(memory: &DataMemory) -> bool {
+        Bit::new(RCON, IPEN).get(memory)
+    }
+
+    // whether any source is flagged and enabled, which wakes a sleeping core whatever GIEH and
+    // GIEL say.
+    pub fn requesting(&self, memory: &DataMemory) -> bool {
+        self.sources.iter().any(|source| source.requesting(memory))
+    }
+
+    // the interrupt the core would take now, if any. with priorities off everything goes to the
+    // high priority vector, gated by GIE and, for peripherals, PEIE; with them on, high priority
+    // sources need GIEH and low priority ones GIEH and GIEL.
+    pub fn pending(&self, memory: &DataMemory) -> Option<Priority> {
+        let gieh = Bit::new(INTCON, GIEH).get(memory);
+        let giel = Bit::new(INTCON, GIEL).get(memory);
+        if !gieh {
+            return None;
+        }
+        let requesting = self.sources.iter().filter(|source| source.requesting(memory));
+        if !InterruptController::priorities_enabled(memory) {
+            return requesting.filter(|source| giel || !source.peripheral).map(|_| Priority::High).next();
+        }
+        let mut pending = None;
+        for source in requesting {
+            match source.priority(memory) {
+                Priority::High => { return Some(Priority::High); },
+                Priority::Low if giel => { pending = Some(Priority::Low); },
+                Priority::Low => {},
+            }
+        }
+        pending
+    }
+
+    // what taking an interrupt does to INTCON: the enable that let it through is cleared, so
+    // only a high priority interrupt can come in on top of a low one.
+    pub fn enter(memory: &mut DataMemory, priority: Priority) {
+        let bit = match priority {
+            Priority::Low if InterruptController::priorities_enabled(memory) => GIEL,
+            _ => GIEH,
+        };
+        Bit::new(INTCON, bit).set(memory, false);
+    }
+
+    // RETFIE sets whichever enable the interrupt cleared: GIEH first, GIEL once that's set and
+    // priorities are on.
+    pub fn retfie(memory: &mut DataMemory) {
+        let bit = if InterruptController::priorities_enabled(memory) && Bit::new(INTCON, GIEH).get(memory) {
+            GIEL
+        } else {
+            GIEH
+        };
+        Bit::new(INTCON, bit).set(memory, true);
+    }
+}
@@ -0,0 +1,282 @@
+use crate::consts;
+use crate::analysis::fsr::{fsr_register, is_indirect, FsrRegister, IndirectMode};
+use crate::device::Device;
+use crate::emu::stack::ReturnStack;
+
+const WREG: u16 = consts::SFR_BASE + consts::SFRS::WREG;
+const STATUS: u16 = consts::SFR_BASE + consts::SFRS::STATUS;
+const BSR: u16 = consts::SFR_BASE + consts::SFRS::BSR;
+const PCL: u16 = consts::SFR_BASE + consts::SFRS::PCL;
+const PCLATH: u16 = consts::SFR_BASE + consts::SFRS::PCLATH;
+const PCLATU: u16 = consts::SFR_BASE + consts::SFRS::PCLATU;
+const STKPTR: u16 = consts::SFR_BASE + consts::SFRS::STKPTR;
+const TOSL: u16 = consts::SFR_BASE + consts::SFRS::TOSL;
+const TOSH: u16 = consts::SFR_BASE + consts::SFRS::TOSH;
+const TOSU: u16 = consts::SFR_BASE + consts::SFRS::TOSU;
+const TBLPTRU: u16 = consts::SFR_BASE + consts::SFRS::TBLPTRU;
+
+// SFRs a reset sets, and what to. the interrupt registers are here so nothing is left pending
+// from before.
+const RESET_VALUES: [(u16, u8); 17] = [
+    (BSR, 0),
+    (STATUS, 0),
+    (PCLATH, 0),
+    (PCLATU, 0),
+    (consts::SFR_BASE + consts::SFRS::TBLPTRL, 0),
+    (consts::SFR_BASE + consts::SFRS::TBLPTRH, 0),
+    (TBLPTRU, 0),
+    (consts::SFR_BASE + consts::SFRS::INTCON, 0x00),
+    (consts::SFR_BASE + consts::SFRS::INTCON2, 0xf5),
+    (consts::SFR_BASE + consts::SFRS::INTCON3, 0xc0),
+    (consts::SFR_BASE + consts::SFRS::PIE1, 0x00),
+    (consts::SFR_BASE + consts::SFRS::PIR1, 0x00),
+    (consts::SFR_BASE + consts::SFRS::IPR1, 0xff),
+    (consts::SFR_BASE + consts::SFRS::PIE2, 0x00),
+    (consts::SFR_BASE + consts::SFRS::PIR2, 0x00),
+    (consts::SFR_BASE + consts::SFRS::IPR2, 0xff),
+    // IPEN clear
+    (consts::SFR_BASE + consts::SFRS::RCON, 0x1c),
+];
+
+// the data address space as instructions see it: RAM, the SFRs, and the core state that shows
+// through them. PC and the return stack are kept here because PCL, STKPTR and TOSU:TOSH:TOSL
+// are views of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataMemory {
+    bytes: Vec<u8>,
+    access_split: u8,
+    // bytes of RAM from address 0. the rest up to the SFRs isn't there: it reads as zero and
+    // ignores writes.
+    ram: u16,
+    pc: u32,
+    stack: ReturnStack,
+    // where a write to PCL sends PC, once the instruction doing it finishes
+    pc_write: Option<u32>,
+}
+
+impl Default for DataMemory {
+    fn default() -> DataMemory {
+        DataMemory::new()
+    }
+}
+
+impl DataMemory {
+    // every address below the SFRs is RAM, and the access bank splits as `consts::access_address`
+    // has it.
+    pub fn new() -> DataMemory {
+        DataMemory {
+            bytes: vec![0; 0x1000],
+            access_split: 0x80,
+            ram: consts::SFR_BASE,
+            pc: 0,
+            stack: ReturnStack::default(),
+            pc_write: None,
+        }
+    }
+
+    // memory as `device` lays it out.
+    pub fn for_device(device: &Device) -> DataMemory {
+        DataMemory {
+            access_split: device.access_split,
+            ram: device.data_memory.min(consts::SFR_BASE),
+            ..DataMemory::new()
+        }
+    }
+
+    // what a reset does: PC to zero, an empty stack, and the SFRs with a defined reset value set
+    // to it. RAM keeps its contents, and STKPTR its fault flags.
+    pub fn reset(&mut self) {
+        self.pc = 0;
+        self.stack.reset();
+        self.pc_write = None;
+        for (addr, value) in RESET_VALUES.iter() {
+            self.bytes[*addr as usize] = *value;
+        }
+    }
+
+    // where the access bank splits between bank 0 RAM and the SFRs, for lifting instructions
+    // with `ir::lift_split`.
+    pub fn access_split(&self) -> u8 {
+        self.access_split
+    }
+
+    pub fn pc(&self) -> u32 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u32) {
+        self.pc = pc;
+    }
+
+    // the jump a write to PCL asked for, PCLATU:PCLATH:value, which is then forgotten.
+    pub fn take_pc_write(&mut self) -> Option<u32> {
+        self.pc_write.take()
+    }
+
+    pub fn stack(&self) -> &ReturnStack {
+        &self.stack
+    }
+
+    pub fn stack_mut(&mut self) -> &mut ReturnStack {
+        &mut self.stack
+    }
+
+    pub fn w(&self) -> u8 {
+        self.bytes[WREG as usize]
+    }
+
+    pub fn set_w(&mut self, value: u8) {
+        self.bytes[WREG as usize] = value;
+    }
+
+    pub fn fsr(&self, fsr: usize) -> u16 {
+        let (low, high) = fsr_addresses(fsr);
+        ((self.bytes[high as usize] as u16) << 8) | self.bytes[low as usize] as u16
+    }
+
+    pub fn set_fsr(&mut self, fsr: usize, value: u16) {
+        let (low, high) = fsr_addresses(fsr);
+        self.bytes[low as usize] = value as u8;
+        self.bytes[high as usize] = (value >> 8) as u8 & 0x0f;
+    }
+
+    // the address an indirect register accesses, moving its pointer as the access does.
+    fn indirect(&mut self, fsr: usize, mode: IndirectMode) -> u16 {
+        let pointer = self.fsr(fsr);
+        match mode {
+            IndirectMode::Indf => pointer,
+            IndirectMode::PostInc => {
+                self.set_fsr(fsr, pointer.wrapping_add(1) & 0xfff);
+                pointer
+            },
+            IndirectMode::PostDec => {
+                self.set_fsr(fsr, pointer.wrapping_sub(1) & 0xfff);
+                pointer
+            },
+            IndirectMode::PreInc => {
+                let pointer = pointer.wrapping_add(1) & 0xfff;
+                self.set_fsr(fsr, pointer);
+                pointer
+            },
+            // W is signed here
+            IndirectMode::PlusW => pointer.wrapping_add(self.w() as i8 as u16) & 0xfff,
+        }
+    }
+
+    // the address an access to `addr` reaches: the pointer's target for indirect registers, which
+    // move it as the access does. `None` for an indirect register reached through a pointer, which
+    // reads as zero and ignores writes.
+    pub fn resolve(&mut self, addr: u16) -> Option<u16> {
+        let addr = addr & 0xfff;
+        if let Some((fsr, FsrRegister::Indirect(mode))) = fsr_register(addr) {
+            let target = self.indirect(fsr, mode);
+            return if is_indirect(target) { None } else { Some(target) };
+        }
+        Some(addr)
+    }
+
+    // the byte at `addr`, with whatever reading it does to the machine: indirect registers access
+    // through their pointer and move it, and reading PCL latches the rest of PC into PCLATH and
+    // PCLATU.
+    pub fn read(&mut self, addr: u16) -> u8 {
+        match self.resolve(addr) {
+            Some(addr) => self.read_direct(addr),
+            None => 0
+        }
+    }
+
+    // `read`, for an address `resolve` has already gone through.
+    pub fn read_direct(&mut self, addr: u16) -> u8 {
+        if addr == PCL {
+            self.bytes[PCLATH as usize] = (self.pc >> 8) as u8;
+            self.bytes[PCLATU as usize] = (self.pc >> 16) as u8 & 0x1f;
+        }
+        self.peek_direct(addr)
+    }
+
+    // the byte a read of `addr` would see, without moving pointers or latching PC. an indirect
+    // register shows the byte its pointer addresses.
+    pub fn peek(&self, addr: u16) -> u8 {
+        let addr = addr & 0xfff;
+        if let Some((fsr, FsrRegister::Indirect(mode))) = fsr_register(addr) {
+            let pointer = self.fsr(fsr);
+            let target = match mode {
+                IndirectMode::PreInc => pointer.wrapping_add(1) & 0xfff,
+                IndirectMode::PlusW => pointer.wrapping_add(self.w() as i8 as u16) & 0xfff,
+                _ => pointer
+            };
+            return if is_indirect(target) { 0 } else { self.peek_direct(target) };
+        }
+        self.peek_direct(addr)
+    }
+
+    fn peek_direct(&self, addr: u16) -> u8 {
+        let tos = self.stack.tos();
+        match addr {
+            PCL => self.pc as u8,
+            STKPTR => self.stack.stkptr(),
+            TOSL => tos as u8,
+            TOSH => (tos >> 8) as u8,
+            TOSU => (tos >> 16) as u8,
+            _ if addr >= self.ram && addr < consts::SFR_BASE => 0,
+            _ => self.bytes[addr as usize]
+        }
+    }
+
+    // store `value` at `addr` as an instruction would: through indirect registers, into the top
+    // of the stack for TOSx, and for PCL, as a jump once the instruction is done.
+    pub fn write(&mut self, addr: u16, value: u8) {
+        if let Some(addr) = self.resolve(addr) {
+            self.write_direct(addr, value);
+        }
+    }
+
+    // `write`, for an address `resolve` has already gone through.
+    pub fn write_direct(&mut self, addr: u16, value: u8) {
+        if addr == PCL {
+            let high = ((self.bytes[PCLATU as usize] as u32 & 0x1f) << 16) | ((self.bytes[PCLATH as usize] as u32) << 8);
+            self.pc_write = Some(high | value as u32);
+        }
+        self.poke(addr, value);
+    }
+
+    // set the byte at `addr` without indirection or jumps, as a debugger would. registers with
+    // unimplemented bits keep them clear.
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        let addr = addr & 0xfff;
+        let (shift, mask) = match addr {
+            TOSL => (0, 0xff),
+            TOSH => (8, 0xff),
+            TOSU => (16, 0x1f),
+            STKPTR => {
+                self.stack.set_stkptr(value);
+                return;
+            },
+            _ => {
+                if addr >= self.ram && addr < consts::SFR_BASE {
+                    return;
+                }
+                let mask = match addr {
+                    BSR => 0x0f,
+                    STATUS | PCLATU => 0x1f,
+                    TBLPTRU => 0x3f,
+                    _ if matches!(fsr_register(addr), Some((_, FsrRegister::High))) => 0x0f,
+                    _ => 0xff
+                };
+                self.bytes[addr as usize] = value & mask;
+                return;
+            }
+        };
+        let tos = self.stack.tos();
+        self.stack.set_tos((tos & !(0xff << shift)) | (((value & mask) as u32) << shift));
+    }
+}
+
+fn fsr_addresses(fsr: usize) -> (u16, u16) {
+    let (low, high) = [
+        (consts::SFRS::FSR0L, consts::SFRS::FSR0H),
+        (consts::SFRS::FSR1L, consts::SFRS::FSR1H),
+        (consts::SFRS::FSR2L, consts::SFRS::FSR2H),
+    ][fsr];
+    (consts::SFR_BASE + low, consts::SFR_BASE + high)
+}
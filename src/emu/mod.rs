@@ -0,0 +1,11 @@
+// running code rather than reading it: a model of the PIC18 core that executes decoded
+// instructions through `ir`, counting cycles as it goes.
+
+pub mod cpu;
+pub mod eusart;
+pub mod interrupt;
+pub mod memory;
+pub mod nvm;
+pub mod peripheral;
+pub mod stack;
+pub mod timer0;
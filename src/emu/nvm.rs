@@ -0,0 +1,309 @@
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use crate::consts;
+use crate::device::{self, Device};
+use crate::emu::interrupt;
+use crate::emu::memory::DataMemory;
+use crate::emu::peripheral::Peripheral;
+
+const EECON1: u16 = consts::SFR_BASE + consts::SFRS::EECON1;
+const EECON2: u16 = consts::SFR_BASE + consts::SFRS::EECON2;
+const EEDATA: u16 = consts::SFR_BASE + consts::SFRS::EEDATA;
+const EEADR: u16 = consts::SFR_BASE + consts::SFRS::EEADR;
+const TBLPTRL: u16 = consts::SFR_BASE + consts::SFRS::TBLPTRL;
+const TBLPTRH: u16 = consts::SFR_BASE + consts::SFRS::TBLPTRH;
+const TBLPTRU: u16 = consts::SFR_BASE + consts::SFRS::TBLPTRU;
+
+const ADDRESSES: [RangeInclusive<u16>; 1] = [EECON1..=EEADR];
+
+// EECON1 bits
+const EEPGD: u8 = 7;
+const CFGS: u8 = 6;
+const FREE: u8 = 4;
+const WRERR: u8 = 3;
+const WREN: u8 = 2;
+const WR: u8 = 1;
+const RD: u8 = 0;
+
+// configuration words and device IDs, which table writes and reads here don't reach
+const CONFIG_SPACE: u32 = 0x30_0000;
+
+// typical self-timed write times, in microseconds
+const EEPROM_WRITE_US: u64 = 4000;
+const FLASH_WRITE_US: u64 = 2000;
+
+const DEFAULT_CLOCK: u32 = 4_000_000;
+
+// how far through the unlock sequence firmware is, with instruction cycles since the last step.
+// `movlw 0x55; movwf EECON2; movlw 0xaa; movwf EECON2; bsf EECON1, WR` is the only way through:
+// anything more between the steps, an interrupt say, locks it again.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Unlock {
+    Locked,
+    Wrote55(u32),
+    WroteAA(u32),
+}
+
+// program flash and data EEPROM, and the EECON registers that write them.
+//
+// table writes fill the holding registers, and a write sends them to the write block TBLPTR is
+// in; an erase clears the erase block. the core stalls while flash is busy. programming can
+// only clear bits, as on the part, so flash written without an erase keeps what was there
+// ANDed in. EEPROM writes run in the background for as long as the part takes, and both set
+// EEIF when done. configuration words can't be written.
+#[derive(Debug)]
+pub struct Nvm {
+    program: Vec<u8>,
+    eeprom: Vec<u8>,
+    erase_block: u32,
+    holding: Vec<u8>,
+    eecon1: u8,
+    eedata: u8,
+    eeadr: u8,
+    unlock: Unlock,
+    // an EEPROM write under way: the address, the byte, and cycles until it's done
+    eeprom_write: Option<(u8, u8, u32)>,
+    // cycles the core has to wait for flash
+    stall: u32,
+    clock: u32,
+    program_file: Option<PathBuf>,
+    eeprom_file: Option<PathBuf>,
+    error: Option<io::Error>,
+}
+
+impl Nvm {
+    // `image` in flash laid out as the PIC18F4550 has it.
+    pub fn new(image: &[u8]) -> Nvm {
+        Nvm::for_device(image, &device::PIC18F4550)
+    }
+
+    pub fn for_device(image: &[u8], device: &Device) -> Nvm {
+        Nvm {
+            program: image.to_vec(),
+            eeprom: vec![0xff; device.eeprom as usize],
+            erase_block: device.erase_block as u32,
+            holding: vec![0xff; device.write_block as usize],
+            eecon1: 0,
+            eedata: 0,
+            eeadr: 0,
+            unlock: Unlock::Locked,
+            eeprom_write: None,
+            stall: 0,
+            clock: DEFAULT_CLOCK,
+            program_file: None,
+            eeprom_file: None,
+            error: None,
+        }
+    }
+
+    // the oscillator frequency in Hz, which write times in instruction cycles depend on. 4MHz
+    // unless set.
+    pub fn set_clock(&mut self, clock: u32) {
+        self.clock = clock;
+    }
+
+    fn cycles(&self, us: u64) -> u32 {
+        (us * (self.clock as u64 / 4) / 1_000_000) as u32
+    }
+
+    pub fn program(&self) -> &[u8] {
+        &self.program
+    }
+
+    // flash past the image is erased.
+    pub fn program_byte(&self, addr: u32) -> u8 {
+        self.program.get(addr as usize).cloned().unwrap_or(0xff)
+    }
+
+    pub fn eeprom(&self) -> &[u8] {
+        &self.eeprom
+    }
+
+    pub fn set_eeprom(&mut self, addr: u8, value: u8) {
+        let len = self.eeprom.len();
+        self.eeprom[addr as usize % len] = value;
+    }
+
+    // TBLWT: `value` into the holding register for `addr`.
+    pub fn table_write(&mut self, addr: u32, value: u8) {
+        if addr < CONFIG_SPACE {
+            let len = self.holding.len();
+            self.holding[addr as usize % len] = value;
+        }
+    }
+
+    // cycles the core stalls for flash since last asked, which it then owes no longer.
+    pub fn take_stall(&mut self) -> u32 {
+        std::mem::take(&mut self.stall)
+    }
+
+    // an error saving to a persistence file, which is then forgotten.
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    // keep program memory in the file at `path`: if it exists its contents replace flash now,
+    // and otherwise it's created from flash. every erase and write saves it.
+    pub fn persist_program<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref().to_path_buf();
+        if path.exists() {
+            self.program = fs::read(&path)?;
+        } else {
+            fs::write(&path, &self.program)?;
+        }
+        self.program_file = Some(path);
+        Ok(())
+    }
+
+    // `persist_program`, for data EEPROM.
+    pub fn persist_eeprom<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref().to_path_buf();
+        if path.exists() {
+            let contents = fs::read(&path)?;
+            let len = contents.len().min(self.eeprom.len());
+            self.eeprom[..len].copy_from_slice(&contents[..len]);
+        } else {
+            fs::write(&path, &self.eeprom)?;
+        }
+        self.eeprom_file = Some(path);
+        Ok(())
+    }
+
+    fn save(&mut self, eeprom: bool) {
+        let (path, contents) = if eeprom {
+            (&self.eeprom_file, &self.eeprom)
+        } else {
+            (&self.program_file, &self.program)
+        };
+        if let Some(path) = path {
+            if let Err(error) = fs::write(path, contents) {
+                self.error = Some(error);
+            }
+        }
+    }
+
+    fn flash_block(&mut self, addr: u32, len: u32) -> &mut [u8] {
+        let start = (addr & !(len - 1)) as usize;
+        let end = start + len as usize;
+        if self.program.len() < end {
+            self.program.resize(end, 0xff);
+        }
+        &mut self.program[start..end]
+    }
+
+    // WR set with the unlock sequence just done and WREN set.
+    fn start_write(&mut self, memory: &mut DataMemory) {
+        if self.eecon1 & (1 << CFGS) != 0 {
+            return;
+        }
+        if self.eecon1 & (1 << EEPGD) == 0 {
+            let cycles = self.cycles(EEPROM_WRITE_US);
+            self.eeprom_write = Some((self.eeadr, self.eedata, cycles));
+            self.eecon1 |= 1 << WR;
+            return;
+        }
+        let tblptr = (memory.peek(TBLPTRL) as u32) |
+            ((memory.peek(TBLPTRH) as u32) << 8) |
+            ((memory.peek(TBLPTRU) as u32 & 0x3f) << 16);
+        if tblptr >= CONFIG_SPACE {
+            return;
+        }
+        if self.eecon1 & (1 << FREE) != 0 {
+            let erase_block = self.erase_block;
+            for byte in self.flash_block(tblptr, erase_block).iter_mut() {
+                *byte = 0xff;
+            }
+        } else {
+            let holding = std::mem::take(&mut self.holding);
+            for (byte, value) in self.flash_block(tblptr, holding.len() as u32).iter_mut().zip(holding.iter()) {
+                *byte &= value;
+            }
+            self.holding = vec![0xff; holding.len()];
+        }
+        self.stall += self.cycles(FLASH_WRITE_US);
+        interrupt::EE.raise(memory);
+        self.save(false);
+    }
+}
+
+impl Peripheral for Nvm {
+    fn name(&self) -> &'static str {
+        "NVM"
+    }
+
+    fn addresses(&self) -> &[RangeInclusive<u16>] {
+        &ADDRESSES
+    }
+
+    fn read(&mut self, addr: u16, _memory: &mut DataMemory) -> u8 {
+        self.peek(addr)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            EECON1 => self.eecon1,
+            EEDATA => self.eedata,
+            EEADR => self.eeadr,
+            // EECON2 isn't a register
+            _ => 0
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8, memory: &mut DataMemory) {
+        match addr {
+            EECON1 => {
+                let start = value & (1 << WR) != 0 && self.eecon1 & (1 << WR) == 0;
+                // software can't clear WR, and RD never stays set
+                self.eecon1 = (value & !(1 << WR | 1 << RD)) | (self.eecon1 & (1 << WR));
+                let eeprom = value & (1 << EEPGD | 1 << CFGS) == 0;
+                if value & (1 << RD) != 0 && eeprom {
+                    self.eedata = self.eeprom[self.eeadr as usize % self.eeprom.len()];
+                }
+                if start && value & (1 << WREN) != 0 && matches!(self.unlock, Unlock::WroteAA(cycles) if cycles <= 1) {
+                    self.start_write(memory);
+                }
+                self.unlock = Unlock::Locked;
+            },
+            EECON2 => {
+                self.unlock = match (self.unlock, value) {
+                    (_, 0x55) => Unlock::Wrote55(0),
+                    (Unlock::Wrote55(cycles), 0xaa) if cycles <= 2 => Unlock::WroteAA(0),
+                    _ => Unlock::Locked,
+                };
+            },
+            EEDATA => { self.eedata = value; },
+            _ => { self.eeadr = value; }
+        }
+    }
+
+    fn tick(&mut self, cycles: u32, memory: &mut DataMemory) {
+        self.unlock = match self.unlock {
+            Unlock::Wrote55(elapsed) => Unlock::Wrote55(elapsed.saturating_add(cycles)),
+            Unlock::WroteAA(elapsed) => Unlock::WroteAA(elapsed.saturating_add(cycles)),
+            Unlock::Locked => Unlock::Locked,
+        };
+        if let Some((addr, value, left)) = self.eeprom_write {
+            if left > cycles {
+                self.eeprom_write = Some((addr, value, left - cycles));
+            } else {
+                self.eeprom_write = None;
+                self.set_eeprom(addr, value);
+                self.eecon1 &= !(1 << WR);
+                interrupt::EE.raise(memory);
+                self.save(true);
+            }
+        }
+    }
+
+    // a reset abandons an EEPROM write half done, which WRERR owns up to. the holding registers
+    // keep their contents.
+    fn reset(&mut self) {
+        let interrupted = self.eeprom_write.take().is_some();
+        self.eecon1 = if interrupted { 1 << WRERR } else { self.eecon1 & (1 << WRERR) };
+        self.unlock = Unlock::Locked;
+        self.stall = 0;
+    }
+}
@@ -0,0 +1,29 @@
+use std::ops::RangeInclusive;
+
+use crate::emu::memory::DataMemory;
+
+// a model of some hardware behind SFRs. the core hands it every access to its addresses, once
+// any indirection has been gone through, and the cycles each instruction takes. it reaches the
+// rest of the machine through data memory, which is also how it raises interrupts: by setting
+// its source's flag, `interrupt::TMR0.raise(memory)` say.
+pub trait Peripheral {
+    fn name(&self) -> &'static str;
+
+    // the data addresses it answers for
+    fn addresses(&self) -> &[RangeInclusive<u16>];
+
+    // an instruction reading `addr`
+    fn read(&mut self, addr: u16, memory: &mut DataMemory) -> u8;
+
+    // what `read` would return, without anything reading does
+    fn peek(&self, addr: u16) -> u8;
+
+    // an instruction writing `value` to `addr`
+    fn write(&mut self, addr: u16, value: u8, memory: &mut DataMemory);
+
+    // `cycles` instruction cycles have passed
+    fn tick(&mut self, _cycles: u32, _memory: &mut DataMemory) {}
+
+    // back to the state the part resets to
+    fn reset(&mut self) {}
+}
@@ -0,0 +1,151 @@
+use std::fmt::{self, Display, Formatter};
+
+pub const RETURN_STACK_DEPTH: usize = 31;
+
+// STKPTR bits
+const STKFUL: u8 = 7;
+const STKUNF: u8 = 6;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StackFault {
+    Overflow,
+    Underflow,
+}
+
+impl Display for StackFault {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            StackFault::Overflow => write!(f, "stack overflow"),
+            StackFault::Underflow => write!(f, "stack underflow"),
+        }
+    }
+}
+
+// the 31-entry hardware return stack, as STKPTR and TOSU:TOSH:TOSL show it. the stack pointer
+// counts entries in use, and entry 0 is no entry at all: with the stack empty, TOS reads zero.
+//
+// the push filling the 31st entry sets STKFUL, and a pop off an empty stack sets STKUNF. with
+// STVREN set either one faults and resets the part. without it the stack pointer stays where it
+// is: a push onto the full stack faults and is lost, and an underflowing pop returns zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReturnStack {
+    entries: [u32; RETURN_STACK_DEPTH + 1],
+    pointer: u8,
+    full: bool,
+    underflow: bool,
+    // the STVREN configuration bit, set in an erased part
+    stvren: bool,
+}
+
+impl Default for ReturnStack {
+    fn default() -> ReturnStack {
+        ReturnStack::new(true)
+    }
+}
+
+impl ReturnStack {
+    pub fn new(stvren: bool) -> ReturnStack {
+        ReturnStack {
+            entries: [0; RETURN_STACK_DEPTH + 1],
+            pointer: 0,
+            full: false,
+            underflow: false,
+            stvren,
+        }
+    }
+
+    pub fn stvren(&self) -> bool {
+        self.stvren
+    }
+
+    pub fn set_stvren(&mut self, stvren: bool) {
+        self.stvren = stvren;
+    }
+
+    // the stack after any reset but power-on: empty, with STKFUL and STKUNF kept so firmware can
+    // tell a stack fault reset from the others.
+    pub fn reset(&mut self) {
+        self.pointer = 0;
+    }
+
+    // the stack at power-on, with the flags cleared too.
+    pub fn power_on_reset(&mut self) {
+        self.reset();
+        self.full = false;
+        self.underflow = false;
+    }
+
+    pub fn depth(&self) -> usize {
+        self.pointer as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pointer == 0
+    }
+
+    // return addresses in use, oldest first.
+    pub fn entries(&self) -> &[u32] {
+        &self.entries[1..=self.pointer as usize]
+    }
+
+    pub fn full(&self) -> bool {
+        self.full
+    }
+
+    pub fn underflow(&self) -> bool {
+        self.underflow
+    }
+
+    // the push that fills the last entry still lands, but sets STKFUL and, with STVREN, faults.
+    pub fn push(&mut self, addr: u32) -> Result<(), StackFault> {
+        if self.pointer as usize == RETURN_STACK_DEPTH {
+            self.full = true;
+            return Err(StackFault::Overflow);
+        }
+        self.pointer += 1;
+        self.entries[self.pointer as usize] = addr & 0x1f_ffff;
+        if self.pointer as usize == RETURN_STACK_DEPTH {
+            self.full = true;
+            if self.stvren {
+                return Err(StackFault::Overflow);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Result<u32, StackFault> {
+        if self.pointer == 0 {
+            self.underflow = true;
+            return Err(StackFault::Underflow);
+        }
+        let addr = self.entries[self.pointer as usize];
+        self.pointer -= 1;
+        Ok(addr)
+    }
+
+    pub fn tos(&self) -> u32 {
+        if self.pointer == 0 {
+            0
+        } else {
+            self.entries[self.pointer as usize]
+        }
+    }
+
+    // TOSU:TOSH:TOSL writes change the entry in use, if there is one.
+    pub fn set_tos(&mut self, addr: u32) {
+        if self.pointer != 0 {
+            self.entries[self.pointer as usize] = addr & 0x1f_ffff;
+        }
+    }
+
+    pub fn stkptr(&self) -> u8 {
+        ((self.full as u8) << STKFUL) | ((self.underflow as u8) << STKUNF) | self.pointer
+    }
+
+    // software moves the pointer freely, but can only clear the flags.
+    pub fn set_stkptr(&mut self, value: u8) {
+        self.pointer = value & 0x1f;
+        self.full &= value & (1 << STKFUL) != 0;
+        self.underflow &= value & (1 << STKUNF) != 0;
+    }
+}
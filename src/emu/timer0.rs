@@ -0,0 +1,139 @@
+use std::ops::RangeInclusive;
+
+use crate::consts;
+use crate::emu::interrupt;
+use crate::emu::memory::DataMemory;
+use crate::emu::peripheral::Peripheral;
+
+const T0CON: u16 = consts::SFR_BASE + consts::SFRS::T0CON;
+const TMR0L: u16 = consts::SFR_BASE + consts::SFRS::TMR0L;
+const TMR0H: u16 = consts::SFR_BASE + consts::SFRS::TMR0H;
+
+const ADDRESSES: [RangeInclusive<u16>; 1] = [T0CON..=TMR0H];
+
+// T0CON bits
+const TMR0ON: u8 = 7;
+const T08BIT: u8 = 6;
+const T0CS: u8 = 5;
+const PSA: u8 = 3;
+
+// a write to TMR0L holds the count still for this many cycles.
+const WRITE_INHIBIT: u32 = 2;
+
+// Timer0 counting instruction cycles, through its prescaler, as an 8 or 16 bit timer. T0CKI
+// isn't modelled: with T0CS set the timer doesn't count.
+//
+// in 16 bit mode TMR0H is a buffer for the high byte: reading TMR0L loads it from the count and
+// writing TMR0L stores it into the count, so firmware sees all 16 bits at once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timer0 {
+    t0con: u8,
+    count: u16,
+    tmr0h: u8,
+    prescaler: u32,
+    inhibit: u32,
+}
+
+impl Default for Timer0 {
+    fn default() -> Timer0 {
+        Timer0::new()
+    }
+}
+
+impl Timer0 {
+    // as at reset: on, 8 bit, and counting T0CKI, so not counting here
+    pub fn new() -> Timer0 {
+        Timer0 { t0con: 0xff, count: 0, tmr0h: 0, prescaler: 0, inhibit: 0 }
+    }
+
+    pub fn count(&self) -> u16 {
+        self.count
+    }
+
+    fn sixteen_bit(&self) -> bool {
+        self.t0con & (1 << T08BIT) == 0
+    }
+
+    // instruction cycles per count
+    fn prescale(&self) -> u32 {
+        if self.t0con & (1 << PSA) != 0 {
+            1
+        } else {
+            2 << (self.t0con & 0x07)
+        }
+    }
+
+    fn increment(&mut self, memory: &mut DataMemory) {
+        let overflowed = if self.sixteen_bit() {
+            self.count = self.count.wrapping_add(1);
+            self.count == 0
+        } else {
+            let low = (self.count as u8).wrapping_add(1);
+            self.count = (self.count & 0xff00) | low as u16;
+            low == 0
+        };
+        if overflowed {
+            interrupt::TMR0.raise(memory);
+        }
+    }
+}
+
+impl Peripheral for Timer0 {
+    fn name(&self) -> &'static str {
+        "Timer0"
+    }
+
+    fn addresses(&self) -> &[RangeInclusive<u16>] {
+        &ADDRESSES
+    }
+
+    fn read(&mut self, addr: u16, _memory: &mut DataMemory) -> u8 {
+        if addr == TMR0L && self.sixteen_bit() {
+            self.tmr0h = (self.count >> 8) as u8;
+        }
+        self.peek(addr)
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            T0CON => self.t0con,
+            TMR0L => self.count as u8,
+            _ => self.tmr0h
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8, _memory: &mut DataMemory) {
+        match addr {
+            T0CON => { self.t0con = value; },
+            TMR0L => {
+                let high = if self.sixteen_bit() { (self.tmr0h as u16) << 8 } else { self.count & 0xff00 };
+                self.count = high | value as u16;
+                // writing the timer clears the prescaler too
+                self.prescaler = 0;
+                self.inhibit = WRITE_INHIBIT;
+            },
+            _ => { self.tmr0h = value; }
+        }
+    }
+
+    fn tick(&mut self, cycles: u32, memory: &mut DataMemory) {
+        if self.t0con & (1 << TMR0ON) == 0 || self.t0con & (1 << T0CS) != 0 {
+            return;
+        }
+        for _ in 0..cycles {
+            if self.inhibit > 0 {
+                self.inhibit -= 1;
+                continue;
+            }
+            self.prescaler += 1;
+            if self.prescaler >= self.prescale() {
+                self.prescaler = 0;
+                self.increment(memory);
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Timer0::new();
+    }
+}
@@ -0,0 +1,789 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::{consts, Instruction, Opcode, Operand};
+use crate::analysis::disasm::{instruction_len, relative_target};
+use crate::analysis::fsr::{fsr_register, FsrRegister, IndirectMode};
+
+// W, BSR, PRODH:PRODL, TABLAT and TBLPTR are also visible as SFRs; how those aliases line up is
+// up to whatever implements `Machine`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Reg {
+    W,
+    Bsr,
+    ProdL,
+    ProdH,
+    Tablat,
+    // all 22 bits of TBLPTRU:TBLPTRH:TBLPTRL
+    Tblptr,
+}
+
+// the arithmetic flags of STATUS.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Flag {
+    C,
+    DC,
+    Z,
+    OV,
+    N,
+}
+
+impl Flag {
+    pub fn bit(&self) -> u8 {
+        match self {
+            Flag::C => 0,
+            Flag::DC => 1,
+            Flag::Z => 2,
+            Flag::OV => 3,
+            Flag::N => 4,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+    // comparisons are unsigned, and produce 0 or 1
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+}
+
+// expressions are evaluated over u32 with wrapping arithmetic; where a result is a byte, the
+// lifter masks it explicitly.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Expr {
+    Const(u32),
+    Reg(Reg),
+    Flag(Flag),
+    Temp(u8),
+    // a byte of data memory
+    Load(Box<Expr>),
+    // a byte of program memory
+    ProgramLoad(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    // if the first is nonzero, the second, else the third
+    Select(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Stmt {
+    Let(u8, Expr),
+    SetReg(Reg, Expr),
+    SetFlag(Flag, Expr),
+    // data memory address, value
+    Store(Expr, Expr),
+    // program memory address, value: a TBLWT into the holding registers
+    ProgramStore(Expr, Expr),
+    // skip the next instruction if the condition is nonzero
+    Skip(Expr),
+    Branch(Expr, u32),
+    Jump(Expr),
+    // `fast` saves W, STATUS and BSR in the shadow registers
+    Call { target: Expr, ret: u32, fast: bool },
+    Return { fast: bool, enable_interrupts: bool },
+    Push(u32),
+    Pop,
+    Sleep,
+    ClearWatchdog,
+    Reset,
+    // an invalid instruction; what it does is anyone's guess
+    Undefined,
+}
+
+fn b(expr: Expr) -> Box<Expr> {
+    Box::new(expr)
+}
+
+fn c(value: u32) -> Expr {
+    Expr::Const(value)
+}
+
+fn bin(op: BinOp, left: Expr, right: Expr) -> Expr {
+    Expr::Binary(op, b(left), b(right))
+}
+
+fn load(addr: Expr) -> Expr {
+    Expr::Load(b(addr))
+}
+
+fn byte(expr: Expr) -> Expr {
+    bin(BinOp::And, expr, c(0xff))
+}
+
+fn bit(expr: Expr, n: u32) -> Expr {
+    bin(BinOp::And, bin(BinOp::Shr, expr, c(n)), c(1))
+}
+
+struct Lifter {
+    stmts: Vec<Stmt>,
+    // pointer updates for POSTINCn/POSTDECn, which happen after the access
+    post: Vec<Stmt>,
+    temps: u8,
+    access_split: u8,
+}
+
+impl Lifter {
+    fn temp(&mut self, expr: Expr) -> Expr {
+        let temp = self.temps;
+        self.temps += 1;
+        self.stmts.push(Stmt::Let(temp, expr));
+        Expr::Temp(temp)
+    }
+
+    fn emit(&mut self, stmt: Stmt) {
+        self.stmts.push(stmt);
+    }
+
+    fn fsr(fsr: usize) -> (u16, u16) {
+        use crate::consts::SFRS;
+        let (low, high) = [
+            (SFRS::FSR0L, SFRS::FSR0H),
+            (SFRS::FSR1L, SFRS::FSR1H),
+            (SFRS::FSR2L, SFRS::FSR2H),
+        ][fsr];
+        (consts::SFR_BASE + low, consts::SFR_BASE + high)
+    }
+
+    fn set_fsr(fsr: usize, value: Expr) -> Vec<Stmt> {
+        let (low, high) = Lifter::fsr(fsr);
+        vec![
+            Stmt::Store(c(low as u32), byte(value.clone())),
+            Stmt::Store(c(high as u32), bin(BinOp::And, bin(BinOp::Shr, value, c(8)), c(0x0f))),
+        ]
+    }
+
+    // the data address a file operand refers to, computed once. indirect registers become the
+    // pointer they access through, with pointer updates made explicit. banked accesses are taken
+    // as plain memory even if BSR happens to select the SFR bank.
+    fn file(&mut self, operand: &Operand) -> Expr {
+        let addr = match operand {
+            Operand::File(file, true) |
+            Operand::RedirectableFile(file, true, _) => {
+                let banked = bin(BinOp::Or,
+                    bin(BinOp::Shl, bin(BinOp::And, Expr::Reg(Reg::Bsr), c(0x0f)), c(8)),
+                    c(*file as u32));
+                return self.temp(banked);
+            },
+            Operand::File(file, false) |
+            Operand::RedirectableFile(file, false, _) => consts::split_access_address(*file, self.access_split),
+            Operand::AbsoluteFile(addr) => *addr,
+            _ => { return self.temp(c(0)); }
+        };
+
+        let (fsr, mode) = match fsr_register(addr) {
+            Some((fsr, FsrRegister::Indirect(mode))) => (fsr, mode),
+            _ => { return c(addr as u32); }
+        };
+        let (low, high) = Lifter::fsr(fsr);
+        let pointer = self.temp(bin(BinOp::Or,
+            bin(BinOp::Shl, load(c(high as u32)), c(8)),
+            load(c(low as u32))));
+        let wrap = |expr| bin(BinOp::And, expr, c(0xfff));
+        match mode {
+            IndirectMode::Indf => pointer,
+            IndirectMode::PostInc => {
+                self.post.extend(Lifter::set_fsr(fsr, bin(BinOp::Add, pointer.clone(), c(1))));
+                pointer
+            },
+            IndirectMode::PostDec => {
+                self.post.extend(Lifter::set_fsr(fsr, bin(BinOp::Sub, pointer.clone(), c(1))));
+                pointer
+            },
+            IndirectMode::PreInc => {
+                let incremented = self.temp(wrap(bin(BinOp::Add, pointer, c(1))));
+                self.stmts.extend(Lifter::set_fsr(fsr, incremented.clone()));
+                incremented
+            },
+            IndirectMode::PlusW => {
+                // W is signed here: sign-extend it to 12 bits
+                let w = Expr::Reg(Reg::W);
+                let extended = bin(BinOp::Add, w.clone(), bin(BinOp::Mul, bin(BinOp::Shr, w, c(7)), c(0xf00)));
+                self.temp(wrap(bin(BinOp::Add, pointer, extended)))
+            }
+        }
+    }
+
+    fn zero_negative(&mut self, result: &Expr) {
+        self.emit(Stmt::SetFlag(Flag::Z, bin(BinOp::Eq, result.clone(), c(0))));
+        self.emit(Stmt::SetFlag(Flag::N, bit(result.clone(), 7)));
+    }
+
+    // `a + b + carry_in` with every flag set from it, as ADDWF and friends do.
+    fn add(&mut self, a: Expr, b: Expr, carry_in: Expr) -> Expr {
+        let a = self.temp(a);
+        let b = self.temp(b);
+        let carry_in = self.temp(carry_in);
+        let sum = self.temp(bin(BinOp::Add, bin(BinOp::Add, a.clone(), b.clone()), carry_in.clone()));
+        let result = self.temp(byte(sum.clone()));
+        let nibble = |x: Expr| bin(BinOp::And, x, c(0x0f));
+        self.emit(Stmt::SetFlag(Flag::C, bit(sum, 8)));
+        self.emit(Stmt::SetFlag(Flag::DC,
+            bit(bin(BinOp::Add, bin(BinOp::Add, nibble(a.clone()), nibble(b.clone())), carry_in), 4)));
+        // signed overflow: both inputs agree in sign and the result does not
+        self.emit(Stmt::SetFlag(Flag::OV,
+            bit(bin(BinOp::And,
+                bin(BinOp::Xor, a, result.clone()),
+                bin(BinOp::Xor, b, result.clone())), 7)));
+        self.zero_negative(&result);
+        result
+    }
+
+    // `a - b`, less a borrow when `carry_in` is zero. C is the inverse of borrow, as on hardware.
+    fn sub(&mut self, a: Expr, b: Expr, carry_in: Expr) -> Expr {
+        self.add(a, bin(BinOp::Xor, b, c(0xff)), carry_in)
+    }
+
+    fn store_result(&mut self, operand: &Operand, addr: Expr, result: Expr) {
+        match operand {
+            Operand::RedirectableFile(_, _, false) => self.emit(Stmt::SetReg(Reg::W, result)),
+            _ => self.emit(Stmt::Store(addr, result)),
+        }
+    }
+
+    fn finish(mut self) -> Vec<Stmt> {
+        // pointer updates go ahead of any control transfer, which is always last
+        let transfer = match self.stmts.last() {
+            Some(Stmt::Skip(_)) |
+            Some(Stmt::Branch(_, _)) |
+            Some(Stmt::Jump(_)) |
+            Some(Stmt::Call { .. }) |
+            Some(Stmt::Return { .. }) => self.stmts.pop(),
+            _ => None
+        };
+        self.stmts.append(&mut self.post);
+        self.stmts.extend(transfer);
+        self.stmts
+    }
+}
+
+// the IR for the instruction at `addr`, with the access bank split as `consts::access_address`
+// has it.
+pub fn lift(addr: u32, inst: &Instruction) -> Vec<Stmt> {
+    lift_split(addr, inst, consts::ACCESS_SPLIT)
+}
+
+// `lift` for a part whose access bank splits at `access_split`, `Device::access_split` say.
+pub fn lift_split(addr: u32, inst: &Instruction, access_split: u8) -> Vec<Stmt> {
+    let mut l = Lifter { stmts: Vec::new(), post: Vec::new(), temps: 0, access_split };
+    let next = addr + instruction_len(inst);
+    let op = inst.operands[0];
+    let w = || Expr::Reg(Reg::W);
+    let imm = match op {
+        Operand::ImmediateU8(k) => k as u32,
+        Operand::ImmediateU32(k) => k,
+        _ => 0
+    };
+    let tblptr = || Expr::Reg(Reg::Tblptr);
+    let step_tblptr = |delta: Expr, op: BinOp| {
+        Stmt::SetReg(Reg::Tblptr, bin(BinOp::And, bin(op, tblptr(), delta), c(0x3f_ffff)))
+    };
+
+    match inst.opcode {
+        Opcode::Invalid(_, _) => l.emit(Stmt::Undefined),
+        Opcode::NOP => {},
+        Opcode::SLEEP => l.emit(Stmt::Sleep),
+        Opcode::CLRWDT => l.emit(Stmt::ClearWatchdog),
+        Opcode::RESET => l.emit(Stmt::Reset),
+        Opcode::PUSH => l.emit(Stmt::Push(next)),
+        Opcode::POP => l.emit(Stmt::Pop),
+        Opcode::DAW => {
+            let adjust_low = l.temp(bin(BinOp::Or,
+                bin(BinOp::Gt, bin(BinOp::And, w(), c(0x0f)), c(9)),
+                Expr::Flag(Flag::DC)));
+            let partial = l.temp(bin(BinOp::Add, w(), bin(BinOp::Mul, adjust_low, c(0x06))));
+            let adjust_high = l.temp(bin(BinOp::Or,
+                bin(BinOp::Gt, bin(BinOp::Shr, partial.clone(), c(4)), c(9)),
+                Expr::Flag(Flag::C)));
+            let adjusted = l.temp(bin(BinOp::Add, partial, bin(BinOp::Mul, adjust_high.clone(), c(0x60))));
+            l.emit(Stmt::SetFlag(Flag::C, bin(BinOp::Or, adjust_high, bit(adjusted.clone(), 8))));
+            l.emit(Stmt::SetReg(Reg::W, byte(adjusted)));
+        },
+        Opcode::MOVLB => l.emit(Stmt::SetReg(Reg::Bsr, c(imm & 0x0f))),
+        Opcode::MOVLW => l.emit(Stmt::SetReg(Reg::W, c(imm))),
+        Opcode::ADDLW => {
+            let result = l.add(w(), c(imm), c(0));
+            l.emit(Stmt::SetReg(Reg::W, result));
+        },
+        Opcode::SUBLW => {
+            let result = l.sub(c(imm), w(), c(1));
+            l.emit(Stmt::SetReg(Reg::W, result));
+        },
+        Opcode::ANDLW | Opcode::IORLW | Opcode::XORLW => {
+            let op = match inst.opcode {
+                Opcode::ANDLW => BinOp::And,
+                Opcode::IORLW => BinOp::Or,
+                _ => BinOp::Xor,
+            };
+            let result = l.temp(bin(op, w(), c(imm)));
+            l.zero_negative(&result);
+            l.emit(Stmt::SetReg(Reg::W, result));
+        },
+        Opcode::MULLW => {
+            let product = l.temp(bin(BinOp::Mul, w(), c(imm)));
+            l.emit(Stmt::SetReg(Reg::ProdL, byte(product.clone())));
+            l.emit(Stmt::SetReg(Reg::ProdH, byte(bin(BinOp::Shr, product, c(8)))));
+        },
+        Opcode::RETLW => {
+            l.emit(Stmt::SetReg(Reg::W, c(imm)));
+            l.emit(Stmt::Return { fast: false, enable_interrupts: false });
+        },
+        Opcode::RETURN => l.emit(Stmt::Return { fast: false, enable_interrupts: false }),
+        Opcode::RETURN_FAST => l.emit(Stmt::Return { fast: true, enable_interrupts: false }),
+        Opcode::RETFIE => l.emit(Stmt::Return { fast: false, enable_interrupts: true }),
+        Opcode::RETFIE_FAST => l.emit(Stmt::Return { fast: true, enable_interrupts: true }),
+        Opcode::GOTO => l.emit(Stmt::Jump(c(imm))),
+        Opcode::CALL => l.emit(Stmt::Call { target: c(imm), ret: next, fast: false }),
+        Opcode::CALL_FAST => l.emit(Stmt::Call { target: c(imm), ret: next, fast: true }),
+        Opcode::BRA => {
+            let target = relative_target(addr, inst).unwrap_or(next);
+            l.emit(Stmt::Jump(c(target)));
+        },
+        Opcode::RCALL => {
+            let target = relative_target(addr, inst).unwrap_or(next);
+            l.emit(Stmt::Call { target: c(target), ret: next, fast: false });
+        },
+        Opcode::CALLW => {
+            use crate::consts::SFRS;
+            let target = bin(BinOp::Or,
+                bin(BinOp::Or,
+                    bin(BinOp::Shl, load(c((consts::SFR_BASE + SFRS::PCLATU) as u32)), c(16)),
+                    bin(BinOp::Shl, load(c((consts::SFR_BASE + SFRS::PCLATH) as u32)), c(8))),
+                w());
+            l.emit(Stmt::Call { target, ret: next, fast: false });
+        },
+        Opcode::BZ | Opcode::BNZ | Opcode::BC | Opcode::BNC |
+        Opcode::BOV | Opcode::BNOV | Opcode::BN | Opcode::BNN => {
+            let (flag, set) = match inst.opcode {
+                Opcode::BZ => (Flag::Z, true),
+                Opcode::BNZ => (Flag::Z, false),
+                Opcode::BC => (Flag::C, true),
+                Opcode::BNC => (Flag::C, false),
+                Opcode::BOV => (Flag::OV, true),
+                Opcode::BNOV => (Flag::OV, false),
+                Opcode::BN => (Flag::N, true),
+                _ => (Flag::N, false),
+            };
+            let target = relative_target(addr, inst).unwrap_or(next);
+            let cond = if set {
+                Expr::Flag(flag)
+            } else {
+                bin(BinOp::Eq, Expr::Flag(flag), c(0))
+            };
+            l.emit(Stmt::Branch(cond, target));
+        },
+        Opcode::LFSR => {
+            if let (Operand::FileFSR(fsr), Operand::ImmediateU32(k)) = (inst.operands[0], inst.operands[1]) {
+                if (fsr as usize) < 3 {
+                    l.stmts.extend(Lifter::set_fsr(fsr as usize, c(k)));
+                }
+            }
+        },
+        Opcode::MOVFF => {
+            let src = l.file(&inst.operands[0]);
+            let value = l.temp(load(src));
+            let dest = l.file(&inst.operands[1]);
+            l.emit(Stmt::Store(dest, value));
+        },
+        Opcode::TBLRD_S | Opcode::TBLRD_S_I | Opcode::TBLRD_S_D | Opcode::TBLRD_I_S |
+        Opcode::TBLWT_S | Opcode::TBLWT_S_I | Opcode::TBLWT_S_D | Opcode::TBLWT_I_S => {
+            let (write, before, after) = match inst.opcode {
+                Opcode::TBLRD_S => (false, None, None),
+                Opcode::TBLRD_S_I => (false, None, Some(BinOp::Add)),
+                Opcode::TBLRD_S_D => (false, None, Some(BinOp::Sub)),
+                Opcode::TBLRD_I_S => (false, Some(BinOp::Add), None),
+                Opcode::TBLWT_S => (true, None, None),
+                Opcode::TBLWT_S_I => (true, None, Some(BinOp::Add)),
+                Opcode::TBLWT_S_D => (true, None, Some(BinOp::Sub)),
+                _ => (true, Some(BinOp::Add), None),
+            };
+            if let Some(op) = before {
+                l.emit(step_tblptr(c(1), op));
+            }
+            if write {
+                l.emit(Stmt::ProgramStore(tblptr(), Expr::Reg(Reg::Tablat)));
+            } else {
+                l.emit(Stmt::SetReg(Reg::Tablat, Expr::ProgramLoad(b(tblptr()))));
+            }
+            if let Some(op) = after {
+                l.emit(step_tblptr(c(1), op));
+            }
+        },
+        Opcode::MOVWF => {
+            let dest = l.file(&op);
+            l.emit(Stmt::Store(dest, w()));
+        },
+        Opcode::CLRF => {
+            let dest = l.file(&op);
+            l.emit(Stmt::Store(dest, c(0)));
+            l.emit(Stmt::SetFlag(Flag::Z, c(1)));
+        },
+        Opcode::SETF => {
+            let dest = l.file(&op);
+            l.emit(Stmt::Store(dest, c(0xff)));
+        },
+        Opcode::NEGF => {
+            let addr = l.file(&op);
+            let result = l.sub(c(0), load(addr.clone()), c(1));
+            l.emit(Stmt::Store(addr, result));
+        },
+        Opcode::MULWF => {
+            let addr = l.file(&op);
+            let product = l.temp(bin(BinOp::Mul, w(), load(addr)));
+            l.emit(Stmt::SetReg(Reg::ProdL, byte(product.clone())));
+            l.emit(Stmt::SetReg(Reg::ProdH, byte(bin(BinOp::Shr, product, c(8)))));
+        },
+        Opcode::BSF | Opcode::BCF | Opcode::BTG | Opcode::BTFSC | Opcode::BTFSS => {
+            let addr = l.file(&op);
+            let n = match inst.operands[1] {
+                Operand::ImmediateU8(n) => n as u32,
+                _ => 0
+            };
+            let value = load(addr.clone());
+            match inst.opcode {
+                Opcode::BSF => l.emit(Stmt::Store(addr, bin(BinOp::Or, value, c(1 << n)))),
+                Opcode::BCF => l.emit(Stmt::Store(addr, bin(BinOp::And, value, c(!(1 << n) & 0xff)))),
+                Opcode::BTG => l.emit(Stmt::Store(addr, bin(BinOp::Xor, value, c(1 << n)))),
+                Opcode::BTFSC => l.emit(Stmt::Skip(bin(BinOp::Eq, bit(value, n), c(0)))),
+                _ => l.emit(Stmt::Skip(bit(value, n))),
+            }
+        },
+        Opcode::CPFSEQ | Opcode::CPFSGT | Opcode::CPFSLT => {
+            let addr = l.file(&op);
+            let cmp = match inst.opcode {
+                Opcode::CPFSEQ => BinOp::Eq,
+                Opcode::CPFSGT => BinOp::Gt,
+                _ => BinOp::Lt,
+            };
+            l.emit(Stmt::Skip(bin(cmp, load(addr), w())));
+        },
+        Opcode::TSTFSZ => {
+            let addr = l.file(&op);
+            l.emit(Stmt::Skip(bin(BinOp::Eq, load(addr), c(0))));
+        },
+        Opcode::INCFSZ | Opcode::DECFSZ | Opcode::INFSNZ | Opcode::DCFSNZ => {
+            let addr = l.file(&op);
+            let step = match inst.opcode {
+                Opcode::INCFSZ | Opcode::INFSNZ => BinOp::Add,
+                _ => BinOp::Sub,
+            };
+            let result = l.temp(byte(bin(step, load(addr.clone()), c(1))));
+            l.store_result(&op, addr, result.clone());
+            let skip_on = match inst.opcode {
+                Opcode::INCFSZ | Opcode::DECFSZ => BinOp::Eq,
+                _ => BinOp::Ne,
+            };
+            l.emit(Stmt::Skip(bin(skip_on, result, c(0))));
+        },
+        Opcode::ADDWF | Opcode::ADDWFC => {
+            let addr = l.file(&op);
+            let carry = if inst.opcode == Opcode::ADDWFC { Expr::Flag(Flag::C) } else { c(0) };
+            let result = l.add(w(), load(addr.clone()), carry);
+            l.store_result(&op, addr, result);
+        },
+        Opcode::SUBWF | Opcode::SUBWFB | Opcode::SUBFWB => {
+            let addr = l.file(&op);
+            let carry = if inst.opcode == Opcode::SUBWF { c(1) } else { Expr::Flag(Flag::C) };
+            let result = if inst.opcode == Opcode::SUBFWB {
+                l.sub(w(), load(addr.clone()), carry)
+            } else {
+                l.sub(load(addr.clone()), w(), carry)
+            };
+            l.store_result(&op, addr, result);
+        },
+        Opcode::INCF | Opcode::DECF => {
+            let addr = l.file(&op);
+            let result = if inst.opcode == Opcode::INCF {
+                l.add(load(addr.clone()), c(1), c(0))
+            } else {
+                l.add(load(addr.clone()), c(0xff), c(0))
+            };
+            l.store_result(&op, addr, result);
+        },
+        Opcode::IORWF | Opcode::ANDWF | Opcode::XORWF | Opcode::COMF | Opcode::MOVF => {
+            let addr = l.file(&op);
+            let value = load(addr.clone());
+            let result = l.temp(match inst.opcode {
+                Opcode::IORWF => bin(BinOp::Or, w(), value),
+                Opcode::ANDWF => bin(BinOp::And, w(), value),
+                Opcode::XORWF => bin(BinOp::Xor, w(), value),
+                Opcode::COMF => bin(BinOp::Xor, value, c(0xff)),
+                _ => value,
+            });
+            l.zero_negative(&result);
+            l.store_result(&op, addr, result);
+        },
+        Opcode::RLCF | Opcode::RRCF | Opcode::RLNCF | Opcode::RRNCF => {
+            let addr = l.file(&op);
+            let value = l.temp(load(addr.clone()));
+            let result = l.temp(match inst.opcode {
+                Opcode::RLCF => byte(bin(BinOp::Or, bin(BinOp::Shl, value.clone(), c(1)), Expr::Flag(Flag::C))),
+                Opcode::RRCF => bin(BinOp::Or, bin(BinOp::Shr, value.clone(), c(1)), bin(BinOp::Shl, Expr::Flag(Flag::C), c(7))),
+                Opcode::RLNCF => byte(bin(BinOp::Or, bin(BinOp::Shl, value.clone(), c(1)), bit(value.clone(), 7))),
+                _ => bin(BinOp::Or, bin(BinOp::Shr, value.clone(), c(1)), bin(BinOp::Shl, bit(value.clone(), 0), c(7))),
+            });
+            match inst.opcode {
+                Opcode::RLCF => l.emit(Stmt::SetFlag(Flag::C, bit(value, 7))),
+                Opcode::RRCF => l.emit(Stmt::SetFlag(Flag::C, bit(value, 0))),
+                _ => {}
+            }
+            l.zero_negative(&result);
+            l.store_result(&op, addr, result);
+        },
+        Opcode::SWAPF => {
+            let addr = l.file(&op);
+            let value = l.temp(load(addr.clone()));
+            let result = byte(bin(BinOp::Or,
+                bin(BinOp::Shl, value.clone(), c(4)),
+                bin(BinOp::Shr, value, c(4))));
+            l.store_result(&op, addr, result);
+        },
+        Opcode::MOVSF | Opcode::MOVSD => {
+            // extended instruction set, which the decoder doesn't produce
+            l.emit(Stmt::Undefined);
+        },
+    }
+
+    l.finish()
+}
+
+// the machine IR executes against. flags are bits of STATUS, but `Machine` sees them one at a
+// time.
+pub trait Machine {
+    fn reg(&self, reg: Reg) -> u32;
+    fn set_reg(&mut self, reg: Reg, value: u32);
+    fn flag(&self, flag: Flag) -> bool;
+    fn set_flag(&mut self, flag: Flag, value: bool);
+    fn load(&mut self, addr: u16) -> u8;
+    fn store(&mut self, addr: u16, value: u8);
+    fn program_load(&mut self, addr: u32) -> u8;
+    fn program_store(&mut self, addr: u32, value: u8);
+}
+
+// what control does once an instruction's IR has run. moving PC and the return stack is left
+// to the caller.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Next,
+    Skip,
+    Jump(u32),
+    Call { target: u32, ret: u32, fast: bool },
+    Return { fast: bool, enable_interrupts: bool },
+    Push(u32),
+    Pop,
+    Sleep,
+    ClearWatchdog,
+    Reset,
+    Undefined,
+}
+
+pub fn eval<M: Machine>(expr: &Expr, temps: &[u32], machine: &mut M) -> u32 {
+    match expr {
+        Expr::Const(value) => *value,
+        Expr::Reg(reg) => machine.reg(*reg),
+        Expr::Flag(flag) => machine.flag(*flag) as u32,
+        Expr::Temp(temp) => temps[*temp as usize],
+        Expr::Load(addr) => {
+            let addr = eval(addr, temps, machine);
+            machine.load((addr & 0xfff) as u16) as u32
+        },
+        Expr::ProgramLoad(addr) => {
+            let addr = eval(addr, temps, machine);
+            machine.program_load(addr) as u32
+        },
+        Expr::Not(inner) => (eval(inner, temps, machine) == 0) as u32,
+        Expr::Binary(op, left, right) => {
+            let left = eval(left, temps, machine);
+            let right = eval(right, temps, machine);
+            match op {
+                BinOp::Add => left.wrapping_add(right),
+                BinOp::Sub => left.wrapping_sub(right),
+                BinOp::Mul => left.wrapping_mul(right),
+                BinOp::And => left & right,
+                BinOp::Or => left | right,
+                BinOp::Xor => left ^ right,
+                BinOp::Shl => left.checked_shl(right).unwrap_or(0),
+                BinOp::Shr => left.checked_shr(right).unwrap_or(0),
+                BinOp::Eq => (left == right) as u32,
+                BinOp::Ne => (left != right) as u32,
+                BinOp::Lt => (left < right) as u32,
+                BinOp::Gt => (left > right) as u32,
+            }
+        },
+        Expr::Select(cond, then, otherwise) => {
+            if eval(cond, temps, machine) != 0 {
+                eval(then, temps, machine)
+            } else {
+                eval(otherwise, temps, machine)
+            }
+        }
+    }
+}
+
+pub fn execute<M: Machine>(stmts: &[Stmt], machine: &mut M) -> Outcome {
+    let mut temps: Vec<u32> = Vec::new();
+    let mut outcome = Outcome::Next;
+    for stmt in stmts.iter() {
+        match stmt {
+            Stmt::Let(temp, expr) => {
+                let value = eval(expr, &temps, machine);
+                let temp = *temp as usize;
+                if temps.len() <= temp {
+                    temps.resize(temp + 1, 0);
+                }
+                temps[temp] = value;
+            },
+            Stmt::SetReg(reg, expr) => {
+                let value = eval(expr, &temps, machine);
+                machine.set_reg(*reg, value);
+            },
+            Stmt::SetFlag(flag, expr) => {
+                let value = eval(expr, &temps, machine);
+                machine.set_flag(*flag, value != 0);
+            },
+            Stmt::Store(addr, value) => {
+                let addr = eval(addr, &temps, machine);
+                let value = eval(value, &temps, machine);
+                machine.store((addr & 0xfff) as u16, value as u8);
+            },
+            Stmt::ProgramStore(addr, value) => {
+                let addr = eval(addr, &temps, machine);
+                let value = eval(value, &temps, machine);
+                machine.program_store(addr, value as u8);
+            },
+            Stmt::Skip(cond) => {
+                if eval(cond, &temps, machine) != 0 {
+                    outcome = Outcome::Skip;
+                }
+            },
+            Stmt::Branch(cond, target) => {
+                if eval(cond, &temps, machine) != 0 {
+                    outcome = Outcome::Jump(*target);
+                }
+            },
+            Stmt::Jump(target) => {
+                outcome = Outcome::Jump(eval(target, &temps, machine));
+            },
+            Stmt::Call { target, ret, fast } => {
+                outcome = Outcome::Call { target: eval(target, &temps, machine), ret: *ret, fast: *fast };
+            },
+            Stmt::Return { fast, enable_interrupts } => {
+                outcome = Outcome::Return { fast: *fast, enable_interrupts: *enable_interrupts };
+            },
+            Stmt::Push(ret) => { outcome = Outcome::Push(*ret); },
+            Stmt::Pop => { outcome = Outcome::Pop; },
+            Stmt::Sleep => { outcome = Outcome::Sleep; },
+            Stmt::ClearWatchdog => { outcome = Outcome::ClearWatchdog; },
+            Stmt::Reset => { outcome = Outcome::Reset; },
+            Stmt::Undefined => { outcome = Outcome::Undefined; },
+        }
+    }
+    outcome
+}
+
+impl Display for Reg {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Reg::W => write!(f, "W"),
+            Reg::Bsr => write!(f, "BSR"),
+            Reg::ProdL => write!(f, "PRODL"),
+            Reg::ProdH => write!(f, "PRODH"),
+            Reg::Tablat => write!(f, "TABLAT"),
+            Reg::Tblptr => write!(f, "TBLPTR"),
+        }
+    }
+}
+
+impl Display for Flag {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Flag::C => write!(f, "C"),
+            Flag::DC => write!(f, "DC"),
+            Flag::Z => write!(f, "Z"),
+            Flag::OV => write!(f, "OV"),
+            Flag::N => write!(f, "N"),
+        }
+    }
+}
+
+impl Display for BinOp {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let op = match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::And => "&",
+            BinOp::Or => "|",
+            BinOp::Xor => "^",
+            BinOp::Shl => "<<",
+            BinOp::Shr => ">>",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Gt => ">",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Expr::Const(value) => write!(f, "0x{:x}", value),
+            Expr::Reg(reg) => write!(f, "{}", reg),
+            Expr::Flag(flag) => write!(f, "{}", flag),
+            Expr::Temp(temp) => write!(f, "t{}", temp),
+            Expr::Load(addr) => write!(f, "mem[{}]", addr),
+            Expr::ProgramLoad(addr) => write!(f, "prog[{}]", addr),
+            Expr::Not(inner) => write!(f, "!{}", inner),
+            Expr::Binary(op, left, right) => write!(f, "({} {} {})", left, op, right),
+            Expr::Select(cond, then, otherwise) => write!(f, "({} ? {} : {})", cond, then, otherwise),
+        }
+    }
+}
+
+impl Display for Stmt {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Stmt::Let(temp, expr) => write!(f, "t{} = {}", temp, expr),
+            Stmt::SetReg(reg, expr) => write!(f, "{} = {}", reg, expr),
+            Stmt::SetFlag(flag, expr) => write!(f, "{} = {}", flag, expr),
+            Stmt::Store(addr, value) => write!(f, "mem[{}] = {}", addr, value),
+            Stmt::ProgramStore(addr, value) => write!(f, "prog[{}] = {}", addr, value),
+            Stmt::Skip(cond) => write!(f, "skip if {}", cond),
+            Stmt::Branch(cond, target) => write!(f, "if {} goto 0x{:x}", cond, target),
+            Stmt::Jump(target) => write!(f, "goto {}", target),
+            Stmt::Call { target, ret, fast } => {
+                write!(f, "call {} (return to 0x{:x})", target, ret)?;
+                if *fast {
+                    write!(f, " saving shadow registers")?;
+                }
+                Ok(())
+            },
+            Stmt::Return { fast, enable_interrupts } => {
+                write!(f, "return")?;
+                if *enable_interrupts {
+                    write!(f, " enabling interrupts")?;
+                }
+                if *fast {
+                    write!(f, " restoring shadow registers")?;
+                }
+                Ok(())
+            },
+            Stmt::Push(ret) => write!(f, "push 0x{:x}", ret),
+            Stmt::Pop => write!(f, "pop"),
+            Stmt::Sleep => write!(f, "sleep"),
+            Stmt::ClearWatchdog => write!(f, "clear watchdog"),
+            Stmt::Reset => write!(f, "reset"),
+            Stmt::Undefined => write!(f, "undefined"),
+        }
+    }
+}
@@ -4,7 +4,13 @@ use yaxpeax_arch::{Arch, AddressDiff, Decoder, LengthedInstruction, Reader, Stan
 #[macro_use] extern crate serde_derive;
 
 pub mod consts;
+pub mod decompile;
+pub mod device;
+pub mod emu;
 pub mod display;
+pub mod analysis;
+pub mod ir;
+pub mod lint;
 
 #[cfg_attr(feature="use-serde", derive(Serialize, Deserialize))]
 #[derive(Debug)]
@@ -19,7 +25,7 @@ impl Arch for PIC18 {
     type Operand = Operand;
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Instruction {
     pub opcode: Opcode,
     pub operands: [Operand; 2]
@@ -36,6 +42,7 @@ impl LengthedInstruction for Instruction {
                 | Opcode::MOVSF
                 | Opcode::MOVSD
                 | Opcode::CALL
+                | Opcode::CALL_FAST
                 | Opcode::LFSR
                 | Opcode::GOTO => {
                 AddressDiff::from_const(4)
@@ -60,7 +67,7 @@ impl Default for Instruction {
 }
 
 #[allow(non_camel_case_types)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Opcode {
     Invalid(u8, u8),
     NOP,
@@ -68,6 +75,7 @@ pub enum Opcode {
     MOVSF,
     MOVSD,
     CALL,
+    CALL_FAST,
     LFSR,
     GOTO,
     CALLW,
@@ -145,7 +153,7 @@ pub enum Opcode {
     RCALL
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Operand {
     ImmediateU8(u8),
     ImmediateU32(u32),
@@ -429,8 +437,7 @@ impl Decoder<PIC18> for InstDecoder {
                 Ok(())
             }
             /* ... */
-            0xeb | 0xec => {
-                // TODO: respect s bit
+            0xec | 0xed => {
                 let k_lsb = word[0];
                 let mut word2 = [0u8; 2];
                 words.next_n(&mut word2)?;
@@ -441,7 +448,8 @@ impl Decoder<PIC18> for InstDecoder {
 
                 let k_msb = (((word2[1] & 0xf) as u32) << 8) | word2[0] as u32;
 
-                inst.opcode = Opcode::CALL;
+                // the s bit: save W, STATUS and BSR in the shadow registers
+                inst.opcode = if word[1] & 1 == 0 { Opcode::CALL } else { Opcode::CALL_FAST };
                 inst.operands[0] = Operand::ImmediateU32(((k_msb << 8) | k_lsb as u32) << 1);
                 Ok(())
             }
@@ -0,0 +1,117 @@
+use std::collections::BTreeSet;
+
+use crate::{consts, Instruction, Opcode, Operand};
+use crate::analysis::FileRef;
+use crate::analysis::bsr::Bank;
+use crate::analysis::fsr::FsrTracking;
+use crate::lint::{Context, Diagnostic, Rule, Severity};
+
+fn file_operand(inst: &Instruction) -> Option<(u8, bool)> {
+    match inst.operands[0] {
+        Operand::File(file, banked) |
+        Operand::RedirectableFile(file, banked, _) => Some((file, banked)),
+        _ => None
+    }
+}
+
+// a banked (a = 1) operand where what BSR holds isn't known, or depends on the path taken to
+// get there.
+pub struct BankedBsr;
+
+impl Rule for BankedBsr {
+    fn name(&self) -> &'static str {
+        "banked-bsr"
+    }
+
+    fn check_instruction(&self, cx: &Context, addr: u32, inst: &Instruction, out: &mut Vec<Diagnostic>) {
+        let file = match file_operand(inst) {
+            Some((file, true)) => file,
+            _ => { return; }
+        };
+        let message = match cx.bsr.bsr_before(addr) {
+            // never reached from an entry point, so there is nothing to say
+            None => { return; },
+            Some(Bank::Unknown) => {
+                format!("{} uses banked file 0x{:02x}, but nothing here says what BSR holds", inst.opcode, file)
+            },
+            Some(Bank::Known(banks)) if banks.len() > 1 => {
+                let addresses: Vec<String> = banks.iter()
+                    .map(|bank| cx.device.file_name(((*bank as u16) << 8) | file as u16))
+                    .collect();
+                format!(
+                    "{} uses banked file 0x{:02x} where BSR differs between paths, so it may be any of {}",
+                    inst.opcode, file, addresses.join(", ")
+                )
+            },
+            Some(Bank::Known(_)) => { return; },
+        };
+        out.push(Diagnostic {
+            addr,
+            rule: self.name(),
+            severity: Severity::Warning,
+            message,
+            suggestion: Some("select the bank with movlb before this".to_string()),
+        });
+    }
+}
+
+// an access-bank (a = 0) operand between the part's split and 0x80, where other code uses the
+// same address as RAM: banked with BSR = 0, through an FSR, or by MOVFF. it is RAM on the older
+// parts, but an SFR at 0xf60-0xf7f on those that split at 0x60, so code written for one
+// misbehaves on the other. on its own the operand is just an SFR access, like the USB SFRs.
+pub struct AccessSplit;
+
+// data addresses from the part's split up to 0x80 that code reaches some way that can only mean
+// RAM.
+fn ram_above_split(cx: &Context) -> BTreeSet<u16> {
+    let fsr = FsrTracking::analyze(cx.cfg);
+    let split = cx.device.access_split as u16..consts::ACCESS_SPLIT as u16;
+    let mut ram = BTreeSet::new();
+    for (addr, inst) in cx.disassembly.instructions() {
+        let mut addresses = Vec::new();
+        if let Some((file, true)) = file_operand(inst) {
+            addresses.extend(cx.bsr.resolve(addr, FileRef::Banked(file)));
+        }
+        if let (Opcode::MOVFF, Operand::AbsoluteFile(src), Operand::AbsoluteFile(dest)) = (inst.opcode, inst.operands[0], inst.operands[1]) {
+            addresses.push(src);
+            addresses.push(dest);
+        }
+        addresses.extend(fsr.accesses(addr).iter().filter_map(|access| access.address));
+        ram.extend(addresses.into_iter().filter(|address| split.contains(address)));
+    }
+    ram
+}
+
+impl Rule for AccessSplit {
+    fn name(&self) -> &'static str {
+        "access-split"
+    }
+
+    fn check(&self, cx: &Context, out: &mut Vec<Diagnostic>) {
+        let ram = ram_above_split(cx);
+        for (addr, inst) in cx.disassembly.instructions() {
+            let file = match file_operand(inst) {
+                Some((file, false)) => file,
+                _ => { continue; }
+            };
+            let actual = cx.device.access_address(file);
+            let assumed = consts::access_address(file);
+            if actual == assumed || !ram.contains(&assumed) {
+                continue;
+            }
+            out.push(Diagnostic {
+                addr,
+                rule: self.name(),
+                severity: Severity::Warning,
+                message: format!(
+                    "access-bank file 0x{:02x} is {} on {}, but other code uses RAM at 0x{:03x}",
+                    file, cx.device.file_name(actual), cx.device.name, assumed
+                ),
+                suggestion: Some(format!(
+                    "for RAM, place the variable below 0x{:02x} or reach it banked with BSR = 0",
+                    cx.device.access_split
+                )),
+            });
+        }
+    }
+}
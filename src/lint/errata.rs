@@ -0,0 +1,260 @@
+use crate::{consts, Instruction, Opcode, Operand};
+use crate::analysis::{file_written, FileRef};
+use crate::analysis::disasm::{instruction_len, HIGH_PRIORITY_VECTOR};
+use crate::analysis::isr::Priority;
+use crate::device::Device;
+use crate::lint::{Context, Diagnostic, Rule, Severity};
+
+const EECON1: u16 = consts::SFR_BASE + consts::SFRS::EECON1;
+const EECON2: u16 = consts::SFR_BASE + consts::SFRS::EECON2;
+const TBLPTRL: u16 = consts::SFR_BASE + consts::SFRS::TBLPTRL;
+// EECON1 bit starting a write
+const WR: u8 = 1;
+
+// a documented way for correct-looking code to go wrong on some part: from a silicon errata sheet,
+// or a data sheet restriction easy to miss.
+pub struct Erratum {
+    pub id: &'static str,
+    pub summary: &'static str,
+    // where it is documented: the document's DS number and the section or errata item
+    pub reference: &'static str,
+    pub workaround: &'static str,
+    // the parts it applies to, by name; empty for all of them.
+    pub devices: &'static [&'static str],
+    // the silicon revisions of those parts it affects; empty for data sheet restrictions, which
+    // hold for every revision. the revision isn't in the image, so check the REV bits of DEVID1 on the part in hand.
+    pub revisions: &'static [&'static str],
+    // (address, what was found there)
+    check: fn(&Context, &mut Vec<(u32, String)>),
+}
+
+impl Erratum {
+    pub fn applies_to(&self, device: &Device) -> bool {
+        self.devices.is_empty() || self.devices.iter().any(|name| device.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn find(&self, cx: &Context) -> Vec<(u32, String)> {
+        let mut found = Vec::new();
+        (self.check)(cx, &mut found);
+        found
+    }
+}
+
+pub const ERRATA: [Erratum; 6] = [
+    Erratum {
+        id: "fast-return-low",
+        summary: "the fast register stack is only good for one priority",
+        reference: "DS39632 (PIC18F2455/2550/4455/4550 data sheet), section 5.1.3 Fast Register Stack",
+        workaround: "save W, STATUS and BSR in the low priority handler and return with plain retfie",
+        devices: &[],
+        revisions: &[],
+        check: fast_return_low,
+    },
+    Erratum {
+        id: "fast-return-high",
+        summary: "shadow registers can miss a write by the two-cycle instruction an interrupt lands on",
+        reference: "DS80220 (PIC18F2455/2550/4455/4550 silicon errata), Interrupts item",
+        workaround: "start the high priority handler with `call $+4, FAST` and `pop` so the shadow registers are written again",
+        devices: &["PIC18F2550", "PIC18F4550"],
+        revisions: &["A3", "B4"],
+        check: fast_return_high,
+    },
+    Erratum {
+        id: "movff-destination",
+        summary: "MOVFF can't write PCL, TOSU, TOSH or TOSL",
+        reference: "DS39632, section 26.2 Instruction Set, MOVFF",
+        workaround: "move the value through W with movwf",
+        devices: &[],
+        revisions: &[],
+        check: movff_destination,
+    },
+    Erratum {
+        id: "movff-interrupt-control",
+        summary: "MOVFF must not change interrupt control registers while interrupts are enabled",
+        reference: "DS39632, section 9.0 Interrupts, note on MOVFF",
+        workaround: "clear GIEH around the write, or use bsf/bcf/movwf",
+        devices: &[],
+        revisions: &[],
+        check: movff_interrupt_control,
+    },
+    Erratum {
+        id: "unlock-sequence",
+        summary: "a write to flash or EEPROM only starts right after the EECON2 unlock sequence, uninterrupted",
+        reference: "DS39632, sections 6.5.1 Flash Program Memory Write Sequence and 7.4 Writing to the Data EEPROM Memory",
+        workaround: "movlw 0x55; movwf EECON2; movlw 0xaa; movwf EECON2; bsf EECON1, WR, with interrupts disabled",
+        devices: &[],
+        revisions: &[],
+        check: unlock_sequence,
+    },
+    Erratum {
+        id: "tblwt-post-increment",
+        summary: "flash writes go to the block TBLPTR points into when WR is set",
+        reference: "DS39632, section 6.5 Writing to Flash Program Memory",
+        workaround: "use pre-increment table writes (tblwt+*), or step TBLPTR back before setting WR",
+        devices: &[],
+        revisions: &[],
+        check: tblwt_post_increment,
+    },
+];
+
+fn fast_return_low(cx: &Context, found: &mut Vec<(u32, String)>) {
+    if let Some(isr) = cx.isr.isr(Priority::Low).filter(|isr| isr.corrupts_shadow()) {
+        for addr in isr.fast_returns.iter() {
+            found.push((*addr, "retfie fast in the low priority handler restores what a high priority interrupt may have left in the shadow registers".to_string()));
+        }
+    }
+}
+
+fn fast_return_high(cx: &Context, found: &mut Vec<(u32, String)>) {
+    let isr = match cx.isr.isr(Priority::High) {
+        Some(isr) => isr,
+        None => { return; }
+    };
+    // the workaround: the handler's first instruction calls the next one, FAST, and pops
+    let mut addr = HIGH_PRIORITY_VECTOR;
+    while let Some(Instruction { opcode: Opcode::GOTO, operands: [Operand::ImmediateU32(target), _] }) = cx.disassembly.instruction(addr) {
+        if *target == addr {
+            break;
+        }
+        addr = *target;
+    }
+    let refreshed = match (cx.disassembly.instruction(addr), cx.disassembly.instruction(addr + 4)) {
+        (Some(call @ Instruction { opcode: Opcode::CALL_FAST, .. }), Some(Instruction { opcode: Opcode::POP, .. })) => {
+            call.operands[0] == Operand::ImmediateU32(addr + 4)
+        },
+        _ => false
+    };
+    if refreshed {
+        return;
+    }
+    for addr in isr.fast_returns.iter() {
+        found.push((*addr, "retfie fast in the high priority handler may restore W, STATUS or BSR from before the instruction it interrupted finished".to_string()));
+    }
+}
+
+fn movff_destination(cx: &Context, found: &mut Vec<(u32, String)>) {
+    let forbidden = [consts::SFRS::PCL, consts::SFRS::TOSU, consts::SFRS::TOSH, consts::SFRS::TOSL];
+    for (addr, inst) in cx.disassembly.instructions() {
+        if let (Opcode::MOVFF, Operand::AbsoluteFile(dest)) = (inst.opcode, inst.operands[1]) {
+            if forbidden.iter().any(|sfr| consts::SFR_BASE + sfr == dest) {
+                found.push((addr, format!("movff writes {}", cx.device.file_name(dest))));
+            }
+        }
+    }
+}
+
+fn movff_interrupt_control(cx: &Context, found: &mut Vec<(u32, String)>) {
+    for (addr, inst) in cx.disassembly.instructions() {
+        let dest = match (inst.opcode, inst.operands[1]) {
+            (Opcode::MOVFF, Operand::AbsoluteFile(dest)) => dest,
+            _ => { continue; }
+        };
+        let control = match cx.device.sfr_name(dest) {
+            Some(name) => name.starts_with("INTCON") || name.starts_with("PIE") || name.starts_with("PIR") ||
+                name.starts_with("IPR") || name == "RCON",
+            None => false
+        };
+        let masked = cx.isr.masking_before(addr).map(|masking| masking.masks(Priority::High)).unwrap_or(false);
+        if control && !masked {
+            found.push((addr, format!("movff writes {} where interrupts may be enabled", cx.device.file_name(dest))));
+        }
+    }
+}
+
+fn sets_wr(inst: &Instruction) -> bool {
+    inst.opcode == Opcode::BSF && file_written(inst) == Some(FileRef::Absolute(EECON1)) &&
+        inst.operands[1] == Operand::ImmediateU8(WR)
+}
+
+fn unlock_sequence(cx: &Context, found: &mut Vec<(u32, String)>) {
+    let instructions: Vec<(u32, &Instruction)> = cx.disassembly.instructions().collect();
+    for (i, (addr, inst)) in instructions.iter().enumerate() {
+        if !sets_wr(inst) {
+            continue;
+        }
+        // the four instructions before, back to back
+        let sequence = if i >= 4 { &instructions[i - 4..i] } else { &[][..] };
+        let contiguous = sequence.len() == 4 && sequence.iter().zip(instructions[i - 3..=i].iter())
+            .all(|((at, inst), (next, _))| at + instruction_len(inst) == *next);
+        let expected = contiguous && sequence.iter().enumerate().all(|(j, (_, inst))| {
+            match j {
+                0 => inst.opcode == Opcode::MOVLW && inst.operands[0] == Operand::ImmediateU8(0x55),
+                2 => inst.opcode == Opcode::MOVLW && inst.operands[0] == Operand::ImmediateU8(0xaa),
+                _ => inst.opcode == Opcode::MOVWF && file_written(inst) == Some(FileRef::Absolute(EECON2)),
+            }
+        });
+        if !expected {
+            found.push((*addr, "WR is set without the 0x55, 0xaa writes to EECON2 right before it".to_string()));
+            continue;
+        }
+        let start = sequence[0].0;
+        let masked = cx.isr.masking_before(start).map(|masking| masking.masks(Priority::High)).unwrap_or(false);
+        if !masked {
+            found.push((start, "interrupts may be enabled during the unlock sequence, and one taken in it stops the write".to_string()));
+        }
+    }
+}
+
+// steps TBLPTR back, or loads it afresh, so that a post-increment before it no longer counts.
+fn rewinds_tblptr(inst: &Instruction) -> bool {
+    if inst.opcode == Opcode::TBLRD_S_D {
+        return true;
+    }
+    file_written(inst) == Some(FileRef::Absolute(TBLPTRL)) && matches!(inst.opcode,
+        Opcode::DECF | Opcode::DECFSZ | Opcode::DCFSNZ | Opcode::SUBWF | Opcode::SUBWFB |
+        Opcode::SUBFWB | Opcode::MOVWF | Opcode::MOVFF | Opcode::CLRF)
+}
+
+fn tblwt_post_increment(cx: &Context, found: &mut Vec<(u32, String)>) {
+    let instructions: Vec<(u32, &Instruction)> = cx.disassembly.instructions().collect();
+    for (i, (addr, inst)) in instructions.iter().enumerate() {
+        if !sets_wr(inst) {
+            continue;
+        }
+        // the last table write before this in the same function, since any write before
+        let function = cx.cfg.functions().find(|function| {
+            cx.cfg.block_containing(*addr).map(|block| function.blocks.contains(&block.start)).unwrap_or(false)
+        });
+        let last = instructions[..i].iter().rev()
+            .take_while(|(at, _)| {
+                match (function, cx.cfg.block_containing(*at)) {
+                    (Some(function), Some(block)) => function.blocks.contains(&block.start),
+                    _ => false
+                }
+            })
+            .take_while(|(_, inst)| !sets_wr(inst))
+            .find(|(_, inst)| rewinds_tblptr(inst) ||
+                matches!(inst.opcode, Opcode::TBLWT_S | Opcode::TBLWT_S_I | Opcode::TBLWT_S_D | Opcode::TBLWT_I_S));
+        if let Some((at, Instruction { opcode: Opcode::TBLWT_S_I, .. })) = last {
+            found.push((*at, format!("the last table write before WR is set at {:04x} post-increments, and may leave TBLPTR in the next block", addr)));
+        }
+    }
+}
+
+// every erratum that applies to the device being linted.
+pub struct Errata;
+
+impl Rule for Errata {
+    fn name(&self) -> &'static str {
+        "errata"
+    }
+
+    fn check(&self, cx: &Context, out: &mut Vec<Diagnostic>) {
+        for erratum in ERRATA.iter().filter(|erratum| erratum.applies_to(cx.device)) {
+            let reference = if erratum.revisions.is_empty() {
+                erratum.reference.to_string()
+            } else {
+                format!("{}, silicon revisions {}", erratum.reference, erratum.revisions.join(", "))
+            };
+            for (addr, detail) in erratum.find(cx) {
+                out.push(Diagnostic {
+                    addr,
+                    rule: self.name(),
+                    severity: Severity::Warning,
+                    message: format!("{}: {}; {} ({})", erratum.id, detail, erratum.summary, reference),
+                    suggestion: Some(erratum.workaround.to_string()),
+                });
+            }
+        }
+    }
+}
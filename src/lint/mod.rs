@@ -0,0 +1,135 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::Instruction;
+use crate::analysis::bsr::BsrTracking;
+use crate::analysis::cfg::Cfg;
+use crate::analysis::disasm::Disassembly;
+use crate::analysis::isr::IsrAnalysis;
+use crate::device::Device;
+
+pub mod bank;
+pub mod errata;
+pub mod port;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub addr: u32,
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    // what to write instead, when there is an obvious fix
+    pub suggestion: Option<String>,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:04x}: {}[{}]: {}", self.addr, self.severity.name(), self.rule, self.message)?;
+        if let Some(suggestion) = self.suggestion.as_ref() {
+            write!(f, "\n    suggestion: {}", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+// what rules get to look at. analyses more than one rule wants are done once, here.
+pub struct Context<'a> {
+    pub disassembly: &'a Disassembly,
+    pub cfg: &'a Cfg,
+    pub device: &'a Device,
+    pub bsr: BsrTracking,
+    pub isr: IsrAnalysis,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(disassembly: &'a Disassembly, cfg: &'a Cfg, device: &'a Device) -> Context<'a> {
+        Context {
+            disassembly,
+            cfg,
+            device,
+            bsr: BsrTracking::analyze(cfg),
+            isr: IsrAnalysis::analyze(cfg, disassembly),
+        }
+    }
+}
+
+pub trait Rule {
+    // short, kebab-case, as diagnostics and configuration refer to the rule
+    fn name(&self) -> &'static str;
+
+    fn check_instruction(&self, _cx: &Context, _addr: u32, _inst: &Instruction, _out: &mut Vec<Diagnostic>) {}
+
+    // rules that look at one instruction at a time only need `check_instruction`; this is for
+    // rules that need more than that.
+    fn check(&self, cx: &Context, out: &mut Vec<Diagnostic>) {
+        for (addr, inst) in cx.disassembly.instructions() {
+            self.check_instruction(cx, addr, inst, out);
+        }
+    }
+}
+
+pub struct Linter {
+    rules: Vec<Box<dyn Rule>>,
+}
+
+impl Linter {
+    // a linter with no rules.
+    pub fn new() -> Linter {
+        Linter { rules: Vec::new() }
+    }
+
+    pub fn with_default_rules() -> Linter {
+        let mut linter = Linter::new();
+        linter.add(Box::new(port::PortReadModifyWrite));
+        linter.add(Box::new(bank::BankedBsr));
+        linter.add(Box::new(bank::AccessSplit));
+        linter.add(Box::new(errata::Errata));
+        linter
+    }
+
+    pub fn add(&mut self, rule: Box<dyn Rule>) {
+        self.rules.push(rule);
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item=&dyn Rule> {
+        self.rules.iter().map(|rule| rule.as_ref())
+    }
+
+    // drop the rule named `name`. false if there was none.
+    pub fn disable(&mut self, name: &str) -> bool {
+        let before = self.rules.len();
+        self.rules.retain(|rule| rule.name() != name);
+        self.rules.len() != before
+    }
+
+    // diagnostics from every rule, in address order.
+    pub fn run(&self, disassembly: &Disassembly, cfg: &Cfg, device: &Device) -> Vec<Diagnostic> {
+        let cx = Context::new(disassembly, cfg, device);
+        let mut diagnostics = Vec::new();
+        for rule in self.rules.iter() {
+            rule.check(&cx, &mut diagnostics);
+        }
+        diagnostics.sort_by_key(|diagnostic| (diagnostic.addr, diagnostic.rule));
+        diagnostics
+    }
+}
+
+impl Default for Linter {
+    fn default() -> Linter {
+        Linter::new()
+    }
+}
@@ -0,0 +1,43 @@
+use crate::{Instruction, Operand};
+use crate::analysis::{file_read, file_written};
+use crate::lint::{Context, Diagnostic, Rule, Severity};
+
+// an instruction that reads a PORT register, changes it and writes it back: BSF, BCF, BTG, and
+// anything with a file destination. reading PORTx reads the pins, so a pin being held, or still
+// slewing, from the last write loses that write. LATx holds what was written.
+pub struct PortReadModifyWrite;
+
+impl Rule for PortReadModifyWrite {
+    fn name(&self) -> &'static str {
+        "port-rmw"
+    }
+
+    fn check_instruction(&self, cx: &Context, addr: u32, inst: &Instruction, out: &mut Vec<Diagnostic>) {
+        let file = match (file_read(inst), file_written(inst)) {
+            (Some(read), Some(written)) if read == written => written,
+            _ => { return; }
+        };
+        for address in cx.bsr.resolve(addr, file) {
+            let port = match cx.device.port_at(address) {
+                Some(port) => port,
+                None => { continue; }
+            };
+            let mut fixed = *inst;
+            fixed.operands[0] = match inst.operands[0] {
+                Operand::File(_, banked) => Operand::File(port.lat as u8, banked),
+                Operand::RedirectableFile(_, banked, d) => Operand::RedirectableFile(port.lat as u8, banked, d),
+                other => other
+            };
+            out.push(Diagnostic {
+                addr,
+                rule: self.name(),
+                severity: Severity::Warning,
+                message: format!(
+                    "{} reads {} back from the pins before writing it",
+                    inst.opcode, cx.device.file_name(port.port)
+                ),
+                suggestion: Some(format!("write {} instead: {}", cx.device.file_name(port.lat), fixed)),
+            });
+        }
+    }
+}
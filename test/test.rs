@@ -0,0 +1,1570 @@
+extern crate yaxpeax_arch;
+extern crate yaxpeax_pic18;
+
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use yaxpeax_arch::{Decoder, U8Reader};
+use yaxpeax_pic18::{InstDecoder, Instruction, Opcode, Operand};
+use yaxpeax_pic18::analysis::FileRef;
+use yaxpeax_pic18::analysis::bsr::{Bank, BsrTracking};
+use yaxpeax_pic18::analysis::callgraph::{CallGraph, CallSite};
+use yaxpeax_pic18::analysis::cfg::{Cfg, Edge, EdgeKind};
+use yaxpeax_pic18::analysis::diff::{Diff, Line, MatchKind};
+use yaxpeax_pic18::analysis::constprop::Constants;
+use yaxpeax_pic18::analysis::disasm::Disassembler;
+use yaxpeax_pic18::analysis::fsr::{FsrTracking, IndirectAccess, IndirectMode};
+use yaxpeax_pic18::analysis::functions::{self, Evidence};
+use yaxpeax_pic18::analysis::isr::{IsrAnalysis, Priority};
+use yaxpeax_pic18::analysis::jumptable::{self, JumpTable, TableKind};
+use yaxpeax_pic18::analysis::signature::{self, Match, Signature, Signatures};
+use yaxpeax_pic18::analysis::tblptr::{TableRead, TableReads};
+use yaxpeax_pic18::analysis::xref::{Xref, XrefKind, Xrefs};
+use yaxpeax_pic18::decompile::Decompiler;
+use yaxpeax_pic18::device;
+use yaxpeax_pic18::emu::cpu::{Cpu, Stop};
+use yaxpeax_pic18::emu::eusart::Eusart;
+use yaxpeax_pic18::emu::interrupt::InterruptController;
+use yaxpeax_pic18::emu::memory::DataMemory;
+use yaxpeax_pic18::emu::nvm::Nvm;
+use yaxpeax_pic18::emu::peripheral::Peripheral;
+use yaxpeax_pic18::emu::stack::{ReturnStack, StackFault};
+use yaxpeax_pic18::emu::timer0::Timer0;
+use yaxpeax_pic18::ir::{self, Flag, Machine, Outcome, Reg};
+use yaxpeax_pic18::lint::{Context, Rule};
+use yaxpeax_pic18::lint::bank::{AccessSplit, BankedBsr};
+use yaxpeax_pic18::lint::errata::{Errata, ERRATA};
+use yaxpeax_pic18::lint::port::PortReadModifyWrite;
+
+const WREG: usize = 0xfe8;
+const BSR: usize = 0xfe0;
+const STATUS: usize = 0xfd8;
+const PRODL: usize = 0xff3;
+const PRODH: usize = 0xff4;
+const TABLAT: usize = 0xff5;
+const TBLPTRL: usize = 0xff6;
+const FSR0L: usize = 0xfe9;
+const FSR0H: usize = 0xfea;
+
+struct TestMachine {
+    data: [u8; 4096],
+    program: Vec<u8>,
+}
+
+impl TestMachine {
+    fn new() -> TestMachine {
+        TestMachine { data: [0; 4096], program: (0..=255).collect() }
+    }
+
+    fn w(&self) -> u8 {
+        self.data[WREG]
+    }
+
+    fn set_w(&mut self, value: u8) {
+        self.data[WREG] = value;
+    }
+}
+
+impl Machine for TestMachine {
+    fn reg(&self, reg: Reg) -> u32 {
+        match reg {
+            Reg::W => self.data[WREG] as u32,
+            Reg::Bsr => self.data[BSR] as u32,
+            Reg::ProdL => self.data[PRODL] as u32,
+            Reg::ProdH => self.data[PRODH] as u32,
+            Reg::Tablat => self.data[TABLAT] as u32,
+            Reg::Tblptr => {
+                (self.data[TBLPTRL] as u32) |
+                    ((self.data[TBLPTRL + 1] as u32) << 8) |
+                    ((self.data[TBLPTRL + 2] as u32) << 16)
+            }
+        }
+    }
+
+    fn set_reg(&mut self, reg: Reg, value: u32) {
+        match reg {
+            Reg::W => { self.data[WREG] = value as u8; },
+            Reg::Bsr => { self.data[BSR] = value as u8; },
+            Reg::ProdL => { self.data[PRODL] = value as u8; },
+            Reg::ProdH => { self.data[PRODH] = value as u8; },
+            Reg::Tablat => { self.data[TABLAT] = value as u8; },
+            Reg::Tblptr => {
+                self.data[TBLPTRL] = value as u8;
+                self.data[TBLPTRL + 1] = (value >> 8) as u8;
+                self.data[TBLPTRL + 2] = (value >> 16) as u8;
+            }
+        }
+    }
+
+    fn flag(&self, flag: Flag) -> bool {
+        self.data[STATUS] & (1 << flag.bit()) != 0
+    }
+
+    fn set_flag(&mut self, flag: Flag, value: bool) {
+        self.data[STATUS] &= !(1 << flag.bit());
+        self.data[STATUS] |= (value as u8) << flag.bit();
+    }
+
+    fn load(&mut self, addr: u16) -> u8 {
+        self.data[addr as usize]
+    }
+
+    fn store(&mut self, addr: u16, value: u8) {
+        self.data[addr as usize] = value;
+    }
+
+    fn program_load(&mut self, addr: u32) -> u8 {
+        self.program.get(addr as usize).cloned().unwrap_or(0xff)
+    }
+
+    fn program_store(&mut self, addr: u32, value: u8) {
+        if let Some(byte) = self.program.get_mut(addr as usize) {
+            *byte = value;
+        }
+    }
+}
+
+fn try_decode(words: &[u16]) -> Option<Instruction> {
+    let mut bytes = Vec::new();
+    for word in words.iter() {
+        bytes.push(*word as u8);
+        bytes.push((*word >> 8) as u8);
+    }
+    let mut reader = U8Reader::new(&bytes);
+    InstDecoder::default().decode(&mut reader).ok()
+}
+
+fn decode(words: &[u16]) -> Instruction {
+    try_decode(words).unwrap()
+}
+
+#[test]
+fn test_decode_call() {
+    // call 0x1234, and call 0x1234, FAST
+    for (first, opcode) in [(0xec1a, Opcode::CALL), (0xed1a, Opcode::CALL_FAST)].iter() {
+        let inst = decode(&[*first, 0xf009]);
+        assert_eq!(inst.opcode, *opcode);
+        assert!(matches!(inst.operands[0], Operand::ImmediateU32(0x1234)));
+    }
+    assert_eq!(decode(&[0xed1a, 0xf009]).to_string(), "call_fast #0x1234");
+    // 0xeb is MOVSF/MOVSS in the extended instruction set, not CALL
+    assert!(!matches!(try_decode(&[0xeb1a, 0xf009]).map(|inst| inst.opcode), Some(Opcode::CALL)));
+}
+
+fn run(machine: &mut TestMachine, addr: u32, words: &[u16]) -> Outcome {
+    let inst = decode(words);
+    ir::execute(&ir::lift(addr, &inst), machine)
+}
+
+fn flags(machine: &TestMachine) -> (bool, bool, bool, bool, bool) {
+    (machine.flag(Flag::C), machine.flag(Flag::DC), machine.flag(Flag::Z), machine.flag(Flag::OV), machine.flag(Flag::N))
+}
+
+#[test]
+fn test_addwf_flags() {
+    let mut m = TestMachine::new();
+    m.set_w(0x7f);
+    m.data[0x20] = 0x01;
+    // addwf 0x20, W, access
+    assert_eq!(run(&mut m, 0, &[0x2420]), Outcome::Next);
+    assert_eq!(m.w(), 0x80);
+    assert_eq!(m.data[0x20], 0x01);
+    // C, DC, Z, OV, N
+    assert_eq!(flags(&m), (false, true, false, true, true));
+
+    m.set_w(0xff);
+    // addwf 0x20, F, access
+    run(&mut m, 0, &[0x2620]);
+    assert_eq!(m.data[0x20], 0x00);
+    assert_eq!(flags(&m), (true, true, true, false, false));
+}
+
+#[test]
+fn test_addwfc_carries() {
+    let mut m = TestMachine::new();
+    m.set_flag(Flag::C, true);
+    m.set_w(0x10);
+    m.data[0x20] = 0x20;
+    // addwfc 0x20, F, access
+    run(&mut m, 0, &[0x2220]);
+    assert_eq!(m.data[0x20], 0x31);
+    assert!(!m.flag(Flag::C));
+}
+
+#[test]
+fn test_subwf_borrow() {
+    let mut m = TestMachine::new();
+    m.set_w(0x05);
+    m.data[0x20] = 0x03;
+    // subwf 0x20, F, access: 3 - 5 borrows, so C is clear
+    run(&mut m, 0, &[0x5e20]);
+    assert_eq!(m.data[0x20], 0xfe);
+    assert_eq!(flags(&m), (false, false, false, false, true));
+
+    m.data[0x20] = 0x05;
+    run(&mut m, 0, &[0x5e20]);
+    assert_eq!(m.data[0x20], 0x00);
+    assert!(m.flag(Flag::C));
+    assert!(m.flag(Flag::Z));
+}
+
+#[test]
+fn test_sublw() {
+    let mut m = TestMachine::new();
+    m.set_w(0x01);
+    // sublw 0x02
+    run(&mut m, 0, &[0x0802]);
+    assert_eq!(m.w(), 0x01);
+    assert!(m.flag(Flag::C));
+}
+
+#[test]
+fn test_decfsz_skips() {
+    let mut m = TestMachine::new();
+    m.data[0x20] = 2;
+    // decfsz 0x20, F, access
+    assert_eq!(run(&mut m, 0, &[0x2e20]), Outcome::Next);
+    assert_eq!(m.data[0x20], 1);
+    assert_eq!(run(&mut m, 0, &[0x2e20]), Outcome::Skip);
+    assert_eq!(m.data[0x20], 0);
+    // dcfsnz 0x20, F, access
+    assert_eq!(run(&mut m, 0, &[0x4e20]), Outcome::Skip);
+    assert_eq!(m.data[0x20], 0xff);
+}
+
+#[test]
+fn test_bit_tests() {
+    let mut m = TestMachine::new();
+    m.data[0x20] = 0x08;
+    // btfss 0x20, 3, access
+    assert_eq!(run(&mut m, 0, &[0xa620]), Outcome::Skip);
+    // btfsc 0x20, 3, access
+    assert_eq!(run(&mut m, 0, &[0xb620]), Outcome::Next);
+    // bcf 0x20, 3, access
+    run(&mut m, 0, &[0x9620]);
+    assert_eq!(m.data[0x20], 0x00);
+    // btg 0x20, 0, access
+    run(&mut m, 0, &[0x7020]);
+    assert_eq!(m.data[0x20], 0x01);
+}
+
+#[test]
+fn test_banked_access() {
+    let mut m = TestMachine::new();
+    // movlb 2; setf 0x10, banked
+    run(&mut m, 0, &[0x0102]);
+    run(&mut m, 0, &[0x6910]);
+    assert_eq!(m.data[0x210], 0xff);
+    assert_eq!(m.data[0x10], 0x00);
+}
+
+#[test]
+fn test_indirect_postinc() {
+    let mut m = TestMachine::new();
+    m.data[0x123] = 0x42;
+    // lfsr 0, 0x123
+    run(&mut m, 0, &[0xee01, 0xf023]);
+    assert_eq!((m.data[FSR0H], m.data[FSR0L]), (0x01, 0x23));
+    // movf POSTINC0, W, access
+    run(&mut m, 0, &[0x50ee]);
+    assert_eq!(m.w(), 0x42);
+    assert_eq!((m.data[FSR0H], m.data[FSR0L]), (0x01, 0x24));
+    // movwf PREINC0, access
+    run(&mut m, 0, &[0x6eec]);
+    assert_eq!(m.data[0x125], 0x42);
+    assert_eq!(m.data[FSR0L], 0x25);
+}
+
+#[test]
+fn test_indirect_plusw() {
+    let mut m = TestMachine::new();
+    m.data[0x100] = 0x11;
+    // lfsr 0, 0x101
+    run(&mut m, 0, &[0xee01, 0xf001]);
+    m.set_w(0xff);
+    // movf PLUSW0, W, access: W is -1 here
+    run(&mut m, 0, &[0x50eb]);
+    assert_eq!(m.w(), 0x11);
+    assert_eq!(m.data[FSR0L], 0x01);
+}
+
+#[test]
+fn test_table_reads_and_writes() {
+    let mut m = TestMachine::new();
+    m.data[TBLPTRL] = 0x10;
+    // tblrd*+
+    run(&mut m, 0, &[0x0009]);
+    assert_eq!(m.data[TABLAT], 0x10);
+    assert_eq!(m.data[TBLPTRL], 0x11);
+    // tblrd+*
+    run(&mut m, 0, &[0x000b]);
+    assert_eq!(m.data[TABLAT], 0x12);
+    // tblrd*-
+    run(&mut m, 0, &[0x000a]);
+    assert_eq!(m.data[TBLPTRL], 0x11);
+    m.data[TABLAT] = 0xaa;
+    // tblwt*
+    run(&mut m, 0, &[0x000c]);
+    assert_eq!(m.program[0x11], 0xaa);
+}
+
+#[test]
+fn test_multiply() {
+    let mut m = TestMachine::new();
+    m.set_w(0xc8);
+    m.data[0x20] = 0x64;
+    // mulwf 0x20, access
+    run(&mut m, 0, &[0x0220]);
+    assert_eq!((m.data[PRODH], m.data[PRODL]), (0x4e, 0x20));
+    // mullw 2
+    run(&mut m, 0, &[0x0d02]);
+    assert_eq!((m.data[PRODH], m.data[PRODL]), (0x01, 0x90));
+    assert_eq!(m.w(), 0xc8);
+}
+
+#[test]
+fn test_rotates() {
+    let mut m = TestMachine::new();
+    m.data[0x20] = 0x81;
+    // rlcf 0x20, F, access
+    run(&mut m, 0, &[0x3620]);
+    assert_eq!(m.data[0x20], 0x02);
+    assert!(m.flag(Flag::C));
+    // rrcf 0x20, F, access
+    run(&mut m, 0, &[0x3220]);
+    assert_eq!(m.data[0x20], 0x81);
+    assert!(!m.flag(Flag::C));
+    // rrncf 0x20, F, access
+    run(&mut m, 0, &[0x4220]);
+    assert_eq!(m.data[0x20], 0xc0);
+    // swapf 0x20, F, access
+    run(&mut m, 0, &[0x3a20]);
+    assert_eq!(m.data[0x20], 0x0c);
+}
+
+#[test]
+fn test_daw() {
+    let mut m = TestMachine::new();
+    m.set_w(0x19);
+    // addlw 0x28: 0x19 + 0x28 in BCD is 47
+    run(&mut m, 0, &[0x0f28]);
+    run(&mut m, 0, &[0x0007]);
+    assert_eq!(m.w(), 0x47);
+    assert!(!m.flag(Flag::C));
+
+    m.set_w(0x99);
+    run(&mut m, 0, &[0x0f01]);
+    run(&mut m, 0, &[0x0007]);
+    assert_eq!(m.w(), 0x00);
+    assert!(m.flag(Flag::C));
+}
+
+#[test]
+fn test_negf() {
+    let mut m = TestMachine::new();
+    m.data[0x20] = 0x01;
+    // negf 0x20, access
+    run(&mut m, 0, &[0x6c20]);
+    assert_eq!(m.data[0x20], 0xff);
+    assert!(m.flag(Flag::N));
+    assert!(!m.flag(Flag::C));
+}
+
+#[test]
+fn test_compares() {
+    let mut m = TestMachine::new();
+    m.set_w(0x10);
+    m.data[0x20] = 0x08;
+    // cpfslt 0x20, access
+    assert_eq!(run(&mut m, 0, &[0x6020]), Outcome::Skip);
+    // cpfsgt 0x20, access
+    assert_eq!(run(&mut m, 0, &[0x6420]), Outcome::Next);
+    // cpfseq 0x20, access
+    assert_eq!(run(&mut m, 0, &[0x6220]), Outcome::Next);
+    // tstfsz 0x21, access
+    assert_eq!(run(&mut m, 0, &[0x6621]), Outcome::Skip);
+}
+
+#[test]
+fn test_control_flow() {
+    let mut m = TestMachine::new();
+    // retlw 0x33
+    assert_eq!(run(&mut m, 0x100, &[0x0c33]), Outcome::Return { fast: false, enable_interrupts: false });
+    assert_eq!(m.w(), 0x33);
+    // call 0x1234
+    assert_eq!(run(&mut m, 0x100, &[0xec1a, 0xf009]), Outcome::Call { target: 0x1234, ret: 0x104, fast: false });
+    assert_eq!(run(&mut m, 0x100, &[0xed1a, 0xf009]), Outcome::Call { target: 0x1234, ret: 0x104, fast: true });
+    // rcall +2
+    assert_eq!(run(&mut m, 0x100, &[0xd802]), Outcome::Call { target: 0x106, ret: 0x102, fast: false });
+    // bra -1
+    assert_eq!(run(&mut m, 0x100, &[0xd7ff]), Outcome::Jump(0x100));
+    // retfie fast
+    assert_eq!(run(&mut m, 0x100, &[0x0011]), Outcome::Return { fast: true, enable_interrupts: true });
+
+    // bz +4, not taken and then taken
+    m.set_flag(Flag::Z, false);
+    assert_eq!(run(&mut m, 0x100, &[0xe004]), Outcome::Next);
+    m.set_flag(Flag::Z, true);
+    assert_eq!(run(&mut m, 0x100, &[0xe004]), Outcome::Jump(0x10a));
+}
+
+#[test]
+fn test_lifts_every_opcode() {
+    for word in 0..=0xffffu32 {
+        let inst = match try_decode(&[word as u16, 0xf000]) {
+            Some(inst) => inst,
+            None => { continue; }
+        };
+        let mut m = TestMachine::new();
+        ir::execute(&ir::lift(0x100, &inst), &mut m);
+    }
+}
+
+fn image(words: &[(u32, &[u16])]) -> Vec<u8> {
+    let mut bytes = vec![0; 0x200];
+    for (addr, code) in words.iter() {
+        for (i, word) in code.iter().enumerate() {
+            let at = *addr as usize + i * 2;
+            bytes[at] = *word as u8;
+            bytes[at + 1] = (*word >> 8) as u8;
+        }
+    }
+    bytes
+}
+
+fn cfg(words: &[(u32, &[u16])]) -> Cfg {
+    Cfg::build(&Disassembler::new(&image(words)).disassemble())
+}
+
+#[test]
+fn test_disassemble_skip_over_two_word_instruction() {
+    let disassembly = Disassembler::new(&image(&[
+        // btfsc 0x00, 0; goto 0x40; bra $
+        (0x00, &[0xb000, 0xef20, 0xf000, 0xd7ff]),
+        (0x08, &[0x0010]),
+        (0x18, &[0x0010]),
+        // bra $
+        (0x40, &[0xd7ff]),
+    ])).disassemble();
+    assert_eq!(disassembly.skip_target(0x02), 0x06);
+    assert!(disassembly.instruction(0x02).is_some());
+    // the second word of the goto is never decoded on its own
+    assert!(disassembly.instruction(0x04).is_none());
+    assert!(disassembly.is_code(0x04));
+    assert!(disassembly.instruction(0x06).is_some());
+    assert!(disassembly.instruction(0x40).is_some());
+    assert!(disassembly.undecodable().is_empty());
+    // nothing reaches the padding between the vectors and the code at 0x40
+    assert!(!disassembly.is_code(0x20));
+}
+
+#[test]
+fn test_cfg_blocks_and_edges() {
+    let cfg = cfg(&[
+        // bra 0x40
+        (0x00, &[0xd01f]),
+        (0x08, &[0x0010]),
+        (0x18, &[0x0010]),
+        // btfsc 0x00, 0; goto 0x80; call 0x60; bra $
+        (0x40, &[0xb000, 0xef40, 0xf000, 0xec30, 0xf000, 0xd7ff]),
+        // return
+        (0x60, &[0x0012]),
+        // bra $
+        (0x80, &[0xd7ff]),
+    ]);
+    let edge = |target, kind| Edge { target, kind };
+    assert_eq!(cfg.block(0x00).unwrap().successors, vec![edge(0x40, EdgeKind::Taken)]);
+    assert_eq!(cfg.block(0x40).unwrap().successors,
+        vec![edge(0x42, EdgeKind::Fallthrough), edge(0x46, EdgeKind::Skip)]);
+    assert_eq!(cfg.block(0x42).unwrap().successors, vec![edge(0x80, EdgeKind::Taken)]);
+    let call = cfg.block(0x46).unwrap();
+    assert_eq!(call.call, Some(0x60));
+    assert_eq!(call.successors, vec![edge(0x4a, EdgeKind::CallReturn)]);
+    assert_eq!(cfg.predecessors(0x46), &[(0x40, EdgeKind::Skip)]);
+    // the goto's second word belongs to its block
+    assert_eq!(cfg.block_containing(0x44).map(|block| block.start), Some(0x42));
+
+    assert_eq!(cfg.function(0x60).unwrap().blocks.iter().cloned().collect::<Vec<u32>>(), vec![0x60]);
+    let reset = &cfg.function(0x00).unwrap().blocks;
+    assert!([0x00, 0x40, 0x42, 0x46, 0x4a, 0x80].iter().all(|start| reset.contains(start)));
+    assert!(!reset.contains(&0x60));
+}
+
+#[test]
+fn test_cfg_dot_edge_styles() {
+    let mut disassembler = Disassembler::new(&image(&[
+        // bra 0x40
+        (0x00, &[0xd01f]),
+        (0x08, &[0x0010]),
+        (0x18, &[0x0010]),
+        // addwf PCL; bra $; bra $
+        (0x40, &[0x26f9, 0xd7ff, 0xd7ff]),
+    ]));
+    disassembler.add_indirect_target(0x40, 0x42);
+    disassembler.add_indirect_target(0x40, 0x44);
+    let dot = Cfg::build(&disassembler.disassemble()).to_dot();
+    assert!(dot.contains("\"0000\" -> \"0040\" [label=\"taken\", style=bold];"));
+    assert!(dot.contains("\"0040\" -> \"0042\" [label=\"computed\", style=dashed, color=red];"));
+    assert!(dot.contains("\"0040\" -> \"0044\" [label=\"computed\", style=dashed, color=red];"));
+}
+
+#[test]
+fn test_bsr_across_calls() {
+    let cfg = cfg(&[
+        // bra 0x40
+        (0x00, &[0xd01f]),
+        (0x08, &[0x0010]),
+        (0x18, &[0x0010]),
+        // movlb 2; rcall 0x60; movwf 0x10, banked; rcall 0x70; movwf 0x10, banked; bra $
+        (0x40, &[0x0102, 0xd80e, 0x6f10, 0xd814, 0x6f10, 0xd7ff]),
+        // movwf 0x20, banked; return
+        (0x60, &[0x6f20, 0x0012]),
+        // movlb 5; return
+        (0x70, &[0x0105, 0x0012]),
+    ]);
+    let bsr = BsrTracking::analyze(&cfg);
+    // the callee sees the caller's bank, and leaves it alone
+    assert_eq!(bsr.resolve(0x60, FileRef::Banked(0x20)), vec![0x220]);
+    assert_eq!(bsr.resolve(0x44, FileRef::Banked(0x10)), vec![0x210]);
+    assert_eq!(bsr.display(0x44, &decode(&[0x6f10])), "movwf [0x210]");
+    // a callee that selects another bank leaves it unknown
+    assert_eq!(bsr.bsr_before(0x48), Some(&Bank::Unknown));
+    assert!(bsr.resolve(0x48, FileRef::Banked(0x10)).is_empty());
+}
+
+#[test]
+fn test_fsr_post_increment() {
+    let cfg = cfg(&[
+        // bra 0x40
+        (0x00, &[0xd01f]),
+        (0x08, &[0x0010]),
+        (0x18, &[0x0010]),
+        // lfsr 0, 0x100; clrf POSTINC0; clrf POSTINC0; movlw 2; clrf PLUSW0; bra $
+        (0x40, &[0xee01, 0xf000, 0x6aee, 0x6aee, 0x0e02, 0x6aeb, 0xd7ff]),
+    ]);
+    let fsr = FsrTracking::analyze(&cfg);
+    let access = |mode, address| IndirectAccess { fsr: 0, mode, address: Some(address) };
+    assert_eq!(fsr.accesses(0x44), &[access(IndirectMode::PostInc, 0x100)]);
+    assert_eq!(fsr.accesses(0x46), &[access(IndirectMode::PostInc, 0x101)]);
+    assert_eq!(fsr.fsr_before(0x48, 0), Some(0x102));
+    assert_eq!(fsr.accesses(0x4a), &[access(IndirectMode::PlusW, 0x104)]);
+    // PLUSW0 leaves the pointer where it was
+    assert_eq!(fsr.fsr_before(0x4c, 0), Some(0x102));
+}
+
+#[test]
+fn test_stack_depth() {
+    let cfg = cfg(&[
+        // goto 0x40
+        (0x00, &[0xef20, 0xf000]),
+        // high priority: call 0x70; retfie
+        (0x08, &[0xec38, 0xf000, 0x0010]),
+        // low priority: retfie
+        (0x18, &[0x0010]),
+        // main: call 0x60; rcall 0x80; bra $
+        (0x40, &[0xec30, 0xf000, 0xd81d, 0xd7ff]),
+        // call 0x80; bra 0x70, a tail call
+        (0x60, &[0xec40, 0xf000, 0xd005]),
+        // call 0x90; return
+        (0x70, &[0xec48, 0xf000, 0x0012]),
+        // return
+        (0x80, &[0x0012]),
+        // call 0x80; return
+        (0x90, &[0xec40, 0xf000, 0x0012]),
+    ]);
+    let calls = CallGraph::build(&cfg);
+    assert_eq!(calls.calls(0x60), &[
+        CallSite { at: 0x60, target: Some(0x80), tail: false },
+        CallSite { at: 0x64, target: Some(0x70), tail: true },
+    ]);
+    assert_eq!(calls.callees(0x00).into_iter().collect::<Vec<_>>(), vec![0x60, 0x80]);
+    // the tail call costs nothing itself, but what it reaches still counts
+    assert_eq!(calls.depth(0x70).entries(), Some(2));
+    assert_eq!(calls.depth(0x60).entries(), Some(2));
+    assert_eq!(calls.depth(0x60).path(), &[0x60, 0x70, 0x90, 0x80]);
+    assert_eq!(calls.depth(0x00).entries(), Some(3));
+
+    // main can be interrupted by the low priority handler, and that by the high one, which
+    // pushes a return address and uses 2 more
+    let reports = calls.stack_reports();
+    let worst_case = |entry| reports.iter().find(|report| report.entry == entry).unwrap().worst_case;
+    assert_eq!(worst_case(0x00), Some(3 + 1 + 4));
+    assert_eq!(worst_case(0x08), Some(3));
+    assert_eq!(worst_case(0x18), Some(4));
+    assert!(calls.overflows().is_empty());
+}
+
+#[test]
+fn test_stack_depth_recursion() {
+    let mut cfg = cfg(&[
+        // rcall 0x40; bra $
+        (0x00, &[0xd81f, 0xd7ff]),
+        (0x08, &[0x0010]),
+        (0x18, &[0x0010]),
+        // btfsc 0x00, 0; bra 0x50; rcall 0x40; return
+        (0x40, &[0xb000, 0xd006, 0xdffd, 0x0012]),
+        // 0x50: tail calls back and forth use no stack: bra 0x60
+        (0x50, &[0xd007]),
+        // 0x60: btfss 0x00, 1; bra 0x50; return
+        (0x60, &[0xa200, 0xd7f6, 0x0012]),
+    ]);
+    cfg.add_function(0x50);
+    cfg.add_function(0x60);
+    let calls = CallGraph::build(&cfg);
+    assert_eq!(calls.depth(0x50).entries(), Some(0));
+    assert_eq!(calls.depth(0x40).entries(), None);
+    assert_eq!(calls.depth(0x00).entries(), None);
+    assert_eq!(calls.overflows().len(), 1);
+}
+
+#[test]
+fn test_table_read_loop() {
+    let words: &[(u32, &[u16])] = &[
+        // bra 0x40
+        (0x00, &[0xd01f]),
+        (0x08, &[0x0010]),
+        (0x18, &[0x0010]),
+        // clrf TBLPTRU; clrf TBLPTRH; movlw 0x80; movwf TBLPTRL
+        (0x40, &[0x6af8, 0x6af7, 0x0e80, 0x6ef6]),
+        // tblrd*+; movf TABLAT, w; bnz 0x48; bra $
+        (0x48, &[0x0009, 0x50f5, 0xe1fd, 0xd7ff]),
+        // "hi!"
+        (0x80, &[0x6968, 0x0021]),
+    ];
+    let disassembly = Disassembler::new(&image(words)).disassemble();
+    let reads = TableReads::analyze(&Cfg::build(&disassembly));
+    assert_eq!(reads.reads(), &[TableRead { at: 0x48, address: 0x80 }]);
+    assert_eq!(reads.data_ranges(&disassembly), vec![0x80..0x84]);
+}
+
+#[test]
+fn test_table_read_of_code() {
+    let words: &[(u32, &[u16])] = &[
+        // bra 0x40
+        (0x00, &[0xd01f]),
+        (0x08, &[0x0010]),
+        (0x18, &[0x0010]),
+        // clrf TBLPTRU; clrf TBLPTRH; movlw 0x40; movwf TBLPTRL; tblrd*; bra $
+        (0x40, &[0x6af8, 0x6af7, 0x0e40, 0x6ef6, 0x0008, 0xd7ff]),
+    ];
+    let disassembly = Disassembler::new(&image(words)).disassemble();
+    let reads = TableReads::analyze(&Cfg::build(&disassembly));
+    assert_eq!(reads.conflicts(&disassembly), vec![TableRead { at: 0x48, address: 0x40 }]);
+    assert!(reads.data_ranges(&disassembly).is_empty());
+}
+
+#[test]
+fn test_computed_goto_carry_fixup() {
+    let words: &[(u32, &[u16])] = &[
+        // bra 0x40
+        (0x00, &[0xd01f]),
+        (0x08, &[0x0010]),
+        (0x18, &[0x0010]),
+        // as XC8 emits it: movlw 0x00; movwf PCLATH; movlw 0xfc; addwf 0x00, w;
+        // btfsc STATUS, C; incf PCLATH; movwf PCL
+        (0x40, &[0x0e00, 0x6efa, 0x0efc, 0x2400, 0xb0d8, 0x2afa, 0x6ef9]),
+        // a table of bra $ running over into the next page
+        (0xfc, &[0xd7ff, 0xd7ff, 0xd7ff, 0xd7ff]),
+    ];
+    let disassembly = Disassembler::new(&image(words)).disassemble();
+    let tables = jumptable::find(&Cfg::build(&disassembly), &disassembly);
+    assert_eq!(tables, vec![JumpTable {
+        at: 0x4c,
+        kind: TableKind::Jump,
+        entries: vec![0xfc, 0xfe, 0x100, 0x102],
+        values: Vec::new(),
+    }]);
+
+    // PCLATH latches the PC after the read of PCL, here already in the next page
+    let words: &[(u32, &[u16])] = &[
+        // bra 0xfe
+        (0x00, &[0xd07e]),
+        (0x08, &[0x0010]),
+        (0x18, &[0x0010]),
+        // movf PCL, w; movlw 0x10; movwf PCL
+        (0xfe, &[0x50f9, 0x0e10, 0x6ef9]),
+        // bra $
+        (0x110, &[0xd7ff]),
+    ];
+    let disassembly = Disassembler::new(&image(words)).disassemble();
+    let tables = jumptable::find(&Cfg::build(&disassembly), &disassembly);
+    assert_eq!(tables, vec![JumpTable {
+        at: 0x102,
+        kind: TableKind::Jump,
+        entries: vec![0x110],
+        values: Vec::new(),
+    }]);
+}
+
+#[test]
+fn test_port_read_modify_write() {
+    let disassembly = Disassembler::new(&image(&[
+        // bra 0x40; retfie; retfie
+        (0x00, &[0xd01f]),
+        (0x08, &[0x0010]),
+        (0x18, &[0x0010]),
+        // bsf PORTB, 0; bsf LATB, 0; bra $
+        (0x40, &[0x8081, 0x808a, 0xd7ff]),
+    ])).disassemble();
+    let cfg = Cfg::build(&disassembly);
+    let mut out = Vec::new();
+    PortReadModifyWrite.check(&Context::new(&disassembly, &cfg, &device::PIC18F4550), &mut out);
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0].addr, 0x40);
+    assert_eq!(out[0].message, "bsf reads PORTB back from the pins before writing it");
+    assert_eq!(out[0].suggestion.as_deref(), Some("write LATB instead: bsf [LATB], #0x0"));
+}
+
+#[test]
+fn test_bank_rules() {
+    let disassembly = Disassembler::new(&image(&[
+        // bra 0x40; retfie; retfie
+        (0x00, &[0xd01f]),
+        (0x08, &[0x0010]),
+        (0x18, &[0x0010]),
+        // clrf 0x60, ACCESS; clrf 0x20, ACCESS; btfss 0x20, 0, ACCESS; movlb 2; clrf 0x20, BANKED; bra $
+        (0x40, &[0x6a60, 0x6a20, 0xa020, 0x0102, 0x6b20, 0xd7ff]),
+    ])).disassemble();
+    let cfg = Cfg::build(&disassembly);
+
+    // BSR is 0 or 2 depending on the skip
+    let mut out = Vec::new();
+    BankedBsr.check(&Context::new(&disassembly, &cfg, &device::PIC18F4550), &mut out);
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0].addr, 0x48);
+    assert_eq!(out[0].message, "clrf uses banked file 0x20 where BSR differs between paths, so it may be any of 0x020, 0x220");
+
+    // on its own, access-bank 0x60 is just an SFR on the PIC18F4550
+    let mut out = Vec::new();
+    AccessSplit.check(&Context::new(&disassembly, &cfg, &device::PIC18F4550), &mut out);
+    assert!(out.is_empty());
+
+    let disassembly = Disassembler::new(&image(&[
+        // bra 0x40; retfie; retfie
+        (0x00, &[0xd01f]),
+        (0x08, &[0x0010]),
+        (0x18, &[0x0010]),
+        // movlb 0; clrf 0x60, BANKED; clrf 0x60, ACCESS; lfsr 0, 0x061; clrf INDF0; clrf 0x61, ACCESS; bra $
+        (0x40, &[0x0100, 0x6b60, 0x6a60, 0xee00, 0xf061, 0x6aef, 0x6a61, 0xd7ff]),
+    ])).disassemble();
+    let cfg = Cfg::build(&disassembly);
+
+    // but 0x060 and 0x061 are used as RAM, which they only are on the PIC18F452
+    let mut out = Vec::new();
+    AccessSplit.check(&Context::new(&disassembly, &cfg, &device::PIC18F4550), &mut out);
+    assert_eq!(out.iter().map(|diagnostic| diagnostic.addr).collect::<Vec<u32>>(), vec![0x44, 0x4c]);
+    assert_eq!(out[0].message, "access-bank file 0x60 is 0xf60 on PIC18F4550, but other code uses RAM at 0x060");
+    let mut out = Vec::new();
+    AccessSplit.check(&Context::new(&disassembly, &cfg, &device::PIC18F452), &mut out);
+    assert!(out.is_empty());
+}
+
+fn errata_found(id: &str, words: &[(u32, &[u16])]) -> Vec<u32> {
+    let disassembly = Disassembler::new(&image(words)).disassemble();
+    let cfg = Cfg::build(&disassembly);
+    let cx = Context::new(&disassembly, &cfg, &device::PIC18F4550);
+    let erratum = ERRATA.iter().find(|erratum| erratum.id == id).unwrap();
+    erratum.find(&cx).into_iter().map(|(addr, _)| addr).collect()
+}
+
+#[test]
+fn test_errata_tblwt_post_increment() {
+    // bra 0x40; retfie; retfie
+    let vectors: [(u32, &[u16]); 3] = [(0x00, &[0xd01f]), (0x08, &[0x0010]), (0x18, &[0x0010])];
+    // tblwt*+; nop; movlw 0x55; movwf EECON2; movlw 0xaa; movwf EECON2; bsf EECON1, WR; bra $
+    let stepped = [0x000d, 0x0000, 0x0e55, 0x6ea7, 0x0eaa, 0x6ea7, 0x82a6, 0xd7ff];
+    let words = [vectors[0], vectors[1], vectors[2], (0x40, &stepped[..])];
+    assert_eq!(errata_found("tblwt-post-increment", &words), vec![0x40]);
+
+    // decf TBLPTRL puts the pointer back in the block just written
+    let mut rewound = stepped;
+    rewound[1] = 0x06f6;
+    let words = [vectors[0], vectors[1], vectors[2], (0x40, &rewound[..])];
+    assert_eq!(errata_found("tblwt-post-increment", &words), Vec::<u32>::new());
+
+    // as does a tblrd*-
+    rewound[1] = 0x000a;
+    let words = [vectors[0], vectors[1], vectors[2], (0x40, &rewound[..])];
+    assert_eq!(errata_found("tblwt-post-increment", &words), Vec::<u32>::new());
+}
+
+#[test]
+fn test_errata_fast_return_high() {
+    // bra $; call 0x0c, FAST; pop; retfie fast; retfie
+    let mut handler = [0xed06, 0xf000, 0x0006, 0x0011];
+    let words = [(0x00, &[0xd7ff][..]), (0x08, &handler[..]), (0x18, &[0x0010][..])];
+    assert_eq!(errata_found("fast-return-high", &words), Vec::<u32>::new());
+
+    // a plain call leaves the shadow registers as the interrupt found them
+    handler[0] = 0xec06;
+    let words = [(0x00, &[0xd7ff][..]), (0x08, &handler[..]), (0x18, &[0x0010][..])];
+    assert_eq!(errata_found("fast-return-high", &words), vec![0x0e]);
+}
+
+#[test]
+fn test_errata_silicon_revisions() {
+    let disassembly = Disassembler::new(&image(&[
+        // bra $; retfie fast; retfie
+        (0x00, &[0xd7ff]),
+        (0x08, &[0x0011]),
+        (0x18, &[0x0010]),
+    ])).disassemble();
+    let cfg = Cfg::build(&disassembly);
+    let mut out = Vec::new();
+    Errata.check(&Context::new(&disassembly, &cfg, &device::PIC18F4550), &mut out);
+    let fast_return = out.iter().find(|diagnostic| diagnostic.message.starts_with("fast-return-high")).unwrap();
+    assert_eq!(fast_return.addr, 0x08);
+    assert!(fast_return.message.ends_with("(DS80220 (PIC18F2455/2550/4455/4550 silicon errata), Interrupts item, silicon revisions A3, B4)"));
+
+    // the PIC18F452 isn't affected
+    let mut out = Vec::new();
+    Errata.check(&Context::new(&disassembly, &cfg, &device::PIC18F452), &mut out);
+    assert!(out.iter().all(|diagnostic| !diagnostic.message.starts_with("fast-return-high")));
+}
+
+#[test]
+fn test_xrefs() {
+    let disassembly = Disassembler::new(&image(&[
+        // bra 0x40
+        (0x00, &[0xd01f]),
+        (0x08, &[0x0010]),
+        (0x18, &[0x0010]),
+        // call 0x60; movlb 1; incf 0x20, f, banked; movf PORTB, w; lfsr 0, 0x180; clrf INDF0;
+        // bra 0x40
+        (0x40, &[0xec30, 0xf000, 0x0101, 0x2b20, 0x5081, 0xee01, 0xf080, 0x6aef, 0xd7f7]),
+        // return
+        (0x60, &[0x0012]),
+    ])).disassemble();
+    let cfg = Cfg::build(&disassembly);
+    let bsr = BsrTracking::analyze(&cfg);
+    let fsr = FsrTracking::analyze(&cfg);
+    let xrefs = Xrefs::build(&disassembly, Some(&bsr), Some(&fsr));
+    assert_eq!(xrefs.callers(0x60), vec![0x40]);
+    assert_eq!(xrefs.to_code(0x40), &[
+        Xref { from: 0x00, kind: XrefKind::Jump },
+        Xref { from: 0x50, kind: XrefKind::Jump },
+    ]);
+    assert_eq!(xrefs.readers(0x120), vec![0x46]);
+    assert_eq!(xrefs.writers(0x120), vec![0x46]);
+    assert_eq!(xrefs.to_sfr("PORTB"), &[Xref { from: 0x48, kind: XrefKind::Read }]);
+    // through FSR0, as well as INDF0 itself
+    assert_eq!(xrefs.writers(0x180), vec![0x4e]);
+    assert_eq!(xrefs.to_sfr("INDF0"), &[Xref { from: 0x4e, kind: XrefKind::Write }]);
+    assert!(xrefs.unresolved().is_empty());
+
+    let unresolved = Xrefs::build(&disassembly, None, None);
+    assert_eq!(unresolved.unresolved(), &[(0x46, FileRef::Banked(0x20))]);
+    assert!(unresolved.writers(0x180).is_empty());
+}
+
+#[test]
+fn test_constants_across_blocks() {
+    let disassembly = Disassembler::new(&image(&[
+        // bra 0x40
+        (0x00, &[0xd01f]),
+        (0x08, &[0x0010]),
+        (0x18, &[0x0010]),
+        // movlw 5; movwf 0x10; btfsc PORTB, 0; movwf 0x11; addwf 0x10, w; movwf 0x12; bra $
+        (0x40, &[0x0e05, 0x6e10, 0xb081, 0x6e11, 0x2410, 0x6e12, 0xd7ff]),
+    ])).disassemble();
+    let constants = Constants::analyze(&Cfg::build(&disassembly), &disassembly);
+    // both ways into 0x48 agree on W and 0x10, but only one wrote 0x11
+    assert_eq!(constants.w_before(0x48), Some(5));
+    assert_eq!(constants.value_before(0x48, 0x10), Some(5));
+    assert_eq!(constants.value_before(0x48, 0x11), None);
+    assert_eq!(constants.value_before(0x4c, 0x12), Some(10));
+    assert_eq!(constants.before(0x4c).unwrap().flag(Flag::Z), Some(false));
+    // PORTB reads whatever is on the pins
+    assert_eq!(constants.value_before(0x46, 0xf81), None);
+    assert!(constants.display(0x48, disassembly.instruction(0x48).unwrap())
+        .ends_with("  ; W = 0x05, 0x10 = 0x05, W <- 0x0a"));
+    assert_eq!(constants.display(0x4a, disassembly.instruction(0x4a).unwrap()), "movwf [0x12]  ; W = 0x0a, 0x12 <- 0x0a");
+}
+
+#[test]
+fn test_function_detection() {
+    let mut disassembler = Disassembler::new(&image(&[
+        // bra 0x40
+        (0x00, &[0xd01f]),
+        (0x08, &[0x0010]),
+        (0x18, &[0x0010]),
+        // call 0x60; bra $
+        (0x40, &[0xec30, 0xf000, 0xd7ff]),
+        // return
+        (0x60, &[0x0012]),
+        // nothing reaches these: movlb 1; return
+        (0x80, &[0x0101, 0x0012]),
+        // movff FSR2L, POSTINC1; movff FSR1L, FSR2L; return
+        (0xa0, &[0xcfd9, 0xffe6, 0xcfe1, 0xffd9, 0x0012]),
+    ]));
+    let candidates = functions::detect(&disassembler.disassemble());
+    let found = |addr| candidates.iter().find(|candidate| candidate.addr == addr).unwrap();
+    assert_eq!(found(0x60).evidence, vec![Evidence::CallTarget, Evidence::Aligned]);
+    assert_eq!(found(0x80).evidence, vec![Evidence::AfterTerminator, Evidence::BankSelect, Evidence::Aligned]);
+    assert!((found(0x80).confidence - (1.0 - 0.5 * 0.85 * 0.9)).abs() < 1e-6);
+    assert_eq!(found(0xa0).evidence, vec![Evidence::FramePrologue, Evidence::Aligned]);
+    assert!(found(0xa0).confidence > 0.9);
+    // padding is never a candidate
+    assert!(candidates.iter().all(|candidate| candidate.addr < 0x20 || candidate.addr >= 0x40));
+
+    assert!(functions::add_entries(&mut disassembler, &candidates, 0.6));
+    let disassembly = disassembler.disassemble();
+    assert!(disassembly.instruction(0x82).is_some());
+    assert!(disassembly.instruction(0xa8).is_some());
+    assert!(!functions::add_entries(&mut disassembler, &candidates, 0.6));
+}
+
+#[test]
+fn test_decompile() {
+    let cfg = cfg(&[
+        // bra 0x40
+        (0x00, &[0xd01f]),
+        (0x08, &[0x0010]),
+        (0x18, &[0x0010]),
+        // call 0x60; call 0x80; bra $
+        (0x40, &[0xec30, 0xf000, 0xec40, 0xf000, 0xd7ff]),
+        // movlw 5; movwf 0x10; decfsz 0x10; bra 0x64; btfsc PORTB, 0; incf 0x11; return
+        (0x60, &[0x0e05, 0x6e10, 0x2e10, 0xd7fe, 0xb081, 0x2a11, 0x0012]),
+        // lfsr 0, 0x123; movwf POSTINC0; movf 0x12, f; bz 0x90; decf 0x12, f; bsf LATB, 1;
+        // bra 0x86; retlw 1
+        (0x80, &[0xee01, 0xf023, 0x6eee, 0x5212, 0xe003, 0x0612, 0x828a, 0xd7fb, 0x0c01]),
+    ]);
+    let decompiler = Decompiler::new(&cfg);
+    assert_eq!(decompiler.function(0x60).unwrap(), "\
+void func_0060(void) {
+    W = 0x05;
+    ram[0x010] = 0x05;
+    while (--ram[0x010] != 0) {}
+    if (PORTB & (1 << 0)) {
+        ram[0x011]++;
+    }
+    return;
+}
+");
+    // tested at the top: a while
+    assert_eq!(decompiler.function(0x80).unwrap(), "\
+uint8_t func_0080(void) {
+    FSR0 = 0x123;
+    *FSR0 = W;
+    FSR0++;
+    while (ram[0x012] != 0) {
+        ram[0x012]--;
+        LATB |= (1 << 1);
+    }
+    return 0x01;
+}
+");
+    let program = decompiler.program();
+    assert!(program.starts_with("void reset(void) {\n"));
+    assert!(program.contains("    func_0060();\n"));
+}
+
+#[test]
+fn test_signatures() {
+    let library = Disassembler::new(&image(&[
+        // movlb 1; movf 0x10, w; call 0x80; return
+        (0x60, &[0x0101, 0x5010, 0xec40, 0xf000, 0x0012]),
+    ])).disassemble();
+    let generated = signature::generate(&library, &[("helper".to_string(), 0x60), ("next".to_string(), 0x6a)]);
+    let helper = generated.iter().find(|signature| signature.name == "helper").unwrap();
+    // the bank, the RAM address and the call target are wildcards
+    assert_eq!(helper.to_string(), "helper 0.01..50..ec..f.1200");
+    assert_eq!(Signature::parse(&helper.to_string(), 1).as_ref(), Ok(helper));
+    assert_eq!(Signature::parse("helper 0.01..5", 3).unwrap_err().line, 3);
+
+    let mut signatures = Signatures::parse("# runtime\n\nhelper 0.01..50..ec..f.1200\n").unwrap();
+    assert_eq!(signatures.iter().count(), 1);
+    // the same routine linked elsewhere, with other RAM and another callee
+    let disassembly = Disassembler::new(&image(&[
+        // bra 0x40
+        (0x00, &[0xd01f]),
+        (0x08, &[0x0010]),
+        (0x18, &[0x0010]),
+        // call 0xa0; bra $
+        (0x40, &[0xec50, 0xf000, 0xd7ff]),
+        // movlb 3; movf 0x20, w; call 0x120; return
+        (0xa0, &[0x0103, 0x5020, 0xec90, 0xf000, 0x0012]),
+        // return
+        (0x120, &[0x0012]),
+    ])).disassemble();
+    let cfg = Cfg::build(&disassembly);
+    assert_eq!(signatures.identify(&cfg, &disassembly), vec![Match { entry: 0xa0, name: "helper".to_string() }]);
+
+    // the longest signature wins
+    signatures.add(Signature::parse("helper_prefix 0.01..50", 1).unwrap());
+    assert_eq!(signatures.match_at(&disassembly, 0xa0).map(|signature| signature.name.as_str()), Some("helper"));
+}
+
+#[test]
+fn test_diff() {
+    let old = cfg(&[
+        // bra 0x40
+        (0x00, &[0xd01f]),
+        (0x08, &[0x0010]),
+        (0x18, &[0x0010]),
+        // call 0x60; call 0x70; bra $
+        (0x40, &[0xec30, 0xf000, 0xec38, 0xf000, 0xd7ff]),
+        // movlw 1; movwf 0x10; return
+        (0x60, &[0x0e01, 0x6e10, 0x0012]),
+        // movlw 2; movwf 0x11; incf 0x12; return
+        (0x70, &[0x0e02, 0x6e11, 0x2a12, 0x0012]),
+    ]);
+    // the same, with both functions moved and the second one's literal changed
+    let new = cfg(&[
+        (0x00, &[0xd01f]),
+        (0x08, &[0x0010]),
+        (0x18, &[0x0010]),
+        // call 0x80; call 0x90; bra $
+        (0x40, &[0xec40, 0xf000, 0xec48, 0xf000, 0xd7ff]),
+        (0x80, &[0x0e01, 0x6e10, 0x0012]),
+        // movlw 3; movwf 0x11; incf 0x12; return
+        (0x90, &[0x0e03, 0x6e11, 0x2a12, 0x0012]),
+    ]);
+    let diff = Diff::compute(&old, &new);
+    assert!(diff.removed.is_empty());
+    assert!(diff.added.is_empty());
+    let matched = |entry| diff.matches.iter().find(|m| m.old == entry).unwrap();
+    assert_eq!(matched(0x00).kind, MatchKind::Vector);
+    // call targets moving doesn't count as a change
+    assert!(matched(0x00).is_identical());
+    assert_eq!((matched(0x60).new, matched(0x60).kind), (0x80, MatchKind::Identical));
+    assert!(matched(0x60).is_identical());
+
+    let changed: Vec<_> = diff.changed().collect();
+    assert_eq!(changed.len(), 1);
+    assert_eq!((changed[0].old, changed[0].new), (0x70, 0x90));
+    assert!((changed[0].similarity - 0.75).abs() < 1e-6);
+    assert!(matches!(changed[0].lines[0], Line::Changed((0x70, _), (0x90, _))));
+    assert!(changed[0].lines[1..].iter().all(|line| matches!(line, Line::Same(_, _))));
+}
+
+#[test]
+fn test_isr_analysis() {
+    let disassembly = Disassembler::new(&image(&[
+        // bra 0x40
+        (0x00, &[0xd01f]),
+        // high priority: call 0x80; incf 0x20; retfie fast
+        (0x08, &[0xec40, 0xf000, 0x2a20, 0x0011]),
+        // low priority: movlw 1; retfie
+        (0x18, &[0x0e01, 0x0010]),
+        // bsf INTCON, GIEH; movf 0x20, w; bcf INTCON, GIEH; incf 0x20; bsf INTCON, GIEH; bra $
+        (0x40, &[0x8ef2, 0x5020, 0x9ef2, 0x2a20, 0x8ef2, 0xd7ff]),
+        // return
+        (0x80, &[0x0012]),
+    ])).disassemble();
+    let cfg = Cfg::build(&disassembly);
+    let isr = IsrAnalysis::analyze(&cfg, &disassembly);
+
+    let high = isr.isr(Priority::High).unwrap();
+    assert_eq!(high.functions.iter().cloned().collect::<Vec<u32>>(), vec![0x08, 0x80]);
+    assert_eq!(high.fast_returns, vec![0x0e]);
+    let low = isr.isr(Priority::Low).unwrap();
+    assert!(low.clobbered.contains(&(WREG as u16)));
+    assert!(!low.corrupts_shadow());
+
+    // interrupts are off out of reset
+    assert!(isr.masking_before(0x40).unwrap().masks(Priority::High));
+    assert!(!isr.masking_before(0x42).unwrap().masks(Priority::High));
+    assert!(isr.masking_before(0x46).unwrap().masks(Priority::High));
+    // only the read before interrupts are masked is at risk
+    assert_eq!(isr.shared().len(), 1);
+    let shared = &isr.shared()[0];
+    assert_eq!((shared.address, shared.vector), (0x20, 0x08));
+    assert_eq!(shared.isr_accesses, vec![(0x0c, false), (0x0c, true)]);
+    assert_eq!(shared.unprotected, vec![(0x42, false)]);
+
+    // without IPEN, the handler at 0x08 runs on into 0x18
+    let disassembly = Disassembler::new(&image(&[
+        // bra $
+        (0x00, &[0xd7ff]),
+        // nop x8
+        (0x08, &[0x0000; 8]),
+        // movlw 1; retfie
+        (0x18, &[0x0e01, 0x0010]),
+    ])).disassemble();
+    let cfg = Cfg::build(&disassembly);
+    let isr = IsrAnalysis::analyze(&cfg, &disassembly);
+    assert!(isr.isr(Priority::Low).is_none());
+    let high = isr.isr(Priority::High).unwrap();
+    assert_eq!(high.functions.iter().cloned().collect::<Vec<u32>>(), vec![0x08, 0x18]);
+    assert!(high.clobbered.contains(&(WREG as u16)));
+    assert!(high.saved.is_empty());
+
+    // a handler that never returns isn't taken to put anything back
+    let disassembly = Disassembler::new(&image(&[
+        // bra $
+        (0x00, &[0xd7ff]),
+        // movlw 1; bra $
+        (0x08, &[0x0e01, 0xd7ff]),
+        // retfie
+        (0x18, &[0x0010]),
+    ])).disassemble();
+    let cfg = Cfg::build(&disassembly);
+    let isr = IsrAnalysis::analyze(&cfg, &disassembly);
+    let high = isr.isr(Priority::High).unwrap();
+    assert!(high.clobbered.contains(&(WREG as u16)));
+    assert!(high.saved.is_empty());
+}
+
+#[test]
+fn test_emu_cycles() {
+    let mut cpu = Cpu::new(&image(&[(0, &[
+        // movlw 3; movwf 0x00
+        0x0e03, 0x6e00,
+        // decfsz 0x00, f; bra $-2
+        0x2e00, 0xd7fe,
+        // sleep
+        0x0003,
+    ])]));
+    assert_eq!(cpu.run(1000), Stop::Sleep);
+    assert_eq!(cpu.pc(), 0x0a);
+    assert_eq!(cpu.file(0x000), 0);
+    // the last decfsz skips, and each taken bra is two cycles
+    assert_eq!(cpu.cycles(), 11);
+}
+
+#[test]
+fn test_emu_flags() {
+    let mut cpu = Cpu::new(&image(&[(0, &[
+        // movlw 0xff; addlw 1: W wraps to zero with C, DC and Z set
+        0x0eff, 0x0f01,
+        // bnz 0x10; bnc 0x10; btfss STATUS, Z; bra 0x10
+        0xe105, 0xe304, 0xa4d8, 0xd002,
+        // movlw 1; sleep
+        0x0e01, 0x0003,
+        // 0x10: movlw 0xee; sleep
+        0x0eee, 0x0003,
+    ])]));
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    assert_eq!(cpu.w(), 0);
+    assert_eq!(cpu.status(), 0x07);
+    assert_eq!(cpu.run(100), Stop::Sleep);
+    assert_eq!(cpu.w(), 0x01);
+    assert_eq!(cpu.pc(), 0x10);
+
+    // and clear again: bz and bc fall through, and bnz is taken
+    let mut cpu = Cpu::new(&image(&[(0, &[
+        // movlw 1; addlw 1; bz 0x10; bc 0x10; bnz 0x0c; movlw 0xee; movlw 1; sleep
+        0x0e01, 0x0f01, 0xe005, 0xe204, 0xe101, 0x0eee, 0x0e01, 0x0003,
+    ])]));
+    assert_eq!(cpu.run(100), Stop::Sleep);
+    assert_eq!(cpu.w(), 0x01);
+    assert_eq!(cpu.status(), 0);
+}
+
+#[test]
+fn test_emu_call() {
+    let mut cpu = Cpu::new(&image(&[
+        // movlw 0x42; addlw 1; return
+        (0x10, &[0x0e42, 0x0f01, 0x0012]),
+        // rcall $
+        (0x20, &[0xdfff]),
+        // movlw 0; return fast
+        (0x50, &[0x0e00, 0x0013]),
+        // call 0x50, fast; nop
+        (0x60, &[0xed28, 0xf000, 0x0000]),
+    ]));
+    assert_eq!(cpu.call(0x10, 100), Ok(4));
+    assert_eq!(cpu.w(), 0x43);
+    assert!(cpu.stack().is_empty());
+
+    cpu.set_w(9);
+    cpu.set_pc(0x60);
+    cpu.step().unwrap();
+    assert_eq!(cpu.stack().entries(), &[0x64]);
+    cpu.step().unwrap();
+    assert_eq!(cpu.w(), 0);
+    cpu.step().unwrap();
+    assert_eq!(cpu.pc(), 0x64);
+    assert_eq!(cpu.w(), 9);
+
+    // with STVREN, the 31st rcall fills the stack and resets the part
+    cpu.set_pc(0x20);
+    for _ in 0..30 {
+        cpu.step().unwrap();
+    }
+    assert_eq!(cpu.stack().depth(), 30);
+    assert_eq!(cpu.step().err(), Some(Stop::StackOverflow(0x20)));
+    // the reset keeps STKFUL
+    assert_eq!(cpu.pc(), 0);
+    assert_eq!(cpu.file(0xffc), 0x80);
+}
+
+#[test]
+fn test_emu_skips_two_word_instruction() {
+    let mut cpu = Cpu::new(&image(&[
+        // btfsc 0x00, 0; goto 0x100; movlw 7
+        (0x30, &[0xb000, 0xef80, 0xf000, 0x0e07]),
+    ]));
+    cpu.set_pc(0x30);
+    let step = cpu.step().unwrap();
+    assert_eq!(step.cycles, 3);
+    assert_eq!(cpu.pc(), 0x36);
+
+    // the second word of goto runs as nop when jumped into
+    cpu.set_pc(0x34);
+    assert_eq!(cpu.step().unwrap().cycles, 1);
+    assert_eq!(cpu.pc(), 0x36);
+}
+
+#[test]
+fn test_emu_computed_goto() {
+    let mut cpu = Cpu::new(&image(&[
+        // movlw 1; movwf PCLATH; movlw 0x40; movwf PCL
+        (0, &[0x0e01, 0x6efa, 0x0e40, 0x6ef9]),
+        // retlw 0x99
+        (0x140, &[0x0c99]),
+    ]));
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    assert_eq!(cpu.step().unwrap().cycles, 2);
+    assert_eq!(cpu.pc(), 0x140);
+}
+
+#[test]
+fn test_data_memory() {
+    let mut memory = DataMemory::for_device(&device::PIC18F4550);
+    assert_eq!(memory.access_split(), 0x60);
+    // no RAM past 2K
+    memory.write(0x800, 1);
+    assert_eq!(memory.read(0x800), 0);
+
+    // movwf POSTINC0; movf PREINC0; PLUSW0 with a negative W
+    memory.set_fsr(0, 0x120);
+    memory.write(0xfee, 0xaa);
+    assert_eq!(memory.fsr(0), 0x121);
+    memory.write(0x122, 0xbb);
+    assert_eq!(memory.read(0xfec), 0xbb);
+    assert_eq!(memory.fsr(0), 0x122);
+    memory.set_w(0xfe);
+    assert_eq!(memory.read(0xfeb), 0xaa);
+    assert_eq!(memory.fsr(0), 0x122);
+    // W is WREG
+    assert_eq!(memory.peek(0xfe8), 0xfe);
+    // an indirect register through a pointer reads as zero and ignores writes
+    memory.set_fsr(1, 0xfef);
+    memory.write(0xfe7, 0x55);
+    assert_eq!(memory.read(0xfe7), 0);
+
+    // reading PCL latches PCLATH and PCLATU; writing it jumps
+    memory.set_pc(0x12346);
+    assert_eq!(memory.read(0xff9), 0x46);
+    assert_eq!(memory.peek(0xffa), 0x23);
+    assert_eq!(memory.peek(0xffb), 0x01);
+    memory.write(0xffa, 0x02);
+    memory.write(0xff9, 0x10);
+    assert_eq!(memory.take_pc_write(), Some(0x10210));
+    assert_eq!(memory.take_pc_write(), None);
+
+    // TOSx are the top of the stack
+    memory.stack_mut().push(0x1234).unwrap();
+    assert_eq!(memory.read(0xffc), 1);
+    assert_eq!((memory.read(0xffe), memory.read(0xffd)), (0x12, 0x34));
+    memory.write(0xffd, 0x00);
+    assert_eq!(memory.stack().entries(), &[0x1200]);
+}
+
+#[test]
+fn test_emu_access_split() {
+    // movlw 0x5a; movwf 0x5f; movwf 0x60; movlb 3; movwf 0x10, BANKED; sleep
+    let program = image(&[(0, &[0x0e5a, 0x6e5f, 0x6e60, 0x0103, 0x6f10, 0x0003])]);
+    // the 4550 splits the access bank at 0x60, so access file 0x60 is the SFR at 0xf60
+    let mut cpu = Cpu::for_device(&program, &device::PIC18F4550);
+    assert_eq!(cpu.run(100), Stop::Sleep);
+    assert_eq!((cpu.file(0x05f), cpu.file(0x060), cpu.file(0xf60)), (0x5a, 0, 0x5a));
+    assert_eq!(cpu.file(0x310), 0x5a);
+    // the 452 splits it at 0x80, leaving 0x60 in RAM
+    let mut cpu = Cpu::for_device(&program, &device::PIC18F452);
+    assert_eq!(cpu.run(100), Stop::Sleep);
+    assert_eq!((cpu.file(0x05f), cpu.file(0x060), cpu.file(0xf60)), (0x5a, 0x5a, 0));
+}
+
+#[test]
+fn test_return_stack() {
+    let mut stack = ReturnStack::new(false);
+    assert_eq!(stack.pop(), Err(StackFault::Underflow));
+    assert_eq!(stack.stkptr(), 0x40);
+    for i in 0..31 {
+        stack.push(i * 2).unwrap();
+    }
+    // the push filling the last entry sets STKFUL, and one more is lost
+    assert_eq!(stack.stkptr(), 0xdf);
+    assert_eq!(stack.push(0x100), Err(StackFault::Overflow));
+    assert_eq!(stack.tos(), 60);
+    // software can only clear the flags
+    stack.set_stkptr(0xc5);
+    assert_eq!(stack.stkptr(), 0xc5);
+    stack.set_stkptr(0x05);
+    assert_eq!(stack.stkptr(), 0x05);
+    assert_eq!(stack.pop(), Ok(8));
+    stack.set_tos(0x12345);
+    assert_eq!(stack.entries(), &[0, 2, 4, 0x12345]);
+
+    // without STVREN, a return off an empty stack goes to zero and the core keeps running
+    let mut cpu = Cpu::new(&image(&[
+        // return
+        (0x40, &[0x0012]),
+    ]));
+    cpu.memory_mut().stack_mut().set_stvren(false);
+    cpu.set_pc(0x40);
+    cpu.step().unwrap();
+    assert_eq!(cpu.pc(), 0);
+    assert!(cpu.stack().underflow());
+    cpu.reset();
+    assert!(cpu.stack().underflow());
+}
+
+#[test]
+fn test_emu_interrupts() {
+    const INTCON: u16 = 0xff2;
+    const RCON: u16 = 0xfd0;
+    let mut cpu = Cpu::new(&image(&[
+        // bcf INTCON, TMR0IF; movlw 0x11; retfie fast
+        (0x08, &[0x94f2, 0x0e11, 0x0011]),
+        // movlw 0x22
+        (0x18, &[0x0e22]),
+        // nop; sleep; nop
+        (0x40, &[0x0000, 0x0003, 0x0000]),
+    ]));
+    // GIE and TMR0IE, and TMR0 flagged
+    cpu.set_file(INTCON, 0xa0);
+    assert!(cpu.raise("TMR0"));
+    cpu.set_pc(0x40);
+    cpu.set_w(5);
+    let step = cpu.step().unwrap();
+    assert_eq!((step.addr, step.cycles, step.interrupt), (0x08, 3, Some(Priority::High)));
+    assert_eq!(cpu.stack().entries(), &[0x40]);
+    assert_eq!(cpu.file(INTCON), 0x20);
+    cpu.step().unwrap();
+    assert_eq!(cpu.w(), 0x11);
+    cpu.step().unwrap();
+    assert_eq!((cpu.pc(), cpu.w(), cpu.file(INTCON)), (0x40, 5, 0xa0));
+
+    // with priorities, a low priority TMR1 is interrupted by INT0, which is always high
+    cpu.set_file(RCON, 0x80);
+    cpu.set_file(INTCON, 0xd0);
+    cpu.set_file(0xf9d, 0x01);
+    cpu.set_file(0xf9f, 0xfe);
+    assert!(cpu.raise("TMR1"));
+    assert_eq!(cpu.step().unwrap().interrupt, Some(Priority::Low));
+    assert_eq!(cpu.file(INTCON), 0x90);
+    assert!(cpu.raise("INT0"));
+    let step = cpu.step().unwrap();
+    assert_eq!((step.addr, step.interrupt), (0x08, Some(Priority::High)));
+    assert_eq!(cpu.stack().entries(), &[0x40, 0x1a]);
+    // INT0IF stays set for the ISR to clear
+    assert_eq!(cpu.file(INTCON), 0x12);
+    // retfie puts back GIEH, then GIEL
+    InterruptController::retfie(cpu.memory_mut());
+    assert_eq!(cpu.file(INTCON), 0x92);
+    InterruptController::retfie(cpu.memory_mut());
+    assert_eq!(cpu.file(INTCON), 0xd2);
+
+    // an enabled interrupt wakes the core with GIE clear, and it carries on after SLEEP
+    cpu.reset();
+    cpu.set_file(RCON, 0);
+    cpu.set_pc(0x42);
+    assert_eq!(cpu.run(100), Stop::Sleep);
+    assert_eq!(cpu.step(), Err(Stop::Sleep));
+    cpu.set_file(INTCON, 0x20);
+    cpu.raise("TMR0");
+    let step = cpu.step().unwrap();
+    assert_eq!((step.addr, step.interrupt), (0x44, None));
+    assert!(!cpu.sleeping());
+}
+
+#[test]
+fn test_interrupt_sfr_names() {
+    // bsf PIE1, 5; bcf RCON, 7
+    assert_eq!(decode(&[0x8a9d]).to_string(), "bsf [PIE1], #0x5");
+    assert_eq!(decode(&[0x9ed0]).to_string(), "bcf [RCON], #0x7");
+    for addr in 0xf9d..=0xfa2 {
+        assert!(device::PIC18F452.sfr_name(addr).is_some());
+    }
+    assert_eq!(device::PIC18F4550.sfr_name(0xfa2), Some("IPR2"));
+}
+
+#[test]
+fn test_emu_timer0() {
+    let mut cpu = Cpu::new(&image(&[(0, &[
+        // 8 bit, 1:1, counting instruction cycles
+        // movlw 0xc8; movwf T0CON; movlw 0xfc; movwf TMR0L
+        0x0ec8, 0x6ed5, 0x0efc, 0x6ed6,
+        // btfss INTCON, TMR0IF; bra $-2
+        0xa4f2, 0xd7fe,
+        // sleep
+        0x0003,
+    ])]));
+    assert_eq!(cpu.run(1000), Stop::Sleep);
+    // writing TMR0L holds the count for two cycles, then it overflows during the second bra
+    assert_eq!(cpu.cycles(), 13);
+    assert_eq!(cpu.file(0xfd6), 0x03);
+    assert_eq!(cpu.file(0xff2) & 0x04, 0x04);
+
+    // in 16 bit mode TMR0H buffers the high byte
+    let mut memory = DataMemory::new();
+    let mut timer = Timer0::new();
+    timer.write(0xfd5, 0x08, &mut memory);
+    timer.write(0xfd7, 0x12, &mut memory);
+    timer.write(0xfd6, 0x34, &mut memory);
+    assert_eq!(timer.count(), 0x1234);
+    timer.write(0xfd7, 0x99, &mut memory);
+    assert_eq!(timer.read(0xfd6, &mut memory), 0x34);
+    assert_eq!(timer.read(0xfd7, &mut memory), 0x12);
+    // on, 1:4
+    timer.write(0xfd5, 0x01 | 0x80, &mut memory);
+    timer.tick(5, &mut memory);
+    assert_eq!(timer.count(), 0x1234);
+    timer.tick(1, &mut memory);
+    assert_eq!(timer.count(), 0x1235);
+}
+
+// a host side of the line tests can look at after the emulator has it.
+#[derive(Clone, Default)]
+struct Sink(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_emu_eusart() {
+    let sink = Sink::default();
+    let mut cpu = Cpu::new(&image(&[(0, &[
+        // TXEN and BRGH, SPEN and CREN, BRG16, SPBRG 0: one cycle a bit
+        // movlw 0x24; movwf TXSTA; movlw 0x90; movwf RCSTA; movlw 0x08; movwf BAUDCON; clrf SPBRG
+        0x0e24, 0x6eac, 0x0e90, 0x6eab, 0x0e08, 0x6eb8, 0x6aaf,
+        // btfss PIR1, RCIF; bra $-2; movf RCREG, W; addlw 1
+        0xaa9e, 0xd7fe, 0x50ae, 0x0f01,
+        // btfss PIR1, TXIF; bra $-2; movwf TXREG; bra 0x0e
+        0xa89e, 0xd7fe, 0x6ead, 0xd7f8,
+    ])]));
+    cpu.attach(Box::new(Eusart::new(&b"abc"[..], sink.clone())));
+    assert_eq!(cpu.run(200), Stop::CycleLimit);
+    assert_eq!(&sink.0.borrow()[..], b"bcd");
+
+    // nobody reading RCREG: two bytes wait there, and the third overruns
+    let mut memory = DataMemory::new();
+    let mut eusart = Eusart::new(&b"xyz"[..], io::sink());
+    eusart.write(0xfab, 0x90, &mut memory);
+    // BRGH clear and SPBRG 25: 9600 baud, near enough, at 16MHz
+    eusart.write(0xfaf, 0x19, &mut memory);
+    assert_eq!(eusart.bit_periods(), 64 * 26);
+    assert_eq!(eusart.baud(16_000_000), 9615);
+    for _ in 0..3 * 10 * 64 * 26 / 4 {
+        eusart.tick(1, &mut memory);
+    }
+    assert_eq!(eusart.peek(0xfab) & 0x02, 0x02);
+    assert_eq!(memory.peek(0xf9e) & 0x20, 0x20);
+    assert_eq!(eusart.read(0xfae, &mut memory), b'x');
+    assert_eq!(eusart.read(0xfae, &mut memory), b'y');
+    assert_eq!(memory.peek(0xf9e) & 0x20, 0);
+    // clearing CREN clears OERR
+    eusart.write(0xfab, 0x80, &mut memory);
+    assert_eq!(eusart.peek(0xfab), 0x80);
+}
+
+#[test]
+fn test_eusart_sfr_names() {
+    let disassembly = Disassembler::new(&image(&[
+        // movlw 0x19; movwf SPBRG; bsf TXSTA, TXEN; bra $
+        (0x00, &[0x0e19, 0x6eaf, 0x8aac, 0xd7ff]),
+        (0x08, &[0x0010]),
+        (0x18, &[0x0010]),
+    ])).disassemble();
+    let constants = Constants::analyze(&Cfg::build(&disassembly), &disassembly);
+    let movwf = disassembly.instruction(0x02).unwrap();
+    assert_eq!(constants.display(0x02, movwf), "movwf [SPBRG]  ; W = 0x19, SPBRG <- 0x19");
+    assert_eq!(disassembly.instruction(0x04).unwrap().to_string(), "bsf [TXSTA], #0x5");
+    assert_eq!(device::PIC18F4550.sfr_name(0xfb8), Some("BAUDCON"));
+    assert_eq!(device::PIC18F45K22.sfr_name(0xfb8), Some("BAUDCON1"));
+}
+
+#[test]
+fn test_eeprom_sfr_names() {
+    // movwf EEADR; clrf EEDATA
+    assert_eq!(decode(&[0x6ea9]).to_string(), "movwf [EEADR]");
+    assert_eq!(decode(&[0x6aa8]).to_string(), "clrf [EEDATA]");
+    assert_eq!(device::PIC18F452.sfr_name(0xfa8), Some("EEDATA"));
+}
+
+#[test]
+fn test_emu_eeprom() {
+    let mut cpu = Cpu::new(&image(&[(0, &[
+        // movlw 0x10; movwf EEADR; movlw 0x42; movwf EEDATA; movlw 0x04 (WREN); movwf EECON1
+        0x0e10, 0x6ea9, 0x0e42, 0x6ea8, 0x0e04, 0x6ea6,
+        // movlw 0x55; movwf EECON2; movlw 0xaa; movwf EECON2; bsf EECON1, WR
+        0x0e55, 0x6ea7, 0x0eaa, 0x6ea7, 0x82a6,
+        // btfsc EECON1, WR; bra $-2
+        0xb2a6, 0xd7fe,
+        // clrf EEDATA; bsf EECON1, RD; movf EEDATA, W; sleep
+        0x6aa8, 0x80a6, 0x50a8, 0x0003,
+    ])]));
+    let path = env::temp_dir().join(format!("yaxpeax-pic18-eeprom-{}", std::process::id()));
+    let _ = fs::remove_file(&path);
+    cpu.nvm_mut().persist_eeprom(&path).unwrap();
+    assert_eq!(cpu.run(10000), Stop::Sleep);
+    // the write takes 4ms at 4MHz, which the core spends polling WR
+    assert!(cpu.cycles() > 4000 && cpu.cycles() < 4100);
+    assert_eq!(cpu.nvm().eeprom()[0x10], 0x42);
+    assert_eq!(cpu.w(), 0x42);
+    assert_eq!(cpu.file(0xfa1) & 0x10, 0x10);
+    assert_eq!(fs::read(&path).unwrap()[0x10], 0x42);
+    fs::remove_file(&path).unwrap();
+
+    // anything between the steps of the unlock sequence and WR is ignored
+    let mut cpu = Cpu::new(&image(&[(0, &[
+        0x0e10, 0x6ea9, 0x0e42, 0x6ea8, 0x0e04, 0x6ea6,
+        // movlw 0x55; movwf EECON2; nop; movlw 0xaa; movwf EECON2; bsf EECON1, WR; sleep
+        0x0e55, 0x6ea7, 0x0000, 0x0eaa, 0x6ea7, 0x82a6, 0x0003,
+    ])]));
+    assert_eq!(cpu.run(10000), Stop::Sleep);
+    assert_eq!(cpu.file(0xfa6), 0x04);
+    assert_eq!(cpu.nvm().eeprom()[0x10], 0xff);
+}
+
+#[test]
+fn test_emu_flash() {
+    let mut cpu = Cpu::new(&image(&[(0, &[
+        // movlw 0x01; movwf TBLPTRH; clrf TBLPTRL
+        0x0e01, 0x6ef7, 0x6af6,
+        // erase: movlw 0x94 (EEPGD, FREE, WREN); movwf EECON1; unlock; bsf EECON1, WR
+        0x0e94, 0x6ea6, 0x0e55, 0x6ea7, 0x0eaa, 0x6ea7, 0x82a6,
+        // movlw 0x12; movwf TABLAT; tblwt*+; movlw 0x34; movwf TABLAT; tblwt*
+        0x0e12, 0x6ef5, 0x000d, 0x0e34, 0x6ef5, 0x000c,
+        // write: movlw 0x84 (EEPGD, WREN); movwf EECON1; unlock; bsf EECON1, WR; sleep
+        0x0e84, 0x6ea6, 0x0e55, 0x6ea7, 0x0eaa, 0x6ea7, 0x82a6, 0x0003,
+    ])]));
+    assert_eq!(cpu.run(10000), Stop::Sleep);
+    // the core stalls 2ms for each of the erase and the write
+    assert!(cpu.cycles() > 4000 && cpu.cycles() < 4100);
+    // the 4550 erases 64 bytes and writes 32
+    assert_eq!(&cpu.program()[0x100..0x102], &[0x12, 0x34]);
+    assert!(cpu.program()[0x102..0x140].iter().all(|&byte| byte == 0xff));
+    assert_eq!(cpu.program()[0x140], 0);
+    assert_eq!(cpu.file(0xfa1) & 0x10, 0x10);
+
+    // without an erase, a write can only clear bits
+    let mut nvm = Nvm::for_device(&[0xf0; 0x100], &device::PIC18F452);
+    let mut memory = DataMemory::new();
+    nvm.table_write(0x08, 0x3c);
+    memory.poke(0xff6, 0x08);
+    nvm.write(0xfa6, 0x84, &mut memory);
+    nvm.write(0xfa7, 0x55, &mut memory);
+    nvm.write(0xfa7, 0xaa, &mut memory);
+    nvm.write(0xfa6, 0x86, &mut memory);
+    assert_eq!(nvm.take_stall(), 2000);
+    assert_eq!(&nvm.program()[0x07..0x11], &[0xf0, 0x30, 0xf0, 0xf0, 0xf0, 0xf0, 0xf0, 0xf0, 0xf0, 0xf0]);
+}